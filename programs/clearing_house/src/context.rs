@@ -4,6 +4,7 @@ use anchor_spl::token::{Mint, Token, TokenAccount};
 use crate::controller::position::PositionDirection;
 use crate::state::bank::Bank;
 use crate::state::market::Market;
+use crate::state::oracle::IndexPrice;
 use crate::state::order_state::OrderState;
 use crate::state::state::State;
 use crate::state::user::{OrderTriggerCondition, OrderType, User};
@@ -530,6 +531,11 @@ pub struct SettleFunding<'info> {
     pub market: AccountLoader<'info, Market>,
 }
 
+#[derive(Accounts)]
+pub struct SettleFundingPayments<'info> {
+    pub state: Box<Account<'info, State>>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateFundingRate<'info> {
     pub state: Box<Account<'info, State>>,
@@ -539,6 +545,16 @@ pub struct UpdateFundingRate<'info> {
     pub oracle: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+pub struct UpdateFundingRates<'info> {
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct View<'info> {
+    pub state: Box<Account<'info, State>>,
+}
+
 #[derive(Accounts)]
 pub struct RepegCurve<'info> {
     #[account(
@@ -601,6 +617,38 @@ pub struct AdminUpdateK<'info> {
     pub oracle: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+pub struct InitializeIndexPrice<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        init,
+        seeds = [b"index_price".as_ref(), authority.key.as_ref()],
+        space = std::mem::size_of::<IndexPrice>() + 8,
+        bump,
+        payer = admin
+    )]
+    pub index_price: AccountLoader<'info, IndexPrice>,
+    /// CHECK: only stored as the index price authority
+    pub authority: AccountInfo<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateIndexPrice<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub index_price: AccountLoader<'info, IndexPrice>,
+}
+
 #[derive(Accounts)]
 pub struct AdminUpdateMarket<'info> {
     pub admin: Signer<'info>,
@@ -611,3 +659,50 @@ pub struct AdminUpdateMarket<'info> {
     #[account(mut)]
     pub market: AccountLoader<'info, Market>,
 }
+
+#[derive(Accounts)]
+pub struct AdminUpdateUser<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    user_id: u8,
+)]
+pub struct MigrateUser<'info> {
+    /// CHECK: checked and resized in `migrate_user`, an unmigrated user can't be loaded as a User
+    #[account(
+        mut,
+        seeds = [b"user", authority.key.as_ref(), user_id.to_le_bytes().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub user: UncheckedAccount<'info>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarket<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub state: Box<Account<'info, State>>,
+    /// CHECK: checked and resized in `migrate_market`, an unmigrated market can't be loaded as a Market
+    #[account(
+        mut,
+        owner = crate::ID
+    )]
+    pub market: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
@@ -30,6 +30,7 @@ pub fn swap_quote_asset(
     use_spread: bool,
 ) -> ClearingHouseResult<(i128, u128)> {
     amm::update_mark_twap(amm, now, precomputed_mark_price)?;
+    amm::update_open_interest_twap(amm, now)?;
 
     let (
         new_base_asset_reserve,
@@ -197,6 +198,7 @@ pub fn swap_base_asset(
     use_spread: bool,
 ) -> ClearingHouseResult<(u128, u128)> {
     amm::update_mark_twap(amm, now, precomputed_mark_price)?;
+    amm::update_open_interest_twap(amm, now)?;
 
     let (
         new_base_asset_reserve,
@@ -1,24 +1,57 @@
-use std::cmp::{max, min};
-
 use anchor_lang::prelude::*;
 use solana_program::clock::UnixTimestamp;
 use solana_program::msg;
 
-use crate::error::ClearingHouseResult;
+use crate::error::{ClearingHouseResult, ErrorCode};
 use crate::get_then_update_id;
 use crate::math::amm;
-use crate::math::casting::{cast, cast_to_i128};
-use crate::math::constants::{
-    AMM_TO_QUOTE_PRECISION_RATIO_I128, FUNDING_PAYMENT_PRECISION, ONE_HOUR,
+use crate::math::casting::cast;
+use crate::math::constants::MAX_FUNDING_RATE_BATCH_SIZE;
+use crate::math::funding::{
+    calculate_funding_price_spread, calculate_funding_rate, calculate_funding_rate_checksum,
+    calculate_funding_rate_ema, calculate_funding_rate_from_price_spread,
+    calculate_funding_rate_long_short, calculate_next_funding_time,
+    calculate_position_funding_payment, PositionFundingPayment,
 };
-use crate::math::funding::{calculate_funding_payment, calculate_funding_rate_long_short};
 use crate::math::oracle;
 use crate::math_error;
-use crate::state::events::{FundingPaymentRecord, FundingRateRecord};
+use crate::state::events::{
+    FundingBatchRecord, FundingPaymentRecord, FundingRateRecord, FundingRateSummary,
+    FundingUpdateSkipReason, FundingUpdateSkippedRecord,
+};
 use crate::state::market::{Market, AMM};
 use crate::state::market_map::MarketMap;
-use crate::state::state::OracleGuardRails;
-use crate::state::user::User;
+use crate::state::oracle_map::OracleMap;
+use crate::state::state::{FundingBlockGuardRails, OracleGuardRails};
+use crate::state::user::{FundingPaymentHistoryEntry, User};
+use crate::validate;
+
+/// What a funding settlement did, for keepers tuning batch sizes
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SettlementStats {
+    pub iterated: u64,       // open positions looked at
+    pub settled: u64,        // positions that had funding to settle
+    pub total_funding: i128, // QUOTE_PRECISION
+}
+
+impl SettlementStats {
+    pub fn checked_add(&self, other: &SettlementStats) -> ClearingHouseResult<SettlementStats> {
+        Ok(SettlementStats {
+            iterated: self
+                .iterated
+                .checked_add(other.iterated)
+                .ok_or_else(math_error!())?,
+            settled: self
+                .settled
+                .checked_add(other.settled)
+                .ok_or_else(math_error!())?,
+            total_funding: self
+                .total_funding
+                .checked_add(other.total_funding)
+                .ok_or_else(math_error!())?,
+        })
+    }
+}
 
 pub fn settle_funding_payment(
     user: &mut User,
@@ -26,49 +59,149 @@ pub fn settle_funding_payment(
     market_map: &MarketMap,
     now: UnixTimestamp,
 ) -> ClearingHouseResult {
+    _settle_funding_payment(user, user_key, market_map, now)?;
+    Ok(())
+}
+
+/// settle_funding_payment, returning how many positions were iterated and settled
+pub fn settle_funding_payment_with_stats(
+    user: &mut User,
+    user_key: &Pubkey,
+    market_map: &MarketMap,
+    now: UnixTimestamp,
+) -> ClearingHouseResult<SettlementStats> {
+    _settle_funding_payment(user, user_key, market_map, now)
+}
+
+/// A user that failed to settle in settle_funding_payment_batch, so the account can be isolated
+#[derive(Clone, Copy, Debug)]
+pub struct UserSettlementError {
+    pub user_key: Pubkey,
+    pub error_code: ErrorCode,
+}
+
+impl From<UserSettlementError> for ErrorCode {
+    fn from(user_settlement_error: UserSettlementError) -> Self {
+        user_settlement_error.error_code
+    }
+}
+
+/// Settles funding for each user, emitting their FundingPaymentRecords, and returns the settlement stats
+/// summed across all of them
+/// Stops at the first user that fails to settle and returns its key with the error
+pub fn settle_funding_payment_batch(
+    users: &mut [(&Pubkey, &mut User)],
+    market_map: &MarketMap,
+    now: UnixTimestamp,
+) -> std::result::Result<SettlementStats, UserSettlementError> {
+    let mut batch_settlement_stats = SettlementStats::default();
+    for (user_key, user) in users.iter_mut() {
+        let user_settlement_error = |error_code| UserSettlementError {
+            user_key: **user_key,
+            error_code,
+        };
+
+        let settlement_stats = settle_funding_payment_with_stats(user, user_key, market_map, now)
+            .map_err(user_settlement_error)?;
+        batch_settlement_stats = batch_settlement_stats
+            .checked_add(&settlement_stats)
+            .map_err(user_settlement_error)?;
+    }
+
+    Ok(batch_settlement_stats)
+}
+
+/// Settles funding for all the users and returns the settlement stats summed across them
+/// Across the whole population of a balanced book the total funding nets toward zero
+pub fn settle_and_total(
+    users: &mut [(&Pubkey, &mut User)],
+    market_map: &MarketMap,
+    now: UnixTimestamp,
+) -> ClearingHouseResult<SettlementStats> {
+    settle_funding_payment_batch(users, market_map, now).map_err(|user_settlement_error| {
+        msg!(
+            "Failed to settle funding for user {}",
+            user_settlement_error.user_key
+        );
+        user_settlement_error.into()
+    })
+}
+
+fn _settle_funding_payment(
+    user: &mut User,
+    user_key: &Pubkey,
+    market_map: &MarketMap,
+    now: UnixTimestamp,
+) -> ClearingHouseResult<SettlementStats> {
+    let mut settlement_stats = SettlementStats::default();
     for market_position in user.positions.iter_mut() {
         if market_position.base_asset_amount == 0 {
             continue;
         }
+        settlement_stats.iterated = settlement_stats
+            .iterated
+            .checked_add(1)
+            .ok_or_else(math_error!())?;
 
-        let market = &market_map.get_ref(&market_position.market_index)?;
+        let market = &mut market_map.get_ref_mut(&market_position.market_index)?;
         let amm: &AMM = &market.amm;
 
-        let amm_cumulative_funding_rate = if market_position.base_asset_amount > 0 {
-            amm.cumulative_funding_rate_long
-        } else {
-            amm.cumulative_funding_rate_short
-        };
-
-        if amm_cumulative_funding_rate != market_position.last_cumulative_funding_rate {
-            let market_funding_payment =
-                calculate_funding_payment(amm_cumulative_funding_rate, market_position)?
-                    .checked_div(AMM_TO_QUOTE_PRECISION_RATIO_I128)
-                    .ok_or_else(math_error!())?;
-
+        if let Some(PositionFundingPayment {
+            amm_cumulative_funding_rate,
+            funding_payment: market_funding_payment,
+            uncollected_funding,
+            funding_payment_dust,
+        }) = calculate_position_funding_payment(market_position, amm)?
+        {
             emit!(FundingPaymentRecord {
                 ts: now,
                 user_authority: user.authority,
                 user: *user_key,
                 market_index: market_position.market_index,
-                funding_payment: market_funding_payment, //10e13
+                funding_payment: market_funding_payment, //10e6
                 user_last_cumulative_funding: market_position.last_cumulative_funding_rate, //10e14
                 user_last_funding_rate_ts: market_position.last_funding_rate_ts,
                 amm_cumulative_funding_long: amm.cumulative_funding_rate_long, //10e14
                 amm_cumulative_funding_short: amm.cumulative_funding_rate_short, //10e14
                 base_asset_amount: market_position.base_asset_amount,          //10e13
+                funding_payment_dust,                                          //10e13
             });
 
             market_position.last_cumulative_funding_rate = amm_cumulative_funding_rate;
             market_position.last_funding_rate_ts = amm.last_funding_rate_ts;
+            market_position.uncollected_funding = market_position
+                .uncollected_funding
+                .checked_add(uncollected_funding)
+                .ok_or_else(math_error!())?;
             market_position.unsettled_pnl = market_position
                 .unsettled_pnl
                 .checked_add(market_funding_payment)
                 .ok_or_else(math_error!())?;
+            user.funding_payment_history
+                .push(FundingPaymentHistoryEntry {
+                    ts: now,
+                    market_index: market_position.market_index,
+                    funding_payment: market_funding_payment,
+                });
+            // keep what was truncated at the market level so settled payments reconcile with the amm to the unit
+            market.amm.funding_payment_dust = market
+                .amm
+                .funding_payment_dust
+                .checked_add(funding_payment_dust)
+                .ok_or_else(math_error!())?;
+
+            settlement_stats.settled = settlement_stats
+                .settled
+                .checked_add(1)
+                .ok_or_else(math_error!())?;
+            settlement_stats.total_funding = settlement_stats
+                .total_funding
+                .checked_add(market_funding_payment)
+                .ok_or_else(math_error!())?;
         }
     }
 
-    Ok(())
+    Ok(settlement_stats)
 }
 
 pub fn update_funding_rate(
@@ -78,124 +211,276 @@ pub fn update_funding_rate(
     now: UnixTimestamp,
     clock_slot: u64,
     guard_rails: &OracleGuardRails,
+    funding_block_guard_rails: &FundingBlockGuardRails,
     funding_paused: bool,
     precomputed_mark_price: Option<u128>,
 ) -> ClearingHouseResult {
-    let time_since_last_update = now
-        .checked_sub(market.amm.last_funding_rate_ts)
-        .ok_or_else(math_error!())?;
-
-    // Pause funding if oracle is invalid or if mark/oracle spread is too divergent
-    let (block_funding_rate_update, oracle_price_data) = oracle::block_operation(
-        &market.amm,
+    let funding_rate_record = _update_funding_rate(
+        market_index,
+        market,
         price_oracle,
+        now,
         clock_slot,
         guard_rails,
+        funding_block_guard_rails,
+        funding_paused,
         precomputed_mark_price,
     )?;
-    // round next update time to be available on the hour
-    let mut next_update_wait = market.amm.funding_period;
-    if market.amm.funding_period > 1 {
-        let last_update_delay = market
-            .amm
-            .last_funding_rate_ts
-            .rem_euclid(market.amm.funding_period);
-        if last_update_delay != 0 {
-            let max_delay_for_next_period = market
-                .amm
-                .funding_period
-                .checked_div(3)
-                .ok_or_else(math_error!())?;
-
-            let two_funding_periods = market
-                .amm
-                .funding_period
-                .checked_mul(2)
-                .ok_or_else(math_error!())?;
 
-            if last_update_delay > max_delay_for_next_period {
-                // too late for on the hour next period, delay to following period
-                next_update_wait = two_funding_periods
-                    .checked_sub(last_update_delay)
-                    .ok_or_else(math_error!())?;
-            } else {
-                // allow update on the hour
-                next_update_wait = market
-                    .amm
-                    .funding_period
-                    .checked_sub(last_update_delay)
-                    .ok_or_else(math_error!())?;
-            }
-
-            if next_update_wait > two_funding_periods {
-                next_update_wait = next_update_wait
-                    .checked_sub(market.amm.funding_period)
-                    .ok_or_else(math_error!())?;
-            }
-        }
+    if let Some(funding_rate_record) = funding_rate_record {
+        emit!(funding_rate_record);
     }
 
-    if !funding_paused && !block_funding_rate_update && time_since_last_update >= next_update_wait {
-        let oracle_price_twap = amm::update_oracle_price_twap(
-            &mut market.amm,
+    Ok(())
+}
+
+/// Updates the funding rate for each market and emits a single FundingBatchRecord summarizing
+/// the markets that were updated, rather than one FundingRateRecord per market
+pub fn update_funding_rates(
+    market_indexes: &[u64],
+    market_map: &MarketMap,
+    oracle_map: &OracleMap,
+    now: UnixTimestamp,
+    clock_slot: u64,
+    guard_rails: &OracleGuardRails,
+    funding_block_guard_rails: &FundingBlockGuardRails,
+    funding_paused: bool,
+) -> ClearingHouseResult {
+    validate!(
+        market_indexes.len() <= MAX_FUNDING_RATE_BATCH_SIZE,
+        ErrorCode::FundingRateBatchTooLarge,
+        "Can only update {} markets per batch",
+        MAX_FUNDING_RATE_BATCH_SIZE
+    )?;
+
+    let mut funding_rates = Vec::with_capacity(market_indexes.len());
+    for market_index in market_indexes.iter() {
+        let market = &mut market_map.get_ref_mut(market_index)?;
+        let price_oracle = oracle_map.get_account_info(&market.amm.oracle)?;
+
+        let funding_rate_record = _update_funding_rate(
+            *market_index,
+            market,
+            &price_oracle,
             now,
-            &oracle_price_data,
-            precomputed_mark_price,
+            clock_slot,
+            guard_rails,
+            funding_block_guard_rails,
+            funding_paused,
+            None,
         )?;
-        let mid_price_twap = amm::update_mark_twap(&mut market.amm, now, None)?;
 
-        let period_adjustment = (24_i128)
-            .checked_mul(ONE_HOUR)
-            .ok_or_else(math_error!())?
-            .checked_div(max(ONE_HOUR, market.amm.funding_period as i128))
-            .ok_or_else(math_error!())?;
-        // funding period = 1 hour, window = 1 day
-        // low periodicity => quickly updating/settled funding rates => lower funding rate payment per interval
-        let price_spread = cast_to_i128(mid_price_twap)?
-            .checked_sub(oracle_price_twap)
-            .ok_or_else(math_error!())?;
+        if let Some(funding_rate_record) = funding_rate_record {
+            funding_rates.push(FundingRateSummary {
+                record_id: funding_rate_record.record_id,
+                market_index: funding_rate_record.market_index,
+                funding_rate: funding_rate_record.funding_rate,
+                cumulative_funding_rate_long: funding_rate_record.cumulative_funding_rate_long,
+                cumulative_funding_rate_short: funding_rate_record.cumulative_funding_rate_short,
+                oracle_price_twap: funding_rate_record.oracle_price_twap,
+                mark_price_twap: funding_rate_record.mark_price_twap,
+            });
+        }
+    }
 
-        // clamp price divergence to 3% for funding rate calculation
-        let max_price_spread = oracle_price_twap
-            .checked_div(33)
-            .ok_or_else(math_error!())?; // 3%
-        let clamped_price_spread = max(-max_price_spread, min(price_spread, max_price_spread));
+    if !funding_rates.is_empty() {
+        emit!(FundingBatchRecord {
+            ts: now,
+            funding_rates,
+        });
+    }
 
-        let funding_rate = clamped_price_spread
-            .checked_mul(cast(FUNDING_PAYMENT_PRECISION)?)
-            .ok_or_else(math_error!())?
-            .checked_div(cast(period_adjustment)?)
-            .ok_or_else(math_error!())?;
+    Ok(())
+}
 
-        let (funding_rate_long, funding_rate_short) =
-            calculate_funding_rate_long_short(market, funding_rate)?;
+fn _update_funding_rate(
+    market_index: u64,
+    market: &mut Market,
+    price_oracle: &AccountInfo,
+    now: UnixTimestamp,
+    clock_slot: u64,
+    guard_rails: &OracleGuardRails,
+    funding_block_guard_rails: &FundingBlockGuardRails,
+    funding_paused: bool,
+    precomputed_mark_price: Option<u128>,
+) -> ClearingHouseResult<Option<FundingRateRecord>> {
+    if now < calculate_next_funding_time(&market.amm)? {
+        // every fill tries to update funding, so only keeper updates (no precomputed mark price) record it
+        if precomputed_mark_price.is_none() {
+            emit!(FundingUpdateSkippedRecord {
+                ts: now,
+                market_index,
+                reason: FundingUpdateSkipReason::TooSoon,
+            });
+        }
+        return Ok(None);
+    }
 
-        market.amm.cumulative_funding_rate_long = market
-            .amm
-            .cumulative_funding_rate_long
-            .checked_add(funding_rate_long)
-            .ok_or_else(math_error!())?;
+    // Pause funding if oracle is invalid or if mark/oracle spread is too divergent
+    // After the time gate so the hysteresis and outage recovery advance once per funding update, not per fill
+    let (block_funding_rate_update, oracle_price_data) = match oracle::block_funding_rate_update(
+        &mut market.amm,
+        price_oracle,
+        clock_slot,
+        guard_rails,
+        funding_block_guard_rails,
+        precomputed_mark_price,
+    ) {
+        Ok(block_funding_rate_update) => block_funding_rate_update,
+        // a zero oracle price would zero the spread clamp and push funding to the mark price
+        Err(ErrorCode::InvalidOraclePrice) => {
+            emit!(FundingUpdateSkippedRecord {
+                ts: now,
+                market_index,
+                reason: if funding_paused || market.amm.funding_frozen {
+                    FundingUpdateSkipReason::FundingPaused
+                } else {
+                    FundingUpdateSkipReason::OraclePriceZero
+                },
+            });
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
 
-        market.amm.cumulative_funding_rate_short = market
-            .amm
-            .cumulative_funding_rate_short
-            .checked_add(funding_rate_short)
-            .ok_or_else(math_error!())?;
+    // a frozen market stops accruing funding, but positions can still settle what accrued before the freeze
+    let skip_reason = if funding_paused || market.amm.funding_frozen {
+        Some(FundingUpdateSkipReason::FundingPaused)
+    } else if block_funding_rate_update {
+        Some(FundingUpdateSkipReason::OracleInvalid)
+    } else {
+        None
+    };
 
-        market.amm.last_funding_rate = funding_rate;
-        market.amm.last_funding_rate_ts = now;
+    if let Some(reason) = skip_reason {
+        emit!(FundingUpdateSkippedRecord {
+            ts: now,
+            market_index,
+            reason,
+        });
+        return Ok(None);
+    }
 
-        emit!(FundingRateRecord {
+    let oracle_price_twap = amm::update_oracle_price_twap(
+        &mut market.amm,
+        now,
+        &oracle_price_data,
+        precomputed_mark_price,
+    )?;
+    // optionally price funding off the oracle ema, which lags less than the twap in fast moves
+    let oracle_price_twap = if market.amm.funding_use_oracle_ema && market.amm.oracle_price_ema != 0
+    {
+        market.amm.oracle_price_ema
+    } else {
+        oracle_price_twap
+    };
+    // a degenerate twap (e.g. right after market init) would clamp every funding rate to zero
+    if !market.amm.is_valid_oracle_price(oracle_price_twap) {
+        emit!(FundingUpdateSkippedRecord {
             ts: now,
-            record_id: get_then_update_id!(market, next_funding_rate_record_id),
             market_index,
-            funding_rate,
-            cumulative_funding_rate_long: market.amm.cumulative_funding_rate_long,
-            cumulative_funding_rate_short: market.amm.cumulative_funding_rate_short,
-            mark_price_twap: mid_price_twap,
-            oracle_price_twap,
+            reason: FundingUpdateSkipReason::OracleInvalid,
         });
+        return Ok(None);
     }
+    // optionally lean the mark toward recent order flow, buy volume weighs like bid size
+    let funding_mark_price = if market.amm.funding_use_microprice {
+        Some(market.amm.microprice(
+            cast(market.amm.long_intensity_volume)?,
+            cast(market.amm.short_intensity_volume)?,
+        )?)
+    } else {
+        None
+    };
+    let mid_price_twap = amm::update_mark_twap(&mut market.amm, now, funding_mark_price)?;
+    amm::update_open_interest_twap(&mut market.amm, now)?;
 
-    Ok(())
+    let funding_rate = calculate_funding_rate(
+        mid_price_twap,
+        oracle_price_twap,
+        market.amm.funding_period,
+        market.amm.funding_clamp_divisor,
+    )?;
+    // the spreads are recorded so indexers can see how much the clamp cut
+    let (raw_price_spread, clamped_price_spread) = calculate_funding_price_spread(
+        mid_price_twap,
+        oracle_price_twap,
+        market.amm.funding_clamp_divisor,
+    )?;
+
+    // bound how fast cumulative funding can drift while open interest is one-sided
+    let funding_rate = market.amm.cap_funding_rate(funding_rate)?;
+
+    let unclamped_funding_rate =
+        calculate_funding_rate_from_price_spread(raw_price_spread, market.amm.funding_period)?;
+    market.amm.cumulative_funding_clamp_suppression = market
+        .amm
+        .cumulative_funding_clamp_suppression
+        .checked_add(
+            unclamped_funding_rate
+                .checked_sub(funding_rate)
+                .ok_or_else(math_error!())?,
+        )
+        .ok_or_else(math_error!())?;
+
+    let (funding_rate_long, funding_rate_short) =
+        calculate_funding_rate_long_short(market, funding_rate)?;
+
+    market.amm.cumulative_funding_rate_long = market
+        .amm
+        .cumulative_funding_rate_long
+        .checked_add(funding_rate_long)
+        .ok_or_else(math_error!())?;
+
+    market.amm.cumulative_funding_rate_short = market
+        .amm
+        .cumulative_funding_rate_short
+        .checked_add(funding_rate_short)
+        .ok_or_else(math_error!())?;
+
+    let oracle_volatility_bps = amm::calculate_oracle_volatility_bps(&market.amm)?;
+    market.amm.oracle_squared_return_sum = 0;
+
+    market.amm.funding_rate_ema = calculate_funding_rate_ema(
+        market.amm.funding_rate_ema,
+        funding_rate,
+        market.amm.funding_rate_ema_alpha,
+    )?;
+    market.amm.previous_funding_rate = market.amm.last_funding_rate;
+    market.amm.last_funding_rate = funding_rate;
+    market.amm.last_funding_rate_ts = now;
+    market.amm.last_funding_oracle_price_twap = oracle_price_twap;
+    market.amm.funding_update_count = market
+        .amm
+        .funding_update_count
+        .checked_add(1)
+        .ok_or_else(math_error!())?;
+    match funding_rate.signum() {
+        1 => {
+            market.amm.funding_positive_count = market.amm.funding_positive_count.saturating_add(1)
+        }
+        -1 => {
+            market.amm.funding_negative_count = market.amm.funding_negative_count.saturating_add(1)
+        }
+        _ => market.amm.funding_zero_count = market.amm.funding_zero_count.saturating_add(1),
+    }
+
+    let record_id = get_then_update_id!(market, next_funding_rate_record_id);
+    market.amm.funding_rate_checksum =
+        calculate_funding_rate_checksum(market.amm.funding_rate_checksum, funding_rate, record_id);
+
+    Ok(Some(FundingRateRecord {
+        ts: now,
+        record_id,
+        market_index,
+        funding_rate,
+        cumulative_funding_rate_long: market.amm.cumulative_funding_rate_long,
+        cumulative_funding_rate_short: market.amm.cumulative_funding_rate_short,
+        mark_price_twap: mid_price_twap,
+        oracle_price_twap,
+        oracle_volatility_bps,
+        checksum: market.amm.funding_rate_checksum,
+        raw_price_spread,
+        clamped_price_spread,
+    }))
 }
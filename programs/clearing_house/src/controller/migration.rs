@@ -0,0 +1,141 @@
+use std::mem::{size_of, MaybeUninit};
+use std::ptr::addr_of;
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_lang::Discriminator;
+use solana_program::msg;
+
+use crate::error::ErrorCode;
+use crate::state::market::Market;
+use crate::state::user::{FundingPaymentHistory, User};
+
+/// Grows a user account created before funding_payment_history was appended to User
+/// Does nothing if the account is already the current size
+pub fn migrate_user<'info>(
+    user: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let new_len = size_of::<User>() + 8;
+    let old_len = new_len - size_of::<FundingPaymentHistory>();
+
+    if !needs_migration::<User>(user, old_len, new_len)? {
+        return Ok(());
+    }
+
+    // the history is appended, so the zeroed tail is an empty history
+    realloc_account(user, payer, system_program, new_len)
+}
+
+/// Grows a market account created before the funding fields were added to its AMM, shifting the fields after
+/// them (curve_update_intensity onward) to their new offsets and zeroing the new fields
+/// Does nothing if the account is already the current size
+pub fn migrate_market<'info>(
+    market: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    now: i64,
+) -> Result<()> {
+    let (new_fields_start, new_fields_end) = amm_new_fields_range();
+    let new_len = size_of::<Market>() + 8;
+    let old_len = new_len - (new_fields_end - new_fields_start);
+
+    if !needs_migration::<Market>(market, old_len, new_len)? {
+        return Ok(());
+    }
+
+    realloc_account(market, payer, system_program, new_len)?;
+
+    {
+        let mut data = market.try_borrow_mut_data()?;
+        data.copy_within(new_fields_start..old_len, new_fields_end);
+        data[new_fields_start..new_fields_end].fill(0);
+    }
+
+    // the new fields that initialize_market doesn't set to zero
+    let market_loader: AccountLoader<Market> = AccountLoader::try_from(market)?;
+    let market = &mut market_loader.load_mut()?;
+    market.amm.oracle_price_multiplier = 1;
+    market.amm.oracle_price_divisor = 1;
+    market.amm.last_open_interest_twap_ts = now;
+
+    Ok(())
+}
+
+/// Byte range (including the discriminator) of the AMM fields added after markets were first created,
+/// funding_frozen up to curve_update_intensity
+fn amm_new_fields_range() -> (usize, usize) {
+    let market = MaybeUninit::<Market>::uninit();
+    let market_ptr = market.as_ptr();
+
+    // only field addresses are taken, the uninitialized market is never read
+    let (start, end) = unsafe {
+        (
+            addr_of!((*market_ptr).amm.funding_frozen) as usize,
+            addr_of!((*market_ptr).amm.curve_update_intensity) as usize,
+        )
+    };
+    let base = market_ptr as usize;
+
+    (start - base + 8, end - base + 8)
+}
+
+/// Whether the account still has the old_len layout. Errors if it isn't a T in either layout
+fn needs_migration<T: Discriminator>(
+    account: &AccountInfo,
+    old_len: usize,
+    new_len: usize,
+) -> Result<bool> {
+    let data = account.try_borrow_data()?;
+
+    if data.len() < 8 || data[..8] != T::discriminator() {
+        return Err(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+
+    if data.len() == new_len {
+        msg!("Account {} is already migrated", account.key);
+        return Ok(false);
+    }
+
+    if data.len() != old_len {
+        msg!(
+            "Account {} has length {}, expected {} or {}",
+            account.key,
+            data.len(),
+            old_len,
+            new_len
+        );
+        return Err(ErrorCode::UnexpectedAccountLength.into());
+    }
+
+    Ok(true)
+}
+
+/// Resizes the account to new_len, with payer topping its lamports up to the new rent exempt minimum
+fn realloc_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    new_len: usize,
+) -> Result<()> {
+    let rent_exempt_minimum = Rent::get()?.minimum_balance(new_len);
+    let lamports_needed = rent_exempt_minimum.saturating_sub(account.lamports());
+
+    if lamports_needed > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                Transfer {
+                    from: payer.to_account_info(),
+                    to: account.clone(),
+                },
+            ),
+            lamports_needed,
+        )?;
+    }
+
+    account.realloc(new_len, true)?;
+
+    Ok(())
+}
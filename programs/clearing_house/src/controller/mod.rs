@@ -1,7 +1,9 @@
 pub mod amm;
 pub mod bank_balance;
 pub mod funding;
+pub mod migration;
 pub mod orders;
 pub mod position;
 pub mod repeg;
 pub mod token;
+pub mod view;
@@ -612,6 +612,7 @@ pub fn fill_order(
             now,
             clock_slot,
             &state.oracle_guard_rails,
+            &state.funding_block_guard_rails,
             state.funding_paused,
             Some(mark_price_before),
         )?;
@@ -15,7 +15,7 @@ use crate::state::user::{User, UserPositions};
 use crate::MarketPosition;
 use solana_program::msg;
 
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq)]
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
 pub enum PositionDirection {
     Long,
     Short,
@@ -46,13 +46,13 @@ pub fn add_new_position(
         last_funding_rate_ts: 0,
         open_orders: 0,
         unsettled_pnl: 0,
+        max_funding_per_period: 0,
+        uncollected_funding: 0,
         padding0: 0,
         padding1: 0,
         padding2: 0,
         padding3: 0,
         padding4: 0,
-        padding5: 0,
-        padding6: 0,
     };
 
     user_positions[new_position_index] = new_market_position;
@@ -92,6 +92,7 @@ pub fn increase(
             PositionDirection::Long => market.amm.cumulative_funding_rate_long,
             PositionDirection::Short => market.amm.cumulative_funding_rate_short,
         };
+        market_position.last_funding_rate_ts = market.amm.last_funding_rate_ts;
 
         market.open_interest = market
             .open_interest
@@ -472,6 +473,8 @@ pub fn close(
 
     market_position.last_cumulative_funding_rate = 0;
     market_position.last_funding_rate_ts = 0;
+    market_position.max_funding_per_period = 0;
+    market_position.uncollected_funding = 0;
 
     market.open_interest = market
         .open_interest
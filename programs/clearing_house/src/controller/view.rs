@@ -0,0 +1,814 @@
+use std::cell::Ref;
+
+use anchor_lang::prelude::{AccountLoader, Pubkey};
+use anchor_lang::Key;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::msg;
+
+use crate::account_loader::load;
+use crate::controller::position::PositionDirection;
+use crate::error::{ClearingHouseResult, ErrorCode};
+use crate::math::amm::{
+    calculate_price, calculate_price_after_trade, calculate_price_with_rounding,
+    get_oracle_price_twap, haircut_collateral_value, hedge_ratio, PriceRounding,
+};
+use crate::math::casting::cast_to_i128;
+use crate::math::funding::{
+    calculate_funding_rate, calculate_funding_rate_ema, calculate_next_funding_time,
+    calculate_period_adjustment, calculate_position_funding_payment, funding_since,
+};
+use crate::math::repeg::calculate_peg_from_target_price;
+use crate::math::slippage::calculate_slippage_bps;
+use crate::state::market_map::MarketMap;
+use crate::state::oracle::OraclePriceData;
+use crate::state::oracle_map::OracleMap;
+use crate::state::user::{MarketPosition, User};
+
+/// Read-only queries run by the view instruction, each names the accounts it reads
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub enum ViewParams {
+    PeriodFundingFlow {
+        market_index: u64,
+    },
+    OracleConfidenceBps {
+        oracle: Pubkey,
+    },
+    MarkTwapDeviationBps {
+        market_index: u64,
+        spot_mark: u128,
+    },
+    HaircutCollateralValue {
+        oracle: Pubkey,
+        amount: u128,
+    },
+    ClampDivisorForMaxApr {
+        market_index: u64,
+        target_apr_bps: i128,
+    },
+    ReservesAfterTrade {
+        market_index: u64,
+        base_amount: i128,
+    },
+    OraclePrice {
+        oracle: Pubkey,
+    },
+    FundingSince {
+        baseline_cumulative_funding_rate: i128,
+        current_cumulative_funding_rate: i128,
+        base_asset_amount: i128,
+    },
+    UtilizationBps {
+        market_index: u64,
+    },
+    FundingOracleSensitivity {
+        market_index: u64,
+    },
+    FundingPerBaseUnit {
+        market_index: u64,
+        long: bool,
+    },
+    ImpliedMarkTwap {
+        market_index: u64,
+    },
+    FundingCostForPosition {
+        market_index: u64,
+        collateral: u128,
+        leverage: u128,
+    },
+    SpreadBps {
+        market_index: u64,
+    },
+    SlotsUntilStale {
+        oracle: Pubkey,
+        max_delay: i64,
+    },
+    IsNearReserveFloor {
+        market_index: u64,
+        threshold_bps: u128,
+    },
+    PriceInDecimals {
+        oracle: Pubkey,
+        target_decimals: u32,
+    },
+    BasisBreakevenRate {
+        market_index: u64,
+        carry_cost_bps: i128,
+    },
+    PeriodAdjustment {
+        funding_period: i64,
+    },
+    FundingAcceleration {
+        market_index: u64,
+    },
+    FundingAsMarginReturnBps {
+        market_index: u64,
+        base_asset_amount: i128,
+        margin: u128,
+    },
+    OraclePriceAtSlot {
+        oracle: Pubkey,
+        slot: u64,
+    },
+    MarkPrice {
+        market_index: u64,
+    },
+    OraclePriceWithConversion {
+        market_index: u64,
+        oracle_price_multiplier: u128,
+        oracle_price_divisor: u128,
+    },
+    TimeWeightedOi {
+        market_index: u64,
+        window_seconds: i64,
+    },
+    PositionFundingPayment {
+        market_index: u64,
+        base_asset_amount: i128,
+        last_cumulative_funding_rate: i128,
+    },
+    FundingRateForTargetPremiumBps {
+        market_index: u64,
+        target_premium_bps: i128,
+    },
+    PeriodsToFundingLiquidation {
+        market_index: u64,
+        base_asset_amount: i128,
+        maintenance_margin: u128,
+    },
+    HedgeRatio {
+        oracle_a: Pubkey,
+        oracle_b: Pubkey,
+    },
+    FundingTimingErrorBps {
+        market_index: u64,
+    },
+    SkewedMarkPrice {
+        market_index: u64,
+        skew_factor: u128,
+    },
+    AbsFundingRateAbove {
+        market_index: u64,
+        threshold: u128,
+    },
+    Microprice {
+        market_index: u64,
+        bid_size: u128,
+        ask_size: u128,
+    },
+    OraclePriceMedian,
+    OraclePriceContributors,
+    ClampedFundingRateForTargetPremiumBps {
+        market_index: u64,
+        target_premium_bps: i128,
+        funding_clamp_divisor: u64,
+    },
+    MinCollateralForFunding {
+        market_index: u64,
+        base_asset_amount: i128,
+        funding_rate: i128,
+    },
+    FundingRate {
+        mark_twap: u128,
+        oracle_twap: i128,
+        funding_period: i64,
+        clamp_divisor: u64,
+    },
+    CappedFundingRate {
+        market_index: u64,
+        funding_rate: i128,
+        max_funding_rate: u128,
+    },
+    CumulativeClampSuppression {
+        market_index: u64,
+    },
+    UnsettledFunding {
+        user: Pubkey,
+    },
+    SuggestedRepeg {
+        market_index: u64,
+    },
+    PositionCarryAprBps {
+        market_index: u64,
+        base_asset_amount: i128,
+    },
+    PriceForReserves {
+        quote_asset_reserve: u128,
+        base_asset_reserve: u128,
+        peg_multiplier: u128,
+    },
+    RealizedPnlWithFunding {
+        market_index: u64,
+        base_asset_amount: i128,
+        quote_asset_amount: u128,
+        last_cumulative_funding_rate: i128,
+        exit_price: i128,
+    },
+    PriceAfterTrade {
+        market_index: u64,
+        base_asset_amount: i128,
+        direction: PositionDirection,
+    },
+    ExpectedFundingCallsPerDay {
+        market_index: u64,
+        funding_period: i64,
+    },
+    FundingRateEma {
+        funding_rate_ema: i128,
+        funding_rate: i128,
+        alpha: u128,
+    },
+    OraclePriceChecked {
+        oracle: Pubkey,
+        max_delay: i64,
+    },
+    ImpliedBorrowLendRates {
+        market_index: u64,
+    },
+    OraclePriceWithBounds {
+        oracle: Pubkey,
+    },
+    PegForTargetPrice {
+        quote_asset_reserve: u128,
+        base_asset_reserve: u128,
+        target_price: u128,
+    },
+    OracleMoveSinceLastFunding {
+        market_index: u64,
+        current_oracle: i128,
+    },
+    OraclePriceTwap {
+        market_index: u64,
+    },
+    FundingClampPriceBounds {
+        market_index: u64,
+        oracle_twap: i128,
+        funding_clamp_divisor: u64,
+    },
+    MarkTwapAge {
+        market_index: u64,
+    },
+    QuoteAssetPrice {
+        quote_oracle: Pubkey,
+    },
+    FundingSignCounts {
+        market_index: u64,
+    },
+    PriceForReservesWithRounding {
+        quote_asset_reserve: u128,
+        base_asset_reserve: u128,
+        peg_multiplier: u128,
+        rounding: PriceRounding,
+    },
+    NextFundingTime {
+        market_index: u64,
+    },
+    ProjectedFundingOverWindow {
+        market_index: u64,
+        base_asset_amount: i128,
+        mark_price: u128,
+        window_seconds: i64,
+    },
+    OraclePriceWithPythEma {
+        market_index: u64,
+        use_pyth_ema_price: bool,
+    },
+    TwapPair {
+        market_index: u64,
+    },
+}
+
+impl Default for ViewParams {
+    fn default() -> Self {
+        ViewParams::PeriodFundingFlow { market_index: 0 }
+    }
+}
+
+/// Runs the query against the loaded accounts, results are in the precision of the method queried
+pub fn view(
+    params: &ViewParams,
+    market_map: &MarketMap,
+    oracle_map: &mut OracleMap,
+    user_account: Option<&AccountLoader<User>>,
+    now: i64,
+) -> ClearingHouseResult<Vec<i128>> {
+    match *params {
+        ViewParams::PeriodFundingFlow { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.period_funding_flow()?])
+        }
+        ViewParams::OracleConfidenceBps { oracle } => {
+            let oracle_price_data = oracle_map.get_price_data(&oracle)?;
+            Ok(vec![cast_to_i128(oracle_price_data.confidence_bps()?)?])
+        }
+        ViewParams::MarkTwapDeviationBps {
+            market_index,
+            spot_mark,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.amm.mark_twap_deviation_bps(spot_mark)?])
+        }
+        ViewParams::HaircutCollateralValue { oracle, amount } => {
+            let oracle_price_data = oracle_map.get_price_data(&oracle)?;
+            Ok(vec![cast_to_i128(haircut_collateral_value(
+                amount,
+                oracle_price_data,
+            )?)?])
+        }
+        ViewParams::ClampDivisorForMaxApr {
+            market_index,
+            target_apr_bps,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(
+                market.clamp_divisor_for_max_apr(target_apr_bps)?,
+            )?])
+        }
+        ViewParams::ReservesAfterTrade {
+            market_index,
+            base_amount,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let (base_asset_reserve, quote_asset_reserve) =
+                market.amm.reserves_after_trade(base_amount)?;
+            Ok(vec![
+                cast_to_i128(base_asset_reserve)?,
+                cast_to_i128(quote_asset_reserve)?,
+            ])
+        }
+        ViewParams::OraclePrice { oracle } => {
+            oracle_price_data_result(oracle_map.get_price_data(&oracle)?)
+        }
+        ViewParams::FundingSince {
+            baseline_cumulative_funding_rate,
+            current_cumulative_funding_rate,
+            base_asset_amount,
+        } => Ok(vec![funding_since(
+            baseline_cumulative_funding_rate,
+            current_cumulative_funding_rate,
+            base_asset_amount,
+        )?]),
+        ViewParams::UtilizationBps { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.amm.utilization_bps()?)?])
+        }
+        ViewParams::FundingOracleSensitivity { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.funding_oracle_sensitivity()?])
+        }
+        ViewParams::FundingPerBaseUnit { market_index, long } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.funding_per_base_unit(long)?])
+        }
+        ViewParams::ImpliedMarkTwap { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.implied_mark_twap()?)?])
+        }
+        ViewParams::FundingCostForPosition {
+            market_index,
+            collateral,
+            leverage,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.funding_cost_for_position(collateral, leverage)?])
+        }
+        ViewParams::SpreadBps { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.amm.spread_bps()?)?])
+        }
+        ViewParams::SlotsUntilStale { oracle, max_delay } => {
+            let oracle_price_data = oracle_map.get_price_data(&oracle)?;
+            Ok(vec![cast_to_i128(
+                oracle_price_data.slots_until_stale(max_delay),
+            )?])
+        }
+        ViewParams::IsNearReserveFloor {
+            market_index,
+            threshold_bps,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(
+                market.amm.is_near_reserve_floor(threshold_bps)? as u8,
+            )?])
+        }
+        ViewParams::PriceInDecimals {
+            oracle,
+            target_decimals,
+        } => {
+            let oracle_price_data = oracle_map.get_price_data(&oracle)?;
+            Ok(vec![oracle_price_data.price_in_decimals(target_decimals)?])
+        }
+        ViewParams::BasisBreakevenRate {
+            market_index,
+            carry_cost_bps,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.basis_breakeven_rate(carry_cost_bps)?])
+        }
+        ViewParams::PeriodAdjustment { funding_period } => {
+            Ok(vec![calculate_period_adjustment(funding_period)?])
+        }
+        ViewParams::FundingAcceleration { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.amm.funding_acceleration()?])
+        }
+        ViewParams::FundingAsMarginReturnBps {
+            market_index,
+            base_asset_amount,
+            margin,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let market_position = MarketPosition {
+                market_index,
+                base_asset_amount,
+                ..MarketPosition::default()
+            };
+            Ok(vec![
+                market_position.funding_as_margin_return_bps(&market.amm, margin)?
+            ])
+        }
+        ViewParams::OraclePriceAtSlot { oracle, slot } => {
+            oracle_price_data_result(&oracle_map.get_price_data_at_slot(&oracle, slot)?)
+        }
+        ViewParams::MarkPrice { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.amm.mark_price()?)?])
+        }
+        ViewParams::OraclePriceWithConversion {
+            market_index,
+            oracle_price_multiplier,
+            oracle_price_divisor,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let mut amm = market.amm;
+            amm.oracle_price_multiplier = oracle_price_multiplier;
+            amm.oracle_price_divisor = oracle_price_divisor;
+            let oracle_price_data = oracle_map.get_amm_price_data(&amm)?;
+            Ok(vec![
+                oracle_price_data.price,
+                cast_to_i128(oracle_price_data.confidence)?,
+            ])
+        }
+        ViewParams::TimeWeightedOi {
+            market_index,
+            window_seconds,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(
+                market.amm.time_weighted_oi(now, window_seconds)?,
+            )?])
+        }
+        ViewParams::PositionFundingPayment {
+            market_index,
+            base_asset_amount,
+            last_cumulative_funding_rate,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let market_position = MarketPosition {
+                market_index,
+                base_asset_amount,
+                last_cumulative_funding_rate,
+                ..MarketPosition::default()
+            };
+            let funding_payment =
+                calculate_position_funding_payment(&market_position, &market.amm)?
+                    .map_or(0, |position_funding_payment| {
+                        position_funding_payment.funding_payment
+                    });
+            Ok(vec![funding_payment])
+        }
+        ViewParams::FundingRateForTargetPremiumBps {
+            market_index,
+            target_premium_bps,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![
+                market.funding_rate_for_target_premium_bps(target_premium_bps)?
+            ])
+        }
+        ViewParams::PeriodsToFundingLiquidation {
+            market_index,
+            base_asset_amount,
+            maintenance_margin,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let market_position = MarketPosition {
+                market_index,
+                base_asset_amount,
+                ..MarketPosition::default()
+            };
+            Ok(vec![cast_to_i128(
+                market_position.periods_to_funding_liquidation(&market.amm, maintenance_margin)?,
+            )?])
+        }
+        ViewParams::HedgeRatio { oracle_a, oracle_b } => {
+            let price_a = oracle_map.get_price_data(&oracle_a)?.price;
+            let price_b = oracle_map.get_price_data(&oracle_b)?.price;
+            Ok(vec![hedge_ratio(price_a, price_b)?])
+        }
+        ViewParams::FundingTimingErrorBps { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.funding_timing_error_bps(now)?])
+        }
+        ViewParams::SkewedMarkPrice {
+            market_index,
+            skew_factor,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let mut amm = market.amm;
+            amm.skew_factor = skew_factor;
+            Ok(vec![cast_to_i128(amm.skewed_mark_price()?)?])
+        }
+        ViewParams::AbsFundingRateAbove {
+            market_index,
+            threshold,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(
+                (market.abs_funding_rate()? > threshold) as u8,
+            )?])
+        }
+        ViewParams::Microprice {
+            market_index,
+            bid_size,
+            ask_size,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(
+                market.amm.microprice(bid_size, ask_size)?,
+            )?])
+        }
+        ViewParams::OraclePriceMedian => {
+            oracle_price_data_result(&oracle_map.get_median_price_data()?)
+        }
+        ViewParams::OraclePriceContributors => {
+            let mut result = vec![];
+            for (oracle_source, price_data) in oracle_map.get_contributor_price_data() {
+                result.push(cast_to_i128(oracle_source as u8)?);
+                match price_data {
+                    Some(price_data) => {
+                        result.push(1);
+                        result.extend(oracle_price_data_result(&price_data)?);
+                    }
+                    // unreadable feeds keep their slot so the results line up with the oracles
+                    None => result.extend([0; 5]),
+                }
+            }
+            Ok(result)
+        }
+        ViewParams::ClampedFundingRateForTargetPremiumBps {
+            market_index,
+            target_premium_bps,
+            funding_clamp_divisor,
+        } => {
+            let mut market = *market_map.get_ref(&market_index)?;
+            market.amm.funding_clamp_divisor = funding_clamp_divisor;
+            Ok(vec![
+                market.funding_rate_for_target_premium_bps(target_premium_bps)?
+            ])
+        }
+        ViewParams::MinCollateralForFunding {
+            market_index,
+            base_asset_amount,
+            funding_rate,
+        } => {
+            let mut market = *market_map.get_ref(&market_index)?;
+            market.amm.last_funding_rate = funding_rate;
+            Ok(vec![cast_to_i128(
+                market.min_collateral_for_funding(base_asset_amount)?,
+            )?])
+        }
+        ViewParams::FundingRate {
+            mark_twap,
+            oracle_twap,
+            funding_period,
+            clamp_divisor,
+        } => Ok(vec![calculate_funding_rate(
+            mark_twap,
+            oracle_twap,
+            funding_period,
+            clamp_divisor,
+        )?]),
+        ViewParams::CappedFundingRate {
+            market_index,
+            funding_rate,
+            max_funding_rate,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let mut amm = market.amm;
+            amm.max_funding_rate = max_funding_rate;
+            Ok(vec![amm.cap_funding_rate(funding_rate)?])
+        }
+        ViewParams::CumulativeClampSuppression { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.amm.cumulative_clamp_suppression()?])
+        }
+        ViewParams::UnsettledFunding { user } => {
+            let user = load_user(user_account, &user)?;
+            Ok(vec![user.preview_funding_settlement(market_map)?])
+        }
+        ViewParams::SuggestedRepeg { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.amm.suggested_repeg()?)?])
+        }
+        ViewParams::PositionCarryAprBps {
+            market_index,
+            base_asset_amount,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let market_position = MarketPosition {
+                market_index,
+                base_asset_amount,
+                ..MarketPosition::default()
+            };
+            Ok(vec![market_position.carry_apr_bps(&market)?])
+        }
+        ViewParams::PriceForReserves {
+            quote_asset_reserve,
+            base_asset_reserve,
+            peg_multiplier,
+        } => Ok(vec![cast_to_i128(calculate_price(
+            quote_asset_reserve,
+            base_asset_reserve,
+            peg_multiplier,
+        )?)?]),
+        ViewParams::RealizedPnlWithFunding {
+            market_index,
+            base_asset_amount,
+            quote_asset_amount,
+            last_cumulative_funding_rate,
+            exit_price,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let market_position = MarketPosition {
+                market_index,
+                base_asset_amount,
+                quote_asset_amount,
+                last_cumulative_funding_rate,
+                ..MarketPosition::default()
+            };
+            Ok(vec![
+                market_position.realized_pnl_with_funding(&market.amm, exit_price)?
+            ])
+        }
+        ViewParams::PriceAfterTrade {
+            market_index,
+            base_asset_amount,
+            direction,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let price_after =
+                calculate_price_after_trade(&market.amm, base_asset_amount, direction)?;
+            let slippage_bps = calculate_slippage_bps(price_after, market.amm.mark_price()?)?;
+            Ok(vec![cast_to_i128(price_after)?, slippage_bps])
+        }
+        ViewParams::ExpectedFundingCallsPerDay {
+            market_index,
+            funding_period,
+        } => {
+            let mut market = *market_map.get_ref(&market_index)?;
+            market.amm.funding_period = funding_period;
+            Ok(vec![cast_to_i128(market.expected_funding_calls_per_day())?])
+        }
+        ViewParams::FundingRateEma {
+            funding_rate_ema,
+            funding_rate,
+            alpha,
+        } => Ok(vec![calculate_funding_rate_ema(
+            funding_rate_ema,
+            funding_rate,
+            alpha,
+        )?]),
+        ViewParams::OraclePriceChecked { oracle, max_delay } => {
+            oracle_price_data_result(&oracle_map.get_price_data_checked(&oracle, max_delay)?)
+        }
+        ViewParams::ImpliedBorrowLendRates { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            let (borrow_rate, lend_rate) = market.implied_borrow_lend_rates()?;
+            Ok(vec![borrow_rate, lend_rate])
+        }
+        ViewParams::OraclePriceWithBounds { oracle } => {
+            let (lower_bound, price, upper_bound) = oracle_map.get_price_with_bounds(&oracle)?;
+            Ok(vec![lower_bound, price, upper_bound])
+        }
+        ViewParams::PegForTargetPrice {
+            quote_asset_reserve,
+            base_asset_reserve,
+            target_price,
+        } => Ok(vec![cast_to_i128(calculate_peg_from_target_price(
+            quote_asset_reserve,
+            base_asset_reserve,
+            target_price,
+        )?)?]),
+        ViewParams::OracleMoveSinceLastFunding {
+            market_index,
+            current_oracle,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market
+                .amm
+                .oracle_move_since_last_funding(current_oracle)?])
+        }
+        ViewParams::OraclePriceTwap { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![get_oracle_price_twap(&market.amm, now)?])
+        }
+        ViewParams::FundingClampPriceBounds {
+            market_index,
+            oracle_twap,
+            funding_clamp_divisor,
+        } => {
+            let mut market = *market_map.get_ref(&market_index)?;
+            market.amm.funding_clamp_divisor = funding_clamp_divisor;
+            let (lower_bound, upper_bound) = market.funding_clamp_price_bounds(oracle_twap)?;
+            Ok(vec![lower_bound, upper_bound])
+        }
+        ViewParams::MarkTwapAge { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(market.amm.mark_twap_age(now)?)?])
+        }
+        ViewParams::QuoteAssetPrice { quote_oracle } => {
+            oracle_price_data_result(&oracle_map.get_quote_asset_price_data(&quote_oracle)?)
+        }
+        ViewParams::FundingSignCounts { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            let (positive, negative, zero) = market.amm.funding_sign_counts();
+            Ok(vec![
+                cast_to_i128(positive)?,
+                cast_to_i128(negative)?,
+                cast_to_i128(zero)?,
+            ])
+        }
+        ViewParams::PriceForReservesWithRounding {
+            quote_asset_reserve,
+            base_asset_reserve,
+            peg_multiplier,
+            rounding,
+        } => Ok(vec![cast_to_i128(calculate_price_with_rounding(
+            quote_asset_reserve,
+            base_asset_reserve,
+            peg_multiplier,
+            rounding,
+        )?)?]),
+        ViewParams::NextFundingTime { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![cast_to_i128(calculate_next_funding_time(
+                &market.amm,
+            )?)?])
+        }
+        ViewParams::ProjectedFundingOverWindow {
+            market_index,
+            base_asset_amount,
+            mark_price,
+            window_seconds,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            Ok(vec![market.amm.projected_funding_over_window(
+                base_asset_amount,
+                mark_price,
+                window_seconds,
+            )?])
+        }
+        ViewParams::OraclePriceWithPythEma {
+            market_index,
+            use_pyth_ema_price,
+        } => {
+            let market = market_map.get_ref(&market_index)?;
+            let mut amm = market.amm;
+            amm.use_pyth_ema_price = use_pyth_ema_price;
+            let oracle_price_data = oracle_map.get_amm_price_data(&amm)?;
+            Ok(vec![
+                oracle_price_data.price,
+                cast_to_i128(oracle_price_data.confidence)?,
+            ])
+        }
+        ViewParams::TwapPair { market_index } => {
+            let market = market_map.get_ref(&market_index)?;
+            let (mark_twap, oracle_twap) = market.amm.twap_pair();
+            Ok(vec![cast_to_i128(mark_twap)?, oracle_twap])
+        }
+    }
+}
+
+/// [price, confidence, delay, has_sufficient_number_of_data_points as 0 or 1]
+fn oracle_price_data_result(oracle_price_data: &OraclePriceData) -> ClearingHouseResult<Vec<i128>> {
+    Ok(vec![
+        oracle_price_data.price,
+        cast_to_i128(oracle_price_data.confidence)?,
+        cast_to_i128(oracle_price_data.delay)?,
+        cast_to_i128(oracle_price_data.has_sufficient_number_of_data_points as u8)?,
+    ])
+}
+
+/// The user account passed to the view, which has to be the user the query names
+fn load_user<'a>(
+    user_account: Option<&'a AccountLoader<User>>,
+    user: &Pubkey,
+) -> ClearingHouseResult<Ref<'a, User>> {
+    match user_account {
+        Some(user_account) if user_account.key() == *user => load(user_account),
+        _ => {
+            msg!("User {} wasn't passed after the markets", user);
+            Err(ErrorCode::UnableToLoadAccountLoader)
+        }
+    }
+}
@@ -158,6 +158,24 @@ pub enum ErrorCode {
     UserMustSettleTheirOwnPositiveUnsettledPNL,
     #[msg("CantUpdatePNLPoolBalanceType")]
     CantUpdatePNLPoolBalanceType,
+    #[msg("InvalidFundingPeriod")]
+    InvalidFundingPeriod,
+    #[msg("FundingRateBatchTooLarge")]
+    FundingRateBatchTooLarge,
+    #[msg("OracleAccountWrongOwner")]
+    OracleAccountWrongOwner,
+    #[msg("InvalidTwapWindow")]
+    InvalidTwapWindow,
+    #[msg("InvalidOraclePrice")]
+    InvalidOraclePrice,
+    #[msg("StaleOracle")]
+    StaleOracle,
+    #[msg("DivideByZero")]
+    DivideByZero,
+    #[msg("InvalidMarketParameter")]
+    InvalidMarketParameter,
+    #[msg("UnexpectedAccountLength")]
+    UnexpectedAccountLength,
 }
 
 #[macro_export]
@@ -5,3 +5,22 @@ pub mod pyth_program {
     #[cfg(not(feature = "mainnet-beta"))]
     declare_id!("gSbePebfvPy7tRqimPoVecS2UsBvYv46ynrzWocc92s");
 }
+
+// owner of pyth's PriceUpdateV2 price feed accounts
+pub mod pyth_receiver_program {
+    use solana_program::declare_id;
+    declare_id!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+}
+
+pub mod switchboard_program {
+    use solana_program::declare_id;
+    #[cfg(feature = "mainnet-beta")]
+    declare_id!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+    #[cfg(not(feature = "mainnet-beta"))]
+    declare_id!("2TfB33aLaneQb5TNVwyDz3jSZXS6jdW2ARw1Dgf84XCG");
+}
+
+pub mod chainlink_program {
+    use solana_program::declare_id;
+    declare_id!("HEvSKofvBgfaexv23kMabbYqxasxU3mQ4ibBMEmJWHny");
+}
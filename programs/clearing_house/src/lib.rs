@@ -6,9 +6,10 @@ use borsh::BorshSerialize;
 
 use context::*;
 use controller::position::PositionDirection;
+use controller::view::ViewParams;
 use error::ErrorCode;
 use math::{amm, bn, constants::*, fees, margin::*, orders::*};
-use state::oracle::{get_oracle_price, OracleSource};
+use state::oracle::{get_oracle_price, IndexPrice, OracleSource};
 
 use crate::math::amm::get_update_k_result;
 use crate::state::market::Market;
@@ -49,6 +50,7 @@ pub mod clearing_house {
     };
     use crate::math::bank_balance::get_token_amount;
     use crate::math::casting::{cast, cast_to_i128, cast_to_u128, cast_to_u64};
+    use crate::math::funding::validate_funding_period;
     use crate::math::slippage::{calculate_slippage, calculate_slippage_pct};
     use crate::optional_accounts::{get_discount_token, get_referrer, get_referrer_for_fill_order};
     use crate::state::bank::{Bank, BankBalance, BankBalanceType};
@@ -56,9 +58,11 @@ pub mod clearing_house {
     use crate::state::events::TradeRecord;
     use crate::state::events::{CurveRecord, DepositRecord};
     use crate::state::events::{DepositDirection, LiquidationRecord};
+    use crate::state::events::{FundingSettlementRecord, ViewRecord};
     use crate::state::market::{Market, PNLPool};
     use crate::state::market_map::{
-        get_market_oracles, get_writable_markets, get_writable_markets_for_user_positions,
+        get_market_oracles, get_writable_markets, get_writable_markets_for_funding_settlement,
+        get_writable_markets_for_user_positions, get_writable_markets_with_funding_settlement,
         MarketMap, MarketOracles, WritableMarkets,
     };
     use crate::state::oracle::OraclePriceData;
@@ -150,8 +154,10 @@ pub mod clearing_house {
             order_state: Pubkey::default(),
             number_of_markets: 0,
             number_of_banks: 0,
-            padding0: 0,
-            padding1: 0,
+            funding_block_guard_rails: FundingBlockGuardRails {
+                block_divergence_pct: BID_ASK_SPREAD_PRECISION / 10,
+                release_divergence_pct: BID_ASK_SPREAD_PRECISION / 20,
+            },
             padding2: 0,
             padding3: 0,
         };
@@ -388,12 +394,17 @@ pub mod clearing_house {
                 .get_switchboard_price(&ctx.accounts.oracle, clock_slot)
                 .unwrap(),
             OracleSource::QuoteAsset => panic!(),
+            OracleSource::Index | OracleSource::Chainlink => {
+                get_oracle_price(&oracle_source, &ctx.accounts.oracle, clock_slot).unwrap()
+            }
         };
 
         let last_oracle_price_twap = match oracle_source {
             OracleSource::Pyth => market.amm.get_pyth_twap(&ctx.accounts.oracle)?,
             OracleSource::Switchboard => oracle_price,
             OracleSource::QuoteAsset => panic!(),
+            OracleSource::Index => oracle_price,
+            OracleSource::Chainlink => oracle_price,
         };
 
         validate_margin(
@@ -402,6 +413,9 @@ pub mod clearing_house {
             margin_ratio_maintenance,
         )?;
 
+        validate_funding_period(amm_periodicity)?;
+        amm::validate_twap_window(amm_periodicity)?;
+
         let state = &mut ctx.accounts.state;
         let market_index = state.number_of_markets;
         **market = Market {
@@ -472,6 +486,36 @@ pub mod clearing_house {
                 short_intensity_time: amm_periodicity,
                 short_intensity_count: 0,
                 short_intensity_volume: 0,
+                funding_frozen: false,
+                funding_update_count: 0,
+                oracle_squared_return_sum: 0,
+                previous_funding_rate: 0,
+                oracle_price_multiplier: 1,
+                oracle_price_divisor: 1,
+                last_open_interest_twap: 0,
+                last_open_interest_twap_ts: now,
+                funding_rate_checksum: 0,
+                skew_factor: 0,
+                funding_blocked: false,
+                funding_use_microprice: false,
+                oracle_outage_recovery_count: 0,
+                funding_clamp_divisor: 0,
+                max_funding_rate: 0,
+                cumulative_funding_clamp_suppression: 0,
+                allow_negative_oracle: false,
+                oracle_price_ema: 0,
+                oracle_ema_halflife: 0,
+                funding_use_oracle_ema: false,
+                funding_rate_ema: 0,
+                funding_rate_ema_alpha: 0,
+                last_funding_oracle_price_twap: 0,
+                pyth_max_confidence_bps: 0,
+                funding_positive_count: 0,
+                funding_negative_count: 0,
+                funding_zero_count: 0,
+                funding_payment_dust: 0,
+                use_pyth_ema_price: false,
+                switchboard_max_confidence_bps: 0,
                 curve_update_intensity: 0,
                 padding0: 0,
                 padding1: 0,
@@ -504,7 +548,7 @@ pub mod clearing_house {
         let bank_map = BankMap::load(&get_writable_banks(bank_index), remaining_accounts_iter)?;
 
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&user.positions),
             &MarketOracles::new(),
             remaining_accounts_iter,
         )?;
@@ -583,7 +627,7 @@ pub mod clearing_house {
         let mut oracle_map = OracleMap::load(remaining_accounts_iter, clock.slot)?;
         let bank_map = BankMap::load(&get_writable_banks(bank_index), remaining_accounts_iter)?;
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&user.positions),
             &MarketOracles::new(),
             remaining_accounts_iter,
         )?;
@@ -772,7 +816,7 @@ pub mod clearing_house {
             remaining_accounts_iter,
         )?;
         let market_map = MarketMap::load(
-            &get_writable_markets(market_index),
+            &get_writable_markets_with_funding_settlement(market_index, &user.positions),
             &get_market_oracles(market_index, &ctx.accounts.oracle),
             remaining_accounts_iter,
         )?;
@@ -1018,6 +1062,7 @@ pub mod clearing_house {
                 now,
                 clock_slot,
                 &ctx.accounts.state.oracle_guard_rails,
+                &ctx.accounts.state.funding_block_guard_rails,
                 ctx.accounts.state.funding_paused,
                 Some(mark_price_before),
             )?;
@@ -1047,7 +1092,7 @@ pub mod clearing_house {
             remaining_accounts_iter,
         )?;
         let market_map = MarketMap::load(
-            &get_writable_markets(market_index),
+            &get_writable_markets_with_funding_settlement(market_index, &user.positions),
             &get_market_oracles(market_index, &ctx.accounts.oracle),
             remaining_accounts_iter,
         )?;
@@ -1216,6 +1261,7 @@ pub mod clearing_house {
             now,
             clock_slot,
             &ctx.accounts.state.oracle_guard_rails,
+            &ctx.accounts.state.funding_block_guard_rails,
             ctx.accounts.state.funding_paused,
             Some(mark_price_before),
         )?;
@@ -1228,7 +1274,7 @@ pub mod clearing_house {
         let _oracle_map = OracleMap::load(remaining_accounts_iter, Clock::get()?.slot)?;
         let _bank_map = BankMap::load(&WritableMarkets::new(), remaining_accounts_iter)?;
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&load(&ctx.accounts.user)?.positions),
             &get_market_oracles(params.market_index, &ctx.accounts.oracle),
             remaining_accounts_iter,
         )?;
@@ -1290,7 +1336,7 @@ pub mod clearing_house {
         let mut oracle_map = OracleMap::load(remaining_accounts_iter, Clock::get()?.slot)?;
         let bank_map = BankMap::load(&WritableMarkets::new(), remaining_accounts_iter)?;
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&load(&ctx.accounts.user)?.positions),
             market_oracles,
             remaining_accounts_iter,
         )?;
@@ -1328,7 +1374,7 @@ pub mod clearing_house {
         let mut oracle_map = OracleMap::load(remaining_accounts_iter, Clock::get()?.slot)?;
         let bank_map = BankMap::load(&WritableMarkets::new(), remaining_accounts_iter)?;
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&load(&ctx.accounts.user)?.positions),
             market_oracles,
             remaining_accounts_iter,
         )?;
@@ -1362,7 +1408,7 @@ pub mod clearing_house {
             let order = &user.orders[order_index];
 
             (
-                &get_writable_markets(order.market_index),
+                &get_writable_markets_with_funding_settlement(order.market_index, &user.positions),
                 &get_market_oracles(order.market_index, &ctx.accounts.oracle),
             )
         };
@@ -1418,7 +1464,10 @@ pub mod clearing_house {
             remaining_accounts_iter,
         )?;
         let market_map = MarketMap::load(
-            &get_writable_markets(params.market_index),
+            &get_writable_markets_with_funding_settlement(
+                params.market_index,
+                &load(&ctx.accounts.user)?.positions,
+            ),
             &get_market_oracles(params.market_index, &ctx.accounts.oracle),
             remaining_accounts_iter,
         )?;
@@ -2382,6 +2431,14 @@ pub mod clearing_house {
         Ok(())
     }
 
+    pub fn migrate_user(ctx: Context<MigrateUser>, _user_id: u8) -> Result<()> {
+        controller::migration::migrate_user(
+            &ctx.accounts.user,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )
+    }
+
     #[access_control(
         exchange_not_paused(&ctx.accounts.state)
     )]
@@ -2391,7 +2448,7 @@ pub mod clearing_house {
 
         let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
         let market_map = MarketMap::load(
-            &WritableMarkets::new(),
+            &get_writable_markets_for_funding_settlement(&load(&ctx.accounts.user)?.positions),
             &MarketOracles::new(), // oracles validated in calculate liquidation status
             remaining_accounts_iter,
         )?;
@@ -2402,6 +2459,48 @@ pub mod clearing_house {
         Ok(())
     }
 
+    /// Settles funding for several users at once and records the total settled across them
+    /// The markets of the users' open positions are passed as remaining accounts, followed by the users
+    #[access_control(
+        exchange_not_paused(&ctx.accounts.state)
+    )]
+    pub fn settle_funding_payments(ctx: Context<SettleFundingPayments>) -> Result<()> {
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+
+        let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+        // settlement loads the markets mutably, which fails for any passed read-only
+        let market_map = MarketMap::load(
+            &WritableMarkets::new(),
+            &MarketOracles::new(),
+            remaining_accounts_iter,
+        )?;
+
+        let user_loaders = remaining_accounts_iter
+            .map(AccountLoader::<User>::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let mut user_refs = user_loaders
+            .iter()
+            .map(|user_loader| Ok((user_loader.key(), load_mut(user_loader)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut users = user_refs
+            .iter_mut()
+            .map(|(user_key, user)| (&*user_key, &mut **user))
+            .collect::<Vec<_>>();
+
+        let settlement_stats = controller::funding::settle_and_total(&mut users, &market_map, now)?;
+
+        emit!(FundingSettlementRecord {
+            ts: now,
+            number_of_users: cast(users.len())?,
+            total_funding_payment: settlement_stats.total_funding,
+            positions_iterated: settlement_stats.iterated,
+            positions_settled: settlement_stats.settled,
+        });
+
+        Ok(())
+    }
+
     #[allow(unused_must_use)]
     #[access_control(
         market_initialized(&ctx.accounts.market) &&
@@ -2415,6 +2514,8 @@ pub mod clearing_house {
         let now = clock.unix_timestamp;
         let clock_slot = clock.slot;
 
+        validate_funding_period(market.amm.funding_period)?;
+
         controller::funding::update_funding_rate(
             market_index,
             market,
@@ -2422,6 +2523,7 @@ pub mod clearing_house {
             now,
             clock_slot,
             &ctx.accounts.state.oracle_guard_rails,
+            &ctx.accounts.state.funding_block_guard_rails,
             ctx.accounts.state.funding_paused,
             None,
         )?;
@@ -2429,6 +2531,83 @@ pub mod clearing_house {
         Ok(())
     }
 
+    #[access_control(
+        exchange_not_paused(&ctx.accounts.state)
+    )]
+    pub fn update_funding_rates(
+        ctx: Context<UpdateFundingRates>,
+        market_indexes: Vec<u64>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let clock_slot = clock.slot;
+
+        let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+        let oracle_map = OracleMap::load(remaining_accounts_iter, clock_slot)?;
+
+        let mut writable_markets = WritableMarkets::new();
+        for market_index in market_indexes.iter() {
+            writable_markets.insert(*market_index);
+        }
+        let market_map = MarketMap::load(
+            &writable_markets,
+            &MarketOracles::new(),
+            remaining_accounts_iter,
+        )?;
+
+        for market_index in market_indexes.iter() {
+            validate_funding_period(market_map.get_ref(market_index)?.amm.funding_period)?;
+        }
+
+        controller::funding::update_funding_rates(
+            &market_indexes,
+            &market_map,
+            &oracle_map,
+            now,
+            clock_slot,
+            &ctx.accounts.state.oracle_guard_rails,
+            &ctx.accounts.state.funding_block_guard_rails,
+            ctx.accounts.state.funding_paused,
+        )?;
+
+        Ok(())
+    }
+
+    /// Runs a read-only query and emits its result in a ViewRecord, meant to be simulated rather than sent
+    /// The oracles, markets and user the query reads are passed as remaining accounts
+    pub fn view(ctx: Context<View>, params: ViewParams) -> Result<()> {
+        let clock = Clock::get()?;
+
+        let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+        let mut oracle_map = OracleMap::load(remaining_accounts_iter, clock.slot)?;
+        let market_map = MarketMap::load(
+            &WritableMarkets::new(),
+            &MarketOracles::new(),
+            remaining_accounts_iter,
+        )?;
+        // a query about a user takes the user account after the markets
+        let user = remaining_accounts_iter
+            .next()
+            .map(AccountLoader::<User>::try_from)
+            .transpose()?;
+
+        let result = controller::view::view(
+            &params,
+            &market_map,
+            &mut oracle_map,
+            user.as_ref(),
+            clock.unix_timestamp,
+        )?;
+
+        emit!(ViewRecord {
+            ts: clock.unix_timestamp,
+            params,
+            result,
+        });
+
+        Ok(())
+    }
+
     #[allow(unused_must_use)]
     #[access_control(
         market_initialized(&ctx.accounts.market) &&
@@ -2446,11 +2625,7 @@ pub mod clearing_house {
         let base_asset_amount = market.amm.net_base_asset_amount;
         let open_interest = market.open_interest;
 
-        let price_before = math::amm::calculate_price(
-            market.amm.quote_asset_reserve,
-            market.amm.base_asset_reserve,
-            market.amm.peg_multiplier,
-        )?;
+        let price_before = market.amm.mark_price()?;
 
         let peg_multiplier_before = market.amm.peg_multiplier;
         let base_asset_reserve_before = market.amm.base_asset_reserve;
@@ -2490,11 +2665,7 @@ pub mod clearing_house {
 
         let amm = &market.amm;
 
-        let price_after = math::amm::calculate_price(
-            amm.quote_asset_reserve,
-            amm.base_asset_reserve,
-            amm.peg_multiplier,
-        )?;
+        let price_after = amm.mark_price()?;
 
         let price_change_too_large = cast_to_i128(price_before)?
             .checked_sub(cast_to_i128(price_after)?)
@@ -2683,6 +2854,24 @@ pub mod clearing_house {
         Ok(())
     }
 
+    pub fn update_funding_block_guard_rails(
+        ctx: Context<AdminUpdateState>,
+        funding_block_guard_rails: FundingBlockGuardRails,
+    ) -> Result<()> {
+        ctx.accounts.state.funding_block_guard_rails = funding_block_guard_rails;
+        Ok(())
+    }
+
+    pub fn migrate_market(ctx: Context<MigrateMarket>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        controller::migration::migrate_market(
+            &ctx.accounts.market,
+            &ctx.accounts.admin,
+            &ctx.accounts.system_program,
+            now,
+        )
+    }
+
     #[access_control(
         market_initialized(&ctx.accounts.market)
     )]
@@ -2697,6 +2886,177 @@ pub mod clearing_house {
         Ok(())
     }
 
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_funding_frozen(
+        ctx: Context<AdminUpdateMarket>,
+        funding_frozen: bool,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.funding_frozen = funding_frozen;
+        Ok(())
+    }
+
+    pub fn initialize_index_price(ctx: Context<InitializeIndexPrice>, expo: i32) -> Result<()> {
+        let index_price = &mut ctx.accounts.index_price.load_init()?;
+        **index_price = IndexPrice {
+            authority: *ctx.accounts.authority.key,
+            price: 0,
+            conf: 0,
+            expo,
+            valid_slot: 0,
+        };
+        Ok(())
+    }
+
+    pub fn update_index_price(ctx: Context<UpdateIndexPrice>, price: i64, conf: u64) -> Result<()> {
+        validate!(
+            price > 0,
+            ErrorCode::InvalidOraclePrice,
+            "index price {} must be positive",
+            price
+        )?;
+        validate!(
+            conf <= price.unsigned_abs(),
+            ErrorCode::InvalidOraclePrice,
+            "index confidence {} can not be wider than the price {}",
+            conf,
+            price
+        )?;
+
+        let clock = Clock::get()?;
+        let index_price = &mut ctx.accounts.index_price.load_mut()?;
+        index_price.price = price;
+        index_price.conf = conf;
+        index_price.valid_slot = clock.slot;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_allow_negative_oracle(
+        ctx: Context<AdminUpdateMarket>,
+        allow_negative_oracle: bool,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.allow_negative_oracle = allow_negative_oracle;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_oracle_ema_halflife(
+        ctx: Context<AdminUpdateMarket>,
+        oracle_ema_halflife: i64,
+    ) -> Result<()> {
+        validate!(
+            oracle_ema_halflife >= 0,
+            ErrorCode::InvalidTwapWindow,
+            "oracle ema halflife must be non-negative"
+        )?;
+
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.oracle_ema_halflife = oracle_ema_halflife;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_funding_use_oracle_ema(
+        ctx: Context<AdminUpdateMarket>,
+        funding_use_oracle_ema: bool,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.funding_use_oracle_ema = funding_use_oracle_ema;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_funding_rate_ema_alpha(
+        ctx: Context<AdminUpdateMarket>,
+        funding_rate_ema_alpha: u128,
+    ) -> Result<()> {
+        validate!(
+            funding_rate_ema_alpha <= BPS_PRECISION,
+            ErrorCode::InvalidMarketParameter,
+            "funding rate ema alpha must be at most {}",
+            BPS_PRECISION
+        )?;
+
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.funding_rate_ema_alpha = funding_rate_ema_alpha;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_pyth_max_confidence_bps(
+        ctx: Context<AdminUpdateMarket>,
+        pyth_max_confidence_bps: u128,
+    ) -> Result<()> {
+        validate!(
+            pyth_max_confidence_bps <= BPS_PRECISION,
+            ErrorCode::InvalidOracle,
+            "pyth max confidence bps must be at most {}",
+            BPS_PRECISION
+        )?;
+
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.pyth_max_confidence_bps = pyth_max_confidence_bps;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_switchboard_max_confidence_bps(
+        ctx: Context<AdminUpdateMarket>,
+        switchboard_max_confidence_bps: u128,
+    ) -> Result<()> {
+        validate!(
+            switchboard_max_confidence_bps <= BPS_PRECISION,
+            ErrorCode::InvalidOracle,
+            "switchboard max confidence bps must be at most {}",
+            BPS_PRECISION
+        )?;
+
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.switchboard_max_confidence_bps = switchboard_max_confidence_bps;
+        Ok(())
+    }
+
+    #[access_control(
+        market_initialized(&ctx.accounts.market)
+    )]
+    pub fn update_market_funding_use_microprice(
+        ctx: Context<AdminUpdateMarket>,
+        funding_use_microprice: bool,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market.load_mut()?;
+        market.amm.funding_use_microprice = funding_use_microprice;
+        Ok(())
+    }
+
+    /// Caps the funding the user's open position in the market pays per funding period
+    /// The cap and the position's uncollected funding are cleared once the position is closed
+    pub fn update_user_max_funding_per_period(
+        ctx: Context<AdminUpdateUser>,
+        market_index: u64,
+        max_funding_per_period: u128,
+    ) -> Result<()> {
+        let user = &mut load_mut(&ctx.accounts.user)?;
+        let market_position = user.get_position_mut(market_index)?;
+        market_position.max_funding_per_period = max_funding_per_period;
+        Ok(())
+    }
+
     #[access_control(
         market_initialized(&ctx.accounts.market)
     )]
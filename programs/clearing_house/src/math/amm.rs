@@ -1,5 +1,6 @@
 use std::cmp::{max, min};
 
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::msg;
 
 use crate::controller::amm::SwapDirection;
@@ -10,8 +11,9 @@ use crate::math::bn::U192;
 use crate::math::casting::{cast, cast_to_i128, cast_to_u128, cast_to_u64};
 use crate::math::constants::{
     AMM_TIMES_PEG_TO_QUOTE_PRECISION_RATIO_I128, BID_ASK_SPREAD_PRECISION,
-    BID_ASK_SPREAD_PRECISION_I128, K_BPS_DECREASE_MAX, K_BPS_INCREASE_MAX, K_BPS_UPDATE_SCALE,
-    MARK_PRICE_PRECISION, MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO_I128, ONE_HOUR_I128,
+    BID_ASK_SPREAD_PRECISION_I128, BPS_PRECISION, BPS_PRECISION_I128, K_BPS_DECREASE_MAX,
+    K_BPS_INCREASE_MAX, K_BPS_UPDATE_SCALE, MARK_PRICE_PRECISION, MARK_PRICE_PRECISION_I128,
+    MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO_I128, MAX_TWAP_WINDOW, ONE_HOUR_I128,
     PEG_PRECISION, PRICE_TO_PEG_PRECISION_RATIO,
 };
 use crate::math::position::_calculate_base_asset_value_and_pnl;
@@ -20,19 +22,59 @@ use crate::math_error;
 use crate::state::market::{Market, AMM};
 use crate::state::oracle::OraclePriceData;
 use crate::state::state::{PriceDivergenceGuardRails, ValidityGuardRails};
+use crate::validate;
 
+/// Price (in MARK_PRICE_PRECISION) for raw reserves, quote reserve first. Transposing the reserves inverts the
+/// price without any error, so prefer AMM::mark_price when pricing an amm's current reserves
 pub fn calculate_price(
     quote_asset_reserve: u128,
     base_asset_reserve: u128,
     peg_multiplier: u128,
 ) -> ClearingHouseResult<u128> {
+    calculate_price_with_rounding(
+        quote_asset_reserve,
+        base_asset_reserve,
+        peg_multiplier,
+        PriceRounding::Truncate,
+    )
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub enum PriceRounding {
+    Truncate,
+    HalfUp,
+}
+
+/// calculate_price with a choice of rounding for the final division
+pub fn calculate_price_with_rounding(
+    quote_asset_reserve: u128,
+    base_asset_reserve: u128,
+    peg_multiplier: u128,
+    rounding: PriceRounding,
+) -> ClearingHouseResult<u128> {
+    validate!(
+        base_asset_reserve != 0,
+        ErrorCode::DivideByZero,
+        "Can not calculate price with zero base asset reserve"
+    )?;
+
     let peg_quote_asset_amount = quote_asset_reserve
         .checked_mul(peg_multiplier)
         .ok_or_else(math_error!())?;
 
-    U192::from(peg_quote_asset_amount)
+    // multiply before dividing by the base reserve so the only rounding is in the final division
+    let numerator = U192::from(peg_quote_asset_amount)
         .checked_mul(U192::from(PRICE_TO_PEG_PRECISION_RATIO))
-        .ok_or_else(math_error!())?
+        .ok_or_else(math_error!())?;
+
+    let numerator = match rounding {
+        PriceRounding::Truncate => numerator,
+        PriceRounding::HalfUp => numerator
+            .checked_add(U192::from(base_asset_reserve / 2))
+            .ok_or_else(math_error!())?,
+    };
+
+    numerator
         .checked_div(U192::from(base_asset_reserve))
         .ok_or_else(math_error!())?
         .try_to_u128()
@@ -60,6 +102,30 @@ pub fn calculate_terminal_price(market: &mut Market) -> ClearingHouseResult<u128
     Ok(terminal_price)
 }
 
+/// Mark price (in MARK_PRICE_PRECISION) after trading base_asset_amount against the amm's reserves in direction,
+/// without modifying the amm. Only the magnitude of base_asset_amount is used
+pub fn calculate_price_after_trade(
+    amm: &AMM,
+    base_asset_amount: i128,
+    direction: PositionDirection,
+) -> ClearingHouseResult<u128> {
+    // reserves_after_trade takes longs as positive and shorts as negative base
+    let base_asset_amount = cast_to_i128(base_asset_amount.unsigned_abs())?;
+    let signed_base_asset_amount = match direction {
+        PositionDirection::Long => base_asset_amount,
+        PositionDirection::Short => -base_asset_amount,
+    };
+
+    let (new_base_asset_reserve, new_quote_asset_reserve) =
+        amm.reserves_after_trade(signed_base_asset_amount)?;
+
+    calculate_price(
+        new_quote_asset_reserve,
+        new_base_asset_reserve,
+        amm.peg_multiplier,
+    )
+}
+
 pub fn update_mark_twap(
     amm: &mut AMM,
     now: i64,
@@ -94,6 +160,8 @@ pub fn calculate_new_twap(
     current_price: u128,
     last_twap: u128,
 ) -> ClearingHouseResult<u128> {
+    validate_twap_window(amm.funding_period)?;
+
     let since_last = cast_to_i128(max(
         1,
         now.checked_sub(amm.last_mark_price_twap_ts)
@@ -123,34 +191,20 @@ pub fn update_oracle_price_twap(
     precomputed_mark_price: Option<u128>,
 ) -> ClearingHouseResult<i128> {
     let oracle_price = normalise_oracle_price(amm, oracle_price_data, precomputed_mark_price)?;
-
-    let new_oracle_price_spread = oracle_price
-        .checked_sub(amm.last_oracle_price_twap)
-        .ok_or_else(math_error!())?;
-
-    // cap new oracle update to 33% delta from twap
-    let oracle_price_33pct = oracle_price.checked_div(3).ok_or_else(math_error!())?;
-
-    let capped_oracle_update_price =
-        if new_oracle_price_spread.unsigned_abs() > oracle_price_33pct.unsigned_abs() {
-            if oracle_price > amm.last_oracle_price_twap {
-                amm.last_oracle_price_twap
-                    .checked_add(oracle_price_33pct)
-                    .ok_or_else(math_error!())?
-            } else {
-                amm.last_oracle_price_twap
-                    .checked_sub(oracle_price_33pct)
-                    .ok_or_else(math_error!())?
-            }
-        } else {
-            oracle_price
-        };
+    let capped_oracle_update_price = calculate_capped_oracle_update_price(amm, oracle_price)?;
 
     // sanity check
     let oracle_price_twap: i128;
-    if capped_oracle_update_price > 0 && oracle_price > 0 {
+    if amm.is_valid_oracle_price(capped_oracle_update_price)
+        && amm.is_valid_oracle_price(oracle_price)
+    {
+        // uses last_oracle_price_twap_ts, so update before the twap
+        update_oracle_price_ema(amm, now, capped_oracle_update_price)?;
+
         oracle_price_twap = calculate_new_oracle_price_twap(amm, now, capped_oracle_update_price)?;
 
+        update_oracle_squared_return_sum(amm, oracle_price_data.price)?;
+
         //amm.last_oracle_mark_spread = precomputed_mark_price
         amm.last_oracle_normalised_price = capped_oracle_update_price;
         amm.last_oracle_price = oracle_price_data.price;
@@ -168,11 +222,129 @@ pub fn update_oracle_price_twap(
     Ok(oracle_price_twap)
 }
 
+/// Caps a new oracle price (normalised) to a 33% delta from the last oracle twap before it enters the twap
+pub fn calculate_capped_oracle_update_price(
+    amm: &AMM,
+    oracle_price: i128,
+) -> ClearingHouseResult<i128> {
+    let new_oracle_price_spread = oracle_price
+        .checked_sub(amm.last_oracle_price_twap)
+        .ok_or_else(math_error!())?;
+
+    // cap new oracle update to 33% delta from twap
+    let oracle_price_33pct = cast_to_i128(oracle_price.unsigned_abs())?
+        .checked_div(3)
+        .ok_or_else(math_error!())?;
+
+    if new_oracle_price_spread.unsigned_abs() <= oracle_price_33pct.unsigned_abs() {
+        return Ok(oracle_price);
+    }
+
+    if oracle_price > amm.last_oracle_price_twap {
+        amm.last_oracle_price_twap
+            .checked_add(oracle_price_33pct)
+            .ok_or_else(math_error!())
+    } else {
+        amm.last_oracle_price_twap
+            .checked_sub(oracle_price_33pct)
+            .ok_or_else(math_error!())
+    }
+}
+
+/// Accumulates the squared return (in bps) of the oracle price since the last oracle update
+/// The sum is reset on each funding rate update, see calculate_oracle_volatility_bps
+pub fn update_oracle_squared_return_sum(amm: &mut AMM, oracle_price: i128) -> ClearingHouseResult {
+    if amm.last_oracle_price <= 0 {
+        return Ok(());
+    }
+
+    let oracle_return_bps = oracle_price
+        .checked_sub(amm.last_oracle_price)
+        .ok_or_else(math_error!())?
+        .checked_mul(BPS_PRECISION_I128)
+        .ok_or_else(math_error!())?
+        .checked_div(amm.last_oracle_price)
+        .ok_or_else(math_error!())?
+        .unsigned_abs();
+
+    amm.oracle_squared_return_sum = amm
+        .oracle_squared_return_sum
+        .checked_add(
+            oracle_return_bps
+                .checked_mul(oracle_return_bps)
+                .ok_or_else(math_error!())?,
+        )
+        .ok_or_else(math_error!())?;
+
+    Ok(())
+}
+
+/// Realized volatility of the oracle since the last funding rate update (in BPS_PRECISION)
+pub fn calculate_oracle_volatility_bps(amm: &AMM) -> ClearingHouseResult<u128> {
+    U192::from(amm.oracle_squared_return_sum)
+        .integer_sqrt()
+        .try_to_u128()
+}
+
+/// Moves amm.oracle_price_ema toward the oracle price with weight since_last / (since_last + oracle_ema_halflife)
+/// A single update one halflife after the last closes half the gap to the price. More frequent updates compound to
+/// close more of it per halflife (up to 1 - 1/e), so with a halflife well under the funding period the ema converges
+/// faster than the twap
+/// A halflife of zero makes the ema track the oracle price
+/// Takes the same capped, normalised oracle price as the twap so one bad print can't drag the ema further
+pub fn update_oracle_price_ema(amm: &mut AMM, now: i64, oracle_price: i128) -> ClearingHouseResult {
+    if amm.oracle_price_ema == 0 || amm.oracle_ema_halflife <= 0 {
+        amm.oracle_price_ema = oracle_price;
+        return Ok(());
+    }
+
+    let since_last = cast_to_i128(max(
+        0,
+        now.checked_sub(amm.last_oracle_price_twap_ts)
+            .ok_or_else(math_error!())?,
+    ))?;
+    let halflife = cast_to_i128(amm.oracle_ema_halflife)?;
+
+    amm.oracle_price_ema = amm
+        .oracle_price_ema
+        .checked_mul(halflife)
+        .ok_or_else(math_error!())?
+        .checked_add(
+            oracle_price
+                .checked_mul(since_last)
+                .ok_or_else(math_error!())?,
+        )
+        .ok_or_else(math_error!())?
+        .checked_div(halflife.checked_add(since_last).ok_or_else(math_error!())?)
+        .ok_or_else(math_error!())?;
+
+    Ok(())
+}
+
+/// Oracle twap (in MARK_PRICE_PRECISION) brought forward to now without modifying the amm, assuming the oracle
+/// stayed at the last normalised oracle price since the last update. Caps the price the same way
+/// update_oracle_price_twap does before applying the same twap math
+pub fn get_oracle_price_twap(amm: &AMM, now: i64) -> ClearingHouseResult<i128> {
+    if now <= amm.last_oracle_price_twap_ts {
+        return Ok(amm.last_oracle_price_twap);
+    }
+
+    let capped_oracle_update_price =
+        calculate_capped_oracle_update_price(amm, amm.last_oracle_normalised_price)?;
+    if !amm.is_valid_oracle_price(capped_oracle_update_price) {
+        return Ok(amm.last_oracle_price_twap);
+    }
+
+    calculate_new_oracle_price_twap(amm, now, capped_oracle_update_price)
+}
+
 pub fn calculate_new_oracle_price_twap(
     amm: &AMM,
     now: i64,
     oracle_price: i128,
 ) -> ClearingHouseResult<i128> {
+    validate_twap_window(amm.funding_period)?;
+
     let since_last = cast_to_i128(max(
         1,
         now.checked_sub(amm.last_oracle_price_twap_ts)
@@ -247,6 +419,61 @@ pub fn calculate_new_oracle_price_twap(
     Ok(new_twap)
 }
 
+/// The twaps are weighted over the funding period, so it must be positive and not so long that
+/// new prices are given a negligible weight
+pub fn validate_twap_window(twap_window: i64) -> ClearingHouseResult {
+    validate!(
+        twap_window > 0 && twap_window <= MAX_TWAP_WINDOW,
+        ErrorCode::InvalidTwapWindow,
+        "Twap window {} must be in (0, {}]",
+        twap_window,
+        MAX_TWAP_WINDOW
+    )
+}
+
+/// Open interest (quote_asset_amount_long + quote_asset_amount_short) weighted over window_seconds,
+/// blending the stored twap with the open interest held since it was last updated
+pub fn calculate_open_interest_twap(
+    amm: &AMM,
+    now: i64,
+    window_seconds: i64,
+) -> ClearingHouseResult<u128> {
+    validate_twap_window(window_seconds)?;
+
+    let open_interest = amm
+        .quote_asset_amount_long
+        .checked_add(amm.quote_asset_amount_short)
+        .ok_or_else(math_error!())?;
+
+    let since_last = cast_to_i128(max(
+        1,
+        now.checked_sub(amm.last_open_interest_twap_ts)
+            .ok_or_else(math_error!())?,
+    ))?;
+    let from_start = max(
+        1,
+        cast_to_i128(window_seconds)?
+            .checked_sub(since_last)
+            .ok_or_else(math_error!())?,
+    );
+
+    cast(calculate_weighted_average(
+        cast(open_interest)?,
+        cast(amm.last_open_interest_twap)?,
+        since_last,
+        from_start,
+    )?)
+}
+
+pub fn update_open_interest_twap(amm: &mut AMM, now: i64) -> ClearingHouseResult<u128> {
+    let open_interest_twap = calculate_open_interest_twap(amm, now, amm.funding_period)?;
+
+    amm.last_open_interest_twap = open_interest_twap;
+    amm.last_open_interest_twap_ts = now;
+
+    Ok(open_interest_twap)
+}
+
 pub fn calculate_weighted_average(
     data1: i128,
     data2: i128,
@@ -736,39 +963,81 @@ pub fn is_oracle_valid(
 ) -> ClearingHouseResult<bool> {
     let OraclePriceData {
         price: oracle_price,
-        confidence: oracle_conf,
         delay: oracle_delay,
         has_sufficient_number_of_data_points,
         ..
     } = *oracle_price_data;
 
-    let is_oracle_price_nonpositive = oracle_price <= 0;
+    let is_oracle_price_invalid = !amm.is_valid_oracle_price(oracle_price);
+
+    // compare magnitudes so markets allowing negative prices are checked the same way
+    let oracle_price_magnitude = cast_to_i128(oracle_price.unsigned_abs())?;
+    let oracle_price_twap_magnitude = cast_to_i128(amm.last_oracle_price_twap.unsigned_abs())?;
 
-    let is_oracle_price_too_volatile = ((oracle_price
-        .checked_div(max(1, amm.last_oracle_price_twap))
+    let is_oracle_price_too_volatile = ((oracle_price_magnitude
+        .checked_div(max(1, oracle_price_twap_magnitude))
         .ok_or_else(math_error!())?)
     .gt(&valid_oracle_guard_rails.too_volatile_ratio))
-        || ((amm
-            .last_oracle_price_twap
-            .checked_div(max(1, oracle_price))
+        || ((oracle_price_twap_magnitude
+            .checked_div(max(1, oracle_price_magnitude))
             .ok_or_else(math_error!())?)
         .gt(&valid_oracle_guard_rails.too_volatile_ratio));
 
-    let conf_denom_of_price = cast_to_u128(oracle_price)?
-        .checked_div(max(1, oracle_conf))
-        .ok_or_else(math_error!())?;
-    let is_conf_too_large =
-        conf_denom_of_price.lt(&valid_oracle_guard_rails.confidence_interval_max_size);
+    // confidence_interval_max_size is the smallest allowed price / confidence, zero disables the check
+    // a zero price is already invalid and has no confidence_bps
+    let is_conf_too_large = oracle_price != 0
+        && valid_oracle_guard_rails.confidence_interval_max_size != 0
+        && oracle_price_data.confidence_bps()?
+            > BPS_PRECISION
+                .checked_div(valid_oracle_guard_rails.confidence_interval_max_size)
+                .ok_or_else(math_error!())?;
 
     let is_stale = oracle_delay.gt(&valid_oracle_guard_rails.slots_before_stale);
 
     Ok(!(is_stale
         || !has_sufficient_number_of_data_points
-        || is_oracle_price_nonpositive
+        || is_oracle_price_invalid
         || is_oracle_price_too_volatile
         || is_conf_too_large))
 }
 
+/// Base of market B that offsets one base of market A in dollar terms (in MARK_PRICE_PRECISION)
+pub fn hedge_ratio(price_a: i128, price_b: i128) -> ClearingHouseResult<i128> {
+    validate!(
+        price_b != 0,
+        ErrorCode::MathError,
+        "Can not calculate hedge ratio against a price of zero"
+    )?;
+
+    price_a
+        .checked_mul(MARK_PRICE_PRECISION_I128)
+        .ok_or_else(math_error!())?
+        .checked_div(price_b)
+        .ok_or_else(math_error!())
+}
+
+/// Values an amount of collateral at the lower end of the oracle's confidence interval (price - confidence)
+/// so that deposits are never valued above what the oracle can vouch for
+pub fn haircut_collateral_value(
+    amount: u128,
+    oracle_price_data: &OraclePriceData,
+) -> ClearingHouseResult<u128> {
+    let haircut_price = oracle_price_data
+        .price
+        .checked_sub(cast_to_i128(oracle_price_data.confidence)?)
+        .ok_or_else(math_error!())?;
+
+    if haircut_price <= 0 {
+        return Ok(0);
+    }
+
+    amount
+        .checked_mul(haircut_price.unsigned_abs())
+        .ok_or_else(math_error!())?
+        .checked_div(MARK_PRICE_PRECISION)
+        .ok_or_else(math_error!())
+}
+
 pub fn calculate_budgeted_k_scale(
     market: &mut Market,
     budget: i128,
@@ -920,7 +1189,7 @@ pub fn get_update_k_result(
         return Err(ErrorCode::InvalidUpdateK);
     }
 
-    let sqrt_k = new_sqrt_k.try_to_u128().unwrap();
+    let sqrt_k = new_sqrt_k.try_to_u128()?;
     let base_asset_reserve = bn::U192::from(market.amm.base_asset_reserve)
         .checked_mul(sqrt_k_ratio)
         .ok_or_else(math_error!())?
@@ -4,12 +4,15 @@ pub const MARK_PRICE_PRECISION: u128 = 10_000_000_000; //expo = -10
 pub const MARK_PRICE_PRECISION_I128: i128 = 10_000_000_000; //expo = -10
 pub const QUOTE_PRECISION: u128 = 1_000_000; // expo = -6
 pub const FUNDING_PAYMENT_PRECISION: u128 = 10_000; // expo = -4
+pub const FUNDING_RATE_PRECISION: u128 = MARK_PRICE_PRECISION * FUNDING_PAYMENT_PRECISION; // expo = -14
 pub const MARGIN_PRECISION: u128 = 10_000; // expo = -4
 pub const PEG_PRECISION: u128 = 1_000; //expo = -3
                                        // pub const PRICE_SPREAD_PRECISION: i128 = 10_000; // expo = -4
                                        // pub const PRICE_SPREAD_PRECISION_U128: u128 = 10_000; // expo = -4
 pub const BID_ASK_SPREAD_PRECISION: u128 = 1_000_000; // expo = -6
 pub const BID_ASK_SPREAD_PRECISION_I128: i128 = (BID_ASK_SPREAD_PRECISION) as i128;
+pub const BPS_PRECISION: u128 = 10_000; // expo = -4
+pub const BPS_PRECISION_I128: i128 = (BPS_PRECISION) as i128;
 
 pub const AMM_RESERVE_PRECISION_I128: i128 = (AMM_RESERVE_PRECISION) as i128;
 // pub const MARK_PRICE_PRECISION_I128: i128 = 10_000_000_000; //expo = -10
@@ -19,7 +22,9 @@ pub const BANK_UTILIZATION_PRECISION: u128 = 1_000_000; // expo = -6
 pub const BANK_WEIGHT_PRECISION: u128 = 100; // expo = -2
 
 // PRECISION CONVERSIONS
+// calculate_price multiplies by this ratio, it must be exact for the mark price not to truncate
 pub const PRICE_TO_PEG_PRECISION_RATIO: u128 = MARK_PRICE_PRECISION / PEG_PRECISION; // expo: 7
+const _: () = assert!(MARK_PRICE_PRECISION % PEG_PRECISION == 0);
 pub const PRICE_TO_PEG_QUOTE_PRECISION_RATIO: u128 = MARK_PRICE_PRECISION / QUOTE_PRECISION; // expo: 4
 pub const AMM_TO_QUOTE_PRECISION_RATIO: u128 = AMM_RESERVE_PRECISION / QUOTE_PRECISION; // expo: 7
 pub const AMM_TO_QUOTE_PRECISION_RATIO_I128: i128 =
@@ -54,6 +59,23 @@ pub const ONE_HOUR: i128 = 3600;
 pub const ONE_HOUR_I128: i128 = ONE_HOUR as i128;
 pub const ONE_YEAR: u128 = 31536000;
 pub const TWENTYFOUR_HOUR: i64 = 3600 * 24;
+pub const TWENTYFOUR_HOUR_I128: i128 = TWENTYFOUR_HOUR as i128;
+// period_adjustment divides a day by the funding period (floored at an hour), keep the periods consistent and positive
+const _: () = assert!(ONE_HOUR > 0 && ONE_HOUR.checked_mul(24).is_some());
+const _: () = assert!(TWENTYFOUR_HOUR_I128 == 24 * ONE_HOUR);
+pub const MIN_FUNDING_PERIOD: i64 = 60;
+pub const MAX_TWAP_WINDOW: i64 = TWENTYFOUR_HOUR;
+
+// ORACLES
+// switchboard rounds opened more than this many slots ago are treated as not having sufficient data points
+pub const SWITCHBOARD_MAX_ROUND_AGE_SLOTS: i64 = 1000;
+// switchboard rounds with a std deviation wider than this share of the price are treated as unusable,
+// unless the market sets its own switchboard_max_confidence_bps (10%)
+pub const DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS: u128 = 1000;
+// number of funding updates after a switchboard outage during which the confidence floor stays raised
+pub const ORACLE_OUTAGE_RECOVERY_UPDATES: u8 = 5;
+// bps the confidence floor is raised by per remaining recovery update
+pub const ORACLE_OUTAGE_RECOVERY_CONFIDENCE_STEP_BPS: u128 = 10;
 
 // FEES
 pub const DEFAULT_FEE_NUMERATOR: u128 = 10;
@@ -81,6 +103,9 @@ pub const MAX_LIQUIDATION_SLIPPAGE_U128: u128 = 10_000; // expo = -2
 pub const MAX_MARK_TWAP_DIVERGENCE: u128 = 500_000; // expo = -3
 pub const MAXIMUM_MARGIN_RATIO: u32 = MARGIN_PRECISION as u32;
 pub const MINIMUM_MARGIN_RATIO: u32 = MARGIN_PRECISION as u32 / 50;
+pub const MAX_FUNDING_RATE_BATCH_SIZE: usize = 4;
+// funding price spread is clamped to oracle twap / divisor, 33 is the legacy ~3% clamp
+pub const DEFAULT_FUNDING_CLAMP_DIVISOR: u64 = 33;
 
 // FORMULAIC REPEG / K
 pub const K_BPS_UPDATE_SCALE: i128 = 1_000_000; // expo = -6 (represents 100%)
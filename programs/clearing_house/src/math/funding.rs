@@ -1,16 +1,20 @@
 use crate::error::{ClearingHouseResult, ErrorCode};
 use crate::math::bn;
-use crate::math::casting::cast_to_i128;
+use crate::math::casting::{cast_to_i128, cast_to_u128};
 use crate::math::constants::{
-    AMM_TO_QUOTE_PRECISION_RATIO, FUNDING_PAYMENT_PRECISION, MARK_PRICE_PRECISION,
-    QUOTE_TO_BASE_AMT_FUNDING_PRECISION, SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR,
-    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR,
+    AMM_TO_QUOTE_PRECISION_RATIO, AMM_TO_QUOTE_PRECISION_RATIO_I128, BPS_PRECISION,
+    BPS_PRECISION_I128, DEFAULT_FUNDING_CLAMP_DIVISOR, FUNDING_PAYMENT_PRECISION,
+    MARK_PRICE_PRECISION, MIN_FUNDING_PERIOD, ONE_HOUR, QUOTE_TO_BASE_AMT_FUNDING_PRECISION,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR, TWENTYFOUR_HOUR_I128,
 };
 use crate::math_error;
-use crate::state::market::Market;
-use crate::state::user::MarketPosition;
+use crate::state::market::{Market, AMM};
+use crate::state::market_map::MarketMap;
+use crate::state::user::{MarketPosition, User};
+use crate::validate;
 use solana_program::msg;
-use std::cmp::max;
+use std::cmp::{max, min};
 
 /// With a virtual AMM, there can be an imbalance between longs and shorts and thus funding can be asymmetric.
 /// To account for this, amm keeps track of the cumulative funding rate for both longs and shorts.
@@ -153,6 +157,15 @@ fn calculate_capped_funding_rate(
     Ok((capped_funding_rate, capped_funding_pnl))
 }
 
+/// Funding payment owed to a position since it last settled, from the user's perspective (in AMM_RESERVE_PRECISION)
+/// A negative payment means the user pays (reduces unsettled_pnl), a positive payment means the user receives.
+///
+/// | position | cumulative funding rate delta | payment  |
+/// |----------|-------------------------------|----------|
+/// | long     | positive (mark above oracle)  | negative |
+/// | long     | negative (mark below oracle)  | positive |
+/// | short    | positive (mark above oracle)  | positive |
+/// | short    | negative (mark below oracle)  | negative |
 pub fn calculate_funding_payment(
     amm_cumulative_funding_rate: i128,
     market_position: &MarketPosition,
@@ -167,6 +180,20 @@ pub fn calculate_funding_payment(
     Ok(funding_rate_payment)
 }
 
+/// Funding owed by a position of base_asset_amount between two cumulative funding rates (in QUOTE_PRECISION)
+/// Generalizes calculate_funding_payment to an arbitrary baseline rather than the position's last_cumulative_funding_rate
+pub fn funding_since(
+    baseline_cumulative_funding_rate: i128,
+    current_cumulative_funding_rate: i128,
+    base_asset_amount: i128,
+) -> ClearingHouseResult<i128> {
+    let funding_rate_delta = current_cumulative_funding_rate
+        .checked_sub(baseline_cumulative_funding_rate)
+        .ok_or_else(math_error!())?;
+
+    calculate_funding_payment_in_quote_precision(funding_rate_delta, base_asset_amount)
+}
+
 fn _calculate_funding_payment(
     funding_rate_delta: i128,
     base_asset_amount: i128,
@@ -184,7 +211,8 @@ fn _calculate_funding_payment(
             .try_to_u128()?,
     )?;
 
-    // funding_rate: longs pay shorts
+    // positive funding_rate: longs pay shorts
+    // negative funding_rate: shorts pay longs
     let funding_rate_payment_sign: i128 = if base_asset_amount > 0 { -1 } else { 1 };
 
     let funding_rate_payment = (funding_rate_payment_magnitude)
@@ -217,7 +245,7 @@ fn calculate_funding_rate_from_pnl_limit(
         .ok_or_else(math_error!())
 }
 
-fn calculate_funding_payment_in_quote_precision(
+pub fn calculate_funding_payment_in_quote_precision(
     funding_rate_delta: i128,
     base_asset_amount: i128,
 ) -> ClearingHouseResult<i128> {
@@ -228,3 +256,302 @@ fn calculate_funding_payment_in_quote_precision(
 
     Ok(funding_payment_collateral)
 }
+
+/// Funding (in QUOTE_PRECISION) settle_funding_payment would add to the user's unsettled pnl, without modifying the user
+pub fn calculate_unsettled_funding(
+    user: &User,
+    market_map: &MarketMap,
+) -> ClearingHouseResult<i128> {
+    let mut total_funding_payment: i128 = 0;
+    for market_position in user.positions.iter() {
+        if market_position.base_asset_amount == 0 {
+            continue;
+        }
+
+        let market = &market_map.get_ref(&market_position.market_index)?;
+
+        if let Some(position_funding_payment) =
+            calculate_position_funding_payment(market_position, &market.amm)?
+        {
+            total_funding_payment = total_funding_payment
+                .checked_add(position_funding_payment.funding_payment)
+                .ok_or_else(math_error!())?;
+        }
+    }
+
+    Ok(total_funding_payment)
+}
+
+pub struct PositionFundingPayment {
+    pub amm_cumulative_funding_rate: i128,
+    // QUOTE_PRECISION, negative when the user pays
+    pub funding_payment: i128,
+    // QUOTE_PRECISION, funding over the position's max_funding_per_period cap that isn't collected
+    pub uncollected_funding: u128,
+    // QUOTE_PRECISION * AMM_TO_QUOTE_PRECISION_RATIO, truncated from funding_payment and accumulated on the amm
+    pub funding_payment_dust: i128,
+}
+
+/// Funding owed to the position since its last settlement, or None if there is nothing to settle
+pub fn calculate_position_funding_payment(
+    market_position: &MarketPosition,
+    amm: &AMM,
+) -> ClearingHouseResult<Option<PositionFundingPayment>> {
+    validate!(
+        amm.is_initialized(),
+        ErrorCode::MarketIndexNotInitialized,
+        "Can not settle funding against an uninitialized amm"
+    )?;
+
+    let amm_cumulative_funding_rate = if market_position.base_asset_amount > 0 {
+        amm.cumulative_funding_rate_long
+    } else {
+        amm.cumulative_funding_rate_short
+    };
+
+    if amm_cumulative_funding_rate == market_position.last_cumulative_funding_rate {
+        return Ok(None);
+    }
+
+    let market_funding_payment = funding_since(
+        market_position.last_cumulative_funding_rate,
+        amm_cumulative_funding_rate,
+        market_position.base_asset_amount,
+    )?;
+    // what funding_since truncated to QUOTE_PRECISION
+    let funding_payment_dust =
+        calculate_funding_payment(amm_cumulative_funding_rate, market_position)?
+            .checked_rem(AMM_TO_QUOTE_PRECISION_RATIO_I128)
+            .ok_or_else(math_error!())?;
+
+    let mut position_funding_payment = PositionFundingPayment {
+        amm_cumulative_funding_rate,
+        funding_payment: market_funding_payment,
+        uncollected_funding: 0,
+        funding_payment_dust,
+    };
+
+    // funding payments are from the user's perspective (negative means the user paid)
+    let max_funding_per_period = market_position.max_funding_per_period;
+    if max_funding_per_period == 0 || market_funding_payment >= 0 {
+        return Ok(Some(position_funding_payment));
+    }
+
+    // the cap scales with the funding periods since the position last settled
+    let periods_since_last_settle = max(
+        1,
+        amm.last_funding_rate_ts
+            .checked_sub(market_position.last_funding_rate_ts)
+            .ok_or_else(math_error!())?
+            .checked_div(max(1, amm.funding_period))
+            .ok_or_else(math_error!())?,
+    );
+    let max_funding_payment = max_funding_per_period
+        .checked_mul(cast_to_u128(periods_since_last_settle)?)
+        .ok_or_else(math_error!())?;
+
+    let funding_paid = market_funding_payment.unsigned_abs();
+    if funding_paid > max_funding_payment {
+        position_funding_payment.funding_payment = -cast_to_i128(max_funding_payment)?;
+        position_funding_payment.uncollected_funding = funding_paid
+            .checked_sub(max_funding_payment)
+            .ok_or_else(math_error!())?;
+        // the truncated remainder is part of the funding over the cap, which isn't collected either
+        position_funding_payment.funding_payment_dust = 0;
+    }
+
+    Ok(Some(position_funding_payment))
+}
+
+/// Chains the previous checksum with the funding rate and record id (FNV-1a over their little endian bytes)
+/// so indexers can recompute it and detect dropped funding rate records
+pub fn calculate_funding_rate_checksum(
+    previous_checksum: u64,
+    funding_rate: i128,
+    record_id: u64,
+) -> u64 {
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    funding_rate
+        .to_le_bytes()
+        .iter()
+        .chain(record_id.to_le_bytes().iter())
+        .fold(previous_checksum, |checksum, byte| {
+            (checksum ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+        })
+}
+
+/// The number of funding periods per day (funding periods shorter than an hour are treated as an hour)
+/// The price spread is divided by this so that the funding rate for each period is a share of the daily rate
+/// The hour floor is intended: a sub-hour period pays the hourly share rather than a per-second one, so a
+/// funding_period of 1 gives 24, not 86400. Such periods are rejected by validate_funding_period anyway
+pub fn calculate_period_adjustment(funding_period: i64) -> ClearingHouseResult<i128> {
+    TWENTYFOUR_HOUR_I128
+        .checked_div(max(ONE_HOUR, funding_period as i128))
+        .ok_or_else(math_error!())
+}
+
+/// Funding rate (in FUNDING_RATE_PRECISION) for a period given a mark/oracle twap price spread (in MARK_PRICE_PRECISION)
+/// Applies no clamp, calculate_funding_rate clamps the spread first
+pub fn calculate_funding_rate_from_price_spread(
+    price_spread: i128,
+    funding_period: i64,
+) -> ClearingHouseResult<i128> {
+    // funding period = 1 hour, window = 1 day
+    // low periodicity => quickly updating/settled funding rates => lower funding rate payment per interval
+    let period_adjustment = calculate_period_adjustment(funding_period)?;
+
+    price_spread
+        .checked_mul(cast_to_i128(FUNDING_PAYMENT_PRECISION)?)
+        .ok_or_else(math_error!())?
+        .checked_div(period_adjustment)
+        .ok_or_else(math_error!())
+}
+
+/// Funding rate (in FUNDING_RATE_PRECISION) for a period given the mark and oracle twaps (in MARK_PRICE_PRECISION)
+/// The price spread is clamped to oracle_twap / clamp_divisor (zero uses the legacy ~3% clamp) before being
+/// scaled down to the funding period. update_funding_rate prices funding with it (before any max_funding_rate ceiling),
+/// and it is pure so keepers can predict the next rate before cranking update_funding_rate
+pub fn calculate_funding_rate(
+    mark_twap: u128,
+    oracle_twap: i128,
+    funding_period: i64,
+    clamp_divisor: u64,
+) -> ClearingHouseResult<i128> {
+    let (_, clamped_price_spread) =
+        calculate_funding_price_spread(mark_twap, oracle_twap, clamp_divisor)?;
+
+    calculate_funding_rate_from_price_spread(clamped_price_spread, funding_period)
+}
+
+/// Mark/oracle twap spread (in MARK_PRICE_PRECISION) driving the funding rate, as (raw, clamped)
+/// The clamped spread is limited to oracle_twap / clamp_divisor (zero uses the legacy ~3% clamp)
+pub fn calculate_funding_price_spread(
+    mark_twap: u128,
+    oracle_twap: i128,
+    clamp_divisor: u64,
+) -> ClearingHouseResult<(i128, i128)> {
+    let price_spread = cast_to_i128(mark_twap)?
+        .checked_sub(oracle_twap)
+        .ok_or_else(math_error!())?;
+
+    let clamped_price_spread =
+        clamp_funding_price_spread(price_spread, oracle_twap, clamp_divisor)?;
+
+    Ok((price_spread, clamped_price_spread))
+}
+
+/// Limits a mark/oracle twap price spread (in MARK_PRICE_PRECISION) to the funding spread clamp around oracle_twap
+pub fn clamp_funding_price_spread(
+    price_spread: i128,
+    oracle_twap: i128,
+    clamp_divisor: u64,
+) -> ClearingHouseResult<i128> {
+    let max_price_spread = calculate_max_funding_price_spread(oracle_twap, clamp_divisor)?;
+
+    Ok(max(-max_price_spread, min(price_spread, max_price_spread)))
+}
+
+/// Largest price spread (in MARK_PRICE_PRECISION) the funding rate is computed from: oracle_twap / clamp_divisor,
+/// where a clamp_divisor of zero uses the legacy ~3% clamp
+pub fn calculate_max_funding_price_spread(
+    oracle_twap: i128,
+    clamp_divisor: u64,
+) -> ClearingHouseResult<i128> {
+    let clamp_divisor = if clamp_divisor == 0 {
+        DEFAULT_FUNDING_CLAMP_DIVISOR
+    } else {
+        clamp_divisor
+    };
+
+    // the clamp is symmetric around the oracle twap, which can be negative for spread markets
+    oracle_twap
+        .checked_div(cast_to_i128(clamp_divisor)?)
+        .ok_or_else(math_error!())?
+        .checked_abs()
+        .ok_or_else(math_error!())
+}
+
+/// Smoothed funding rate for display: alpha (in BPS_PRECISION) of the new funding rate plus the rest of the
+/// previous ema. An alpha of zero (the default) or BPS_PRECISION tracks the raw rate
+pub fn calculate_funding_rate_ema(
+    funding_rate_ema: i128,
+    funding_rate: i128,
+    alpha: u128,
+) -> ClearingHouseResult<i128> {
+    if alpha == 0 || alpha >= BPS_PRECISION {
+        return Ok(funding_rate);
+    }
+
+    let alpha = cast_to_i128(alpha)?;
+
+    funding_rate
+        .checked_mul(alpha)
+        .ok_or_else(math_error!())?
+        .checked_add(
+            funding_rate_ema
+                .checked_mul(
+                    BPS_PRECISION_I128
+                        .checked_sub(alpha)
+                        .ok_or_else(math_error!())?,
+                )
+                .ok_or_else(math_error!())?,
+        )
+        .ok_or_else(math_error!())?
+        .checked_div(BPS_PRECISION_I128)
+        .ok_or_else(math_error!())
+}
+
+pub fn validate_funding_period(funding_period: i64) -> ClearingHouseResult {
+    validate!(
+        funding_period >= MIN_FUNDING_PERIOD,
+        ErrorCode::InvalidFundingPeriod,
+        "Funding period {} must be at least {} seconds",
+        funding_period,
+        MIN_FUNDING_PERIOD
+    )
+}
+
+/// Earliest timestamp at which update_funding_rate will accept the next funding update
+pub fn calculate_next_funding_time(amm: &AMM) -> ClearingHouseResult<i64> {
+    // round next update time to be available on the hour
+    // a funding_period of 1 (or less) has no boundary to round to, though callers reject periods under MIN_FUNDING_PERIOD
+    let mut next_update_wait = amm.funding_period;
+    if amm.funding_period > 1 {
+        let last_update_delay = amm.last_funding_rate_ts.rem_euclid(amm.funding_period);
+        if last_update_delay != 0 {
+            let max_delay_for_next_period = amm
+                .funding_period
+                .checked_div(3)
+                .ok_or_else(math_error!())?;
+
+            let two_funding_periods = amm
+                .funding_period
+                .checked_mul(2)
+                .ok_or_else(math_error!())?;
+
+            if last_update_delay > max_delay_for_next_period {
+                // too late for on the hour next period, delay to following period
+                next_update_wait = two_funding_periods
+                    .checked_sub(last_update_delay)
+                    .ok_or_else(math_error!())?;
+            } else {
+                // allow update on the hour
+                next_update_wait = amm
+                    .funding_period
+                    .checked_sub(last_update_delay)
+                    .ok_or_else(math_error!())?;
+            }
+
+            if next_update_wait > two_funding_periods {
+                next_update_wait = next_update_wait
+                    .checked_sub(amm.funding_period)
+                    .ok_or_else(math_error!())?;
+            }
+        }
+    }
+
+    amm.last_funding_rate_ts
+        .checked_add(next_update_wait)
+        .ok_or_else(math_error!())
+}
@@ -3,9 +3,10 @@ use solana_program::clock::Slot;
 
 use crate::error::ClearingHouseResult;
 use crate::math::amm;
+use crate::math::constants::ORACLE_OUTAGE_RECOVERY_UPDATES;
 use crate::state::market::AMM;
-use crate::state::oracle::OraclePriceData;
-use crate::state::state::OracleGuardRails;
+use crate::state::oracle::{OraclePriceData, OracleSource};
+use crate::state::state::{FundingBlockGuardRails, OracleGuardRails};
 
 pub fn block_operation(
     amm: &AMM,
@@ -31,6 +32,63 @@ pub fn block_operation(
     Ok((block, oracle_price_data))
 }
 
+/// Like block_operation, but with hysteresis on the mark/oracle divergence for funding rate updates.
+/// Updates amm.funding_blocked with whether the divergence is blocking funding and advances the oracle
+/// outage recovery, so it should only be called for a funding update that is due
+pub fn block_funding_rate_update(
+    amm: &mut AMM,
+    oracle_account_info: &AccountInfo,
+    clock_slot: Slot,
+    guard_rails: &OracleGuardRails,
+    funding_block_guard_rails: &FundingBlockGuardRails,
+    precomputed_mark_price: Option<u128>,
+) -> ClearingHouseResult<(bool, OraclePriceData)> {
+    let OracleStatus {
+        price_data: oracle_price_data,
+        is_valid: oracle_is_valid,
+        mark_too_divergent: is_oracle_mark_too_divergent,
+        oracle_mark_spread_pct,
+    } = get_oracle_status(
+        amm,
+        oracle_account_info,
+        clock_slot,
+        guard_rails,
+        precomputed_mark_price,
+    )?;
+
+    let FundingBlockGuardRails {
+        block_divergence_pct,
+        release_divergence_pct,
+    } = *funding_block_guard_rails;
+
+    let funding_blocked = if block_divergence_pct == 0 || release_divergence_pct == 0 {
+        is_oracle_mark_too_divergent
+    } else if amm.funding_blocked {
+        oracle_mark_spread_pct.unsigned_abs() > release_divergence_pct
+    } else {
+        oracle_mark_spread_pct.unsigned_abs() > block_divergence_pct
+    };
+    amm.funding_blocked = funding_blocked;
+
+    update_oracle_outage_recovery(amm, &oracle_price_data);
+
+    let block = !oracle_is_valid || funding_blocked;
+    Ok((block, oracle_price_data))
+}
+
+/// Restarts the switchboard recovery count while the oracle lacks sufficient data points and counts it down after
+pub fn update_oracle_outage_recovery(amm: &mut AMM, oracle_price_data: &OraclePriceData) {
+    if amm.oracle_source != OracleSource::Switchboard {
+        return;
+    }
+
+    if !oracle_price_data.has_sufficient_number_of_data_points {
+        amm.oracle_outage_recovery_count = ORACLE_OUTAGE_RECOVERY_UPDATES;
+    } else {
+        amm.oracle_outage_recovery_count = amm.oracle_outage_recovery_count.saturating_sub(1);
+    }
+}
+
 #[derive(Default, Clone, Copy, Debug)]
 pub struct OracleStatus {
     pub price_data: OraclePriceData,
@@ -88,11 +88,7 @@ pub fn calculate_repeg_validity(
 
     // if oracle is valid: check on size/direction of repeg
     if oracle_is_valid {
-        let mark_price_after = amm::calculate_price(
-            market.amm.quote_asset_reserve,
-            market.amm.base_asset_reserve,
-            market.amm.peg_multiplier,
-        )?;
+        let mark_price_after = market.amm.mark_price()?;
 
         let oracle_conf_band_top = oracle_price_u128
             .checked_add(oracle_conf)
@@ -2,7 +2,8 @@ use crate::error::ClearingHouseResult;
 // use crate::error::{math_error};
 use crate::math::casting::cast_to_i128;
 use crate::math::constants::{
-    BID_ASK_SPREAD_PRECISION_I128, MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO,
+    BID_ASK_SPREAD_PRECISION_I128, BPS_PRECISION_I128,
+    MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO,
 };
 use crate::math_error;
 use solana_program::msg;
@@ -23,6 +24,18 @@ pub fn calculate_slippage(
         .ok_or_else(math_error!())
 }
 
+/// Change from price_before to price_after (both in MARK_PRICE_PRECISION) in BPS_PRECISION
+/// e.g. with price_after from amm::calculate_price_after_trade and price_before the current mark price
+pub fn calculate_slippage_bps(price_after: u128, price_before: u128) -> ClearingHouseResult<i128> {
+    cast_to_i128(price_after)?
+        .checked_sub(cast_to_i128(price_before)?)
+        .ok_or_else(math_error!())?
+        .checked_mul(BPS_PRECISION_I128)
+        .ok_or_else(math_error!())?
+        .checked_div(cast_to_i128(price_before)?)
+        .ok_or_else(math_error!())
+}
+
 pub fn calculate_slippage_pct(
     slippage: i128,
     mark_price_before: i128,
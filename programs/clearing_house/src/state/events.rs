@@ -2,6 +2,7 @@ use anchor_lang::prelude::*;
 use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::controller::position::PositionDirection;
+use crate::controller::view::ViewParams;
 use crate::state::user::Order;
 
 #[event]
@@ -55,17 +56,69 @@ pub struct FundingPaymentRecord {
     pub user_authority: Pubkey,
     pub user: Pubkey,
     pub market_index: u64,
-    pub funding_payment: i128,
-    pub base_asset_amount: i128,
-    pub user_last_cumulative_funding: i128,
+    pub funding_payment: i128, // QUOTE_PRECISION, negative when the user pays, positive when the user receives
+    pub base_asset_amount: i128, // AMM_RESERVE_PRECISION
+    pub user_last_cumulative_funding: i128, // FUNDING_RATE_PRECISION
     pub user_last_funding_rate_ts: i64,
-    pub amm_cumulative_funding_long: i128,
-    pub amm_cumulative_funding_short: i128,
+    pub amm_cumulative_funding_long: i128, // FUNDING_RATE_PRECISION
+    pub amm_cumulative_funding_short: i128, // FUNDING_RATE_PRECISION
+    pub funding_payment_dust: i128, // QUOTE_PRECISION * AMM_TO_QUOTE_PRECISION_RATIO, added to the amm's funding_payment_dust
 }
 
 #[event]
 pub struct FundingRateRecord {
     pub ts: i64,
+    pub record_id: u64,
+    pub market_index: u64,
+    pub funding_rate: i128,                  // FUNDING_RATE_PRECISION
+    pub cumulative_funding_rate_long: i128,  // FUNDING_RATE_PRECISION
+    pub cumulative_funding_rate_short: i128, // FUNDING_RATE_PRECISION
+    pub oracle_price_twap: i128,             // MARK_PRICE_PRECISION
+    pub mark_price_twap: u128,               // MARK_PRICE_PRECISION
+    pub oracle_volatility_bps: u128,         // BPS_PRECISION
+    pub checksum: u64,
+    pub raw_price_spread: i128, // MARK_PRICE_PRECISION, mark twap - oracle twap
+    pub clamped_price_spread: i128, // MARK_PRICE_PRECISION, after the funding spread clamp
+}
+
+#[event]
+pub struct FundingUpdateSkippedRecord {
+    pub ts: i64,
+    pub market_index: u64,
+    pub reason: FundingUpdateSkipReason,
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq)]
+pub enum FundingUpdateSkipReason {
+    OracleInvalid,
+    FundingPaused,
+    OraclePriceZero,
+    TooSoon,
+}
+
+impl Default for FundingUpdateSkipReason {
+    fn default() -> Self {
+        FundingUpdateSkipReason::OracleInvalid
+    }
+}
+
+#[event]
+pub struct FundingBatchRecord {
+    pub ts: i64,
+    pub funding_rates: Vec<FundingRateSummary>,
+}
+
+#[event]
+pub struct FundingSettlementRecord {
+    pub ts: i64,
+    pub number_of_users: u64,
+    pub total_funding_payment: i128, // QUOTE_PRECISION, summed over the users' FundingPaymentRecords
+    pub positions_iterated: u64,     // open positions looked at, for keepers tuning batch sizes
+    pub positions_settled: u64,      // open positions that had funding to settle
+}
+
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Default)]
+pub struct FundingRateSummary {
     pub record_id: u64,
     pub market_index: u64,
     pub funding_rate: i128,
@@ -147,3 +200,10 @@ impl Default for OrderAction {
         OrderAction::Place
     }
 }
+
+#[event]
+pub struct ViewRecord {
+    pub ts: i64,
+    pub params: ViewParams,
+    pub result: Vec<i128>,
+}
@@ -1,18 +1,31 @@
-use std::cmp::max;
-
 use anchor_lang::prelude::*;
 use solana_program::msg;
-use switchboard_v2::decimal::SwitchboardDecimal;
-use switchboard_v2::AggregatorAccountData;
+use std::cmp::{max, min};
 
+use crate::controller::amm::SwapDirection;
 use crate::error::{ClearingHouseResult, ErrorCode};
 use crate::math::amm;
-use crate::math::casting::{cast, cast_to_i128, cast_to_i64, cast_to_u128};
+use crate::math::bn;
+use crate::math::casting::{cast_to_i128, cast_to_u128};
+use crate::math::constants::{
+    AMM_RESERVE_PRECISION_I128, BPS_PRECISION, BPS_PRECISION_I128,
+    DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS, FUNDING_PAYMENT_PRECISION, MARGIN_PRECISION,
+    MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO, ONE_YEAR,
+    ORACLE_OUTAGE_RECOVERY_CONFIDENCE_STEP_BPS, TWENTYFOUR_HOUR,
+};
+use crate::math::funding::{
+    calculate_funding_payment_in_quote_precision, calculate_funding_rate_from_price_spread,
+    calculate_funding_rate_long_short, calculate_max_funding_price_spread,
+    calculate_period_adjustment, clamp_funding_price_spread,
+};
 use crate::math::margin::MarginRequirementType;
+use crate::math::quote_asset::reserve_to_asset_amount;
 use crate::math_error;
 use crate::state::bank::{BankBalance, BankBalanceType};
+use crate::state::oracle;
 use crate::state::oracle::{OraclePriceData, OracleSource};
-use crate::{BID_ASK_SPREAD_PRECISION, MARK_PRICE_PRECISION};
+use crate::validate;
+use crate::{BID_ASK_SPREAD_PRECISION, BID_ASK_SPREAD_PRECISION_I128};
 
 #[account(zero_copy)]
 #[derive(Default)]
@@ -49,6 +62,314 @@ impl Market {
             MarginRequirementType::Maintenance => self.margin_ratio_maintenance,
         }
     }
+
+    /// Funding rate updates a keeper has to crank per day, zero if the funding period isn't set
+    pub fn expected_funding_calls_per_day(&self) -> u64 {
+        let funding_period = self.amm.funding_period;
+        if funding_period <= 0 {
+            return 0;
+        }
+
+        (TWENTYFOUR_HOUR / funding_period) as u64
+    }
+
+    /// Magnitude of the last funding rate (in FUNDING_RATE_PRECISION)
+    /// unsigned_abs maps i128::MIN to 2^127 instead of overflowing like abs
+    pub fn abs_funding_rate(&self) -> ClearingHouseResult<u128> {
+        Ok(self.amm.last_funding_rate.unsigned_abs())
+    }
+
+    /// Net funding that changed hands over the last funding period (in QUOTE_PRECISION).
+    /// Positive means longs paid more than shorts received, negative means shorts paid more than longs received.
+    pub fn period_funding_flow(&self) -> ClearingHouseResult<i128> {
+        let funding_rate = self.amm.last_funding_rate;
+
+        // funding payments are from the user's perspective (negative means the user paid)
+        let long_funding_payment = calculate_funding_payment_in_quote_precision(
+            funding_rate,
+            self.base_asset_amount_long,
+        )?;
+        let short_funding_payment = calculate_funding_payment_in_quote_precision(
+            funding_rate,
+            self.base_asset_amount_short,
+        )?;
+
+        long_funding_payment
+            .checked_add(short_funding_payment)
+            .ok_or_else(math_error!())?
+            .checked_neg()
+            .ok_or_else(math_error!())
+    }
+
+    /// Funding received by one unit of base (AMM_RESERVE_PRECISION) on the given side over the last period (in QUOTE_PRECISION)
+    /// Negative means the side paid funding
+    pub fn funding_per_base_unit(&self, long: bool) -> ClearingHouseResult<i128> {
+        let base_asset_amount = if long {
+            AMM_RESERVE_PRECISION_I128
+        } else {
+            -AMM_RESERVE_PRECISION_I128
+        };
+
+        calculate_funding_payment_in_quote_precision(self.amm.last_funding_rate, base_asset_amount)
+    }
+
+    /// Derivative of the funding rate with respect to the oracle twap at the current mark/oracle twaps
+    /// (in FUNDING_PAYMENT_PRECISION, i.e. FUNDING_RATE_PRECISION per MARK_PRICE_PRECISION of oracle price)
+    /// The funding rate falls as the oracle rises, so this is negative unless the spread clamp binds, where it is zero
+    pub fn funding_oracle_sensitivity(&self) -> ClearingHouseResult<i128> {
+        let oracle_price_twap = self.amm.last_oracle_price_twap;
+        let price_spread = cast_to_i128(self.amm.last_mark_price_twap)?
+            .checked_sub(oracle_price_twap)
+            .ok_or_else(math_error!())?;
+
+        let max_price_spread =
+            calculate_max_funding_price_spread(oracle_price_twap, self.amm.funding_clamp_divisor)?;
+
+        if price_spread.unsigned_abs() >= max_price_spread.unsigned_abs() {
+            return Ok(0);
+        }
+
+        let period_adjustment = calculate_period_adjustment(self.amm.funding_period)?;
+
+        cast_to_i128(FUNDING_PAYMENT_PRECISION)?
+            .checked_neg()
+            .ok_or_else(math_error!())?
+            .checked_div(period_adjustment)
+            .ok_or_else(math_error!())
+    }
+
+    /// Mark twap implied by the last funding rate and oracle twap (in MARK_PRICE_PRECISION)
+    /// Only matches the stored mark twap if the funding rate wasn't clamped
+    pub fn implied_mark_twap(&self) -> ClearingHouseResult<u128> {
+        let period_adjustment = calculate_period_adjustment(self.amm.funding_period)?;
+
+        let price_spread = self
+            .amm
+            .last_funding_rate
+            .checked_mul(period_adjustment)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(FUNDING_PAYMENT_PRECISION)?)
+            .ok_or_else(math_error!())?;
+
+        cast_to_u128(
+            self.amm
+                .last_oracle_price_twap
+                .checked_add(price_spread)
+                .ok_or_else(math_error!())?,
+        )
+    }
+
+    /// Mark twap range (in MARK_PRICE_PRECISION) around oracle_twap within which the funding rate scales linearly
+    /// with the spread, outside it the spread clamp in update_funding_rate binds
+    pub fn funding_clamp_price_bounds(
+        &self,
+        oracle_twap: i128,
+    ) -> ClearingHouseResult<(i128, i128)> {
+        let max_price_spread =
+            calculate_max_funding_price_spread(oracle_twap, self.amm.funding_clamp_divisor)?;
+
+        Ok((
+            oracle_twap
+                .checked_sub(max_price_spread)
+                .ok_or_else(math_error!())?,
+            oracle_twap
+                .checked_add(max_price_spread)
+                .ok_or_else(math_error!())?,
+        ))
+    }
+
+    /// Funding rate (in FUNDING_RATE_PRECISION) that update_funding_rate would set if the mark twap
+    /// were target_premium_bps over the oracle twap, including the spread clamp
+    pub fn funding_rate_for_target_premium_bps(
+        &self,
+        target_premium_bps: i128,
+    ) -> ClearingHouseResult<i128> {
+        let oracle_price_twap = self.amm.last_oracle_price_twap;
+
+        let price_spread = oracle_price_twap
+            .checked_mul(target_premium_bps)
+            .ok_or_else(math_error!())?
+            .checked_div(BPS_PRECISION_I128)
+            .ok_or_else(math_error!())?;
+
+        let clamped_price_spread = clamp_funding_price_spread(
+            price_spread,
+            oracle_price_twap,
+            self.amm.funding_clamp_divisor,
+        )?;
+
+        calculate_funding_rate_from_price_spread(clamped_price_spread, self.amm.funding_period)
+    }
+
+    /// Relative error (in BPS_PRECISION) of the funding accrued per second caused by keeper timing
+    /// Updates are meant to land on funding_period boundaries, each period's rate is fixed regardless of how long it
+    /// took, so an update landing late (or one that is overdue at now) stretches the period and under-accrues funding
+    pub fn funding_timing_error_bps(&self, now: i64) -> ClearingHouseResult<i128> {
+        let funding_period = self.amm.funding_period;
+        if funding_period <= 0 {
+            return Ok(0);
+        }
+
+        // how far after a period boundary the last update landed
+        let schedule_drift = self.amm.last_funding_rate_ts.rem_euclid(funding_period);
+
+        let overdue = max(
+            0,
+            now.checked_sub(self.amm.last_funding_rate_ts)
+                .ok_or_else(math_error!())?
+                .checked_sub(funding_period)
+                .ok_or_else(math_error!())?,
+        );
+
+        cast_to_i128(
+            schedule_drift
+                .checked_add(overdue)
+                .ok_or_else(math_error!())?,
+        )?
+        .checked_mul(BPS_PRECISION_I128)
+        .ok_or_else(math_error!())?
+        .checked_div(cast_to_i128(funding_period)?)
+        .ok_or_else(math_error!())
+    }
+
+    /// Daily funding paid by a long opened with `collateral` (in QUOTE_PRECISION) at `leverage` (in MARGIN_PRECISION)
+    /// at the last funding rate (in QUOTE_PRECISION). Negative means the long receives funding; a short pays the mirror
+    pub fn funding_cost_for_position(
+        &self,
+        collateral: u128,
+        leverage: u128,
+    ) -> ClearingHouseResult<i128> {
+        let notional = cast_to_i128(
+            collateral
+                .checked_mul(leverage)
+                .ok_or_else(math_error!())?
+                .checked_div(MARGIN_PRECISION)
+                .ok_or_else(math_error!())?,
+        )?;
+
+        let period_adjustment = calculate_period_adjustment(self.amm.funding_period)?;
+
+        // funding rate is per period and relative to the oracle twap
+        notional
+            .checked_mul(self.amm.last_funding_rate)
+            .ok_or_else(math_error!())?
+            .checked_mul(period_adjustment)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(FUNDING_PAYMENT_PRECISION)?)
+            .ok_or_else(math_error!())?
+            .checked_div(self.amm.last_oracle_price_twap)
+            .ok_or_else(math_error!())
+    }
+
+    /// Annualized last funding rate relative to the oracle twap (in BPS_PRECISION)
+    /// Positive means longs pay shorts
+    pub fn funding_apr_bps(&self) -> ClearingHouseResult<i128> {
+        self.annualize_funding_rate_bps(self.amm.last_funding_rate)
+    }
+
+    /// Annualized rates (in BPS_PRECISION) implied by the long/short split of the last funding rate,
+    /// as (borrow, lend): borrow is what longs pay and lend is what shorts receive
+    /// The two differ when the clearing house caps the side it would otherwise have to fund
+    pub fn implied_borrow_lend_rates(&self) -> ClearingHouseResult<(i128, i128)> {
+        // the split draws on the fee pool, so run it against a copy
+        let mut market = *self;
+        let (funding_rate_long, funding_rate_short) =
+            calculate_funding_rate_long_short(&mut market, self.amm.last_funding_rate)?;
+
+        Ok((
+            self.annualize_funding_rate_bps(funding_rate_long)?,
+            self.annualize_funding_rate_bps(funding_rate_short)?,
+        ))
+    }
+
+    /// Annualizes a funding rate (in FUNDING_PAYMENT_PRECISION) relative to the oracle twap (in BPS_PRECISION)
+    pub fn annualize_funding_rate_bps(&self, funding_rate: i128) -> ClearingHouseResult<i128> {
+        let period_adjustment = calculate_period_adjustment(self.amm.funding_period)?;
+        let days_per_year = cast_to_i128(ONE_YEAR)?
+            .checked_div(cast_to_i128(TWENTYFOUR_HOUR)?)
+            .ok_or_else(math_error!())?;
+
+        funding_rate
+            .checked_mul(period_adjustment)
+            .ok_or_else(math_error!())?
+            .checked_mul(days_per_year)
+            .ok_or_else(math_error!())?
+            .checked_mul(BPS_PRECISION_I128)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(FUNDING_PAYMENT_PRECISION)?)
+            .ok_or_else(math_error!())?
+            .checked_div(self.amm.last_oracle_price_twap)
+            .ok_or_else(math_error!())
+    }
+
+    /// Funding apr in excess of carry_cost_bps for a basis trade (long spot, short perp), in BPS_PRECISION
+    /// Positive means funding more than covers the carry, the trade breaks even at zero
+    pub fn basis_breakeven_rate(&self, carry_cost_bps: i128) -> ClearingHouseResult<i128> {
+        self.funding_apr_bps()?
+            .checked_sub(carry_cost_bps)
+            .ok_or_else(math_error!())
+    }
+
+    /// Collateral (in QUOTE_PRECISION) a position of base_asset_amount needs to pay one period of funding
+    /// at the last funding rate and still meet maintenance margin at the current mark price
+    /// Funding the position would receive is not credited
+    pub fn min_collateral_for_funding(&self, base_asset_amount: i128) -> ClearingHouseResult<u128> {
+        let base_asset_value = base_asset_amount
+            .unsigned_abs()
+            .checked_mul(self.amm.mark_price()?)
+            .ok_or_else(math_error!())?
+            .checked_div(MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO)
+            .ok_or_else(math_error!())?;
+
+        let maintenance_margin_requirement = base_asset_value
+            .checked_mul(self.get_margin_ratio(MarginRequirementType::Maintenance) as u128)
+            .ok_or_else(math_error!())?
+            .checked_div(MARGIN_PRECISION)
+            .ok_or_else(math_error!())?;
+
+        // funding payments are from the user's perspective (negative means the user paid)
+        let funding_payment = calculate_funding_payment_in_quote_precision(
+            self.amm.last_funding_rate,
+            base_asset_amount,
+        )?;
+        let funding_paid = if funding_payment < 0 {
+            funding_payment.unsigned_abs()
+        } else {
+            0
+        };
+
+        maintenance_margin_requirement
+            .checked_add(funding_paid)
+            .ok_or_else(math_error!())
+    }
+
+    /// Suggests the divisor for the funding price spread clamp (max spread = oracle twap / divisor)
+    /// such that funding at the clamp does not exceed target_apr_bps.
+    /// The funding rate is scaled down by the number of periods per day, so the clamp bounds the daily rate
+    /// to 1 / divisor regardless of funding_period, i.e. apr = ONE_YEAR / (TWENTYFOUR_HOUR * divisor)
+    pub fn clamp_divisor_for_max_apr(&self, target_apr_bps: i128) -> ClearingHouseResult<u128> {
+        validate!(
+            target_apr_bps > 0,
+            ErrorCode::MathError,
+            "target apr must be positive"
+        )?;
+
+        let numerator = ONE_YEAR
+            .checked_mul(BPS_PRECISION)
+            .ok_or_else(math_error!())?;
+        let denominator = cast_to_u128(TWENTYFOUR_HOUR)?
+            .checked_mul(target_apr_bps.unsigned_abs())
+            .ok_or_else(math_error!())?;
+
+        // round up so the clamp never allows more than the target apr
+        numerator
+            .checked_add(denominator)
+            .ok_or_else(math_error!())?
+            .checked_sub(1)
+            .ok_or_else(math_error!())?
+            .checked_div(denominator)
+            .ok_or_else(math_error!())
+    }
 }
 
 #[zero_copy]
@@ -137,6 +458,38 @@ pub struct AMM {
     pub short_intensity_count: u16,
     pub short_intensity_volume: u64,
 
+    // per-market funding pause, OR'd with the exchange-wide funding_paused in update_funding_rate
+    pub funding_frozen: bool,
+    pub funding_update_count: u64,
+    pub oracle_squared_return_sum: u128,
+    pub previous_funding_rate: i128,
+    pub oracle_price_multiplier: u128,
+    pub oracle_price_divisor: u128,
+    pub last_open_interest_twap: u128,
+    pub last_open_interest_twap_ts: i64,
+    pub funding_rate_checksum: u64,
+    pub skew_factor: u128,
+    pub funding_blocked: bool,
+    pub funding_use_microprice: bool,
+    pub oracle_outage_recovery_count: u8,
+    pub funding_clamp_divisor: u64,
+    pub max_funding_rate: u128, // FUNDING_RATE_PRECISION, zero means no ceiling
+    pub cumulative_funding_clamp_suppression: i128, // FUNDING_RATE_PRECISION
+    pub allow_negative_oracle: bool,
+    pub oracle_price_ema: i128,   // MARK_PRICE_PRECISION
+    pub oracle_ema_halflife: i64, // seconds
+    pub funding_use_oracle_ema: bool,
+    pub funding_rate_ema: i128,               // FUNDING_RATE_PRECISION
+    pub funding_rate_ema_alpha: u128,         // BPS_PRECISION, zero tracks the raw rate
+    pub last_funding_oracle_price_twap: i128, // MARK_PRICE_PRECISION
+    pub pyth_max_confidence_bps: u128, // BPS_PRECISION, zero means only the sanitized checks apply
+    pub funding_positive_count: u32,
+    pub funding_negative_count: u32,
+    pub funding_zero_count: u32,
+    pub funding_payment_dust: i128, // QUOTE_PRECISION * AMM_TO_QUOTE_PRECISION_RATIO, truncated from settled funding payments
+    pub use_pyth_ema_price: bool, // price pyth markets off pyth's smoothed ema price instead of the aggregate
+    pub switchboard_max_confidence_bps: u128, // BPS_PRECISION, zero means DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS
+
     // upgrade-ability
     pub curve_update_intensity: u8,
 
@@ -147,6 +500,114 @@ pub struct AMM {
 }
 
 impl AMM {
+    /// Funding (in FUNDING_RATE_PRECISION) the spread clamp and max_funding_rate have held back across all updates
+    /// Positive means longs would have paid more without the clamps
+    pub fn cumulative_clamp_suppression(&self) -> ClearingHouseResult<i128> {
+        Ok(self.cumulative_funding_clamp_suppression)
+    }
+
+    /// (last_mark_price_twap, last_oracle_price_twap) read together, both MARK_PRICE_PRECISION
+    pub fn twap_pair(&self) -> (u128, i128) {
+        (self.last_mark_price_twap, self.last_oracle_price_twap)
+    }
+
+    /// Seconds since the mark twap was last updated. The mark twap only moves with trades and funding updates,
+    /// so on a thin market a large age means it reflects old trading
+    pub fn mark_twap_age(&self, now: i64) -> ClearingHouseResult<i64> {
+        now.checked_sub(self.last_mark_price_twap_ts)
+            .ok_or_else(math_error!())
+    }
+
+    /// Oracle move (in MARK_PRICE_PRECISION) from the oracle twap used by the last funding update to current_oracle
+    /// The oracle twap itself also moves with trades, so the twap at funding is stored separately
+    pub fn oracle_move_since_last_funding(
+        &self,
+        current_oracle: i128,
+    ) -> ClearingHouseResult<i128> {
+        current_oracle
+            .checked_sub(self.last_funding_oracle_price_twap)
+            .ok_or_else(math_error!())
+    }
+
+    /// Funding (in QUOTE_PRECISION, negative means the position pays) base_asset_amount would accrue over
+    /// window_seconds at the funding rate ema instead of the last rate. The ema is taken relative to the
+    /// oracle twap it was priced against and rescaled to mark_price
+    pub fn projected_funding_over_window(
+        &self,
+        base_asset_amount: i128,
+        mark_price: u128,
+        window_seconds: i64,
+    ) -> ClearingHouseResult<i128> {
+        if base_asset_amount == 0 || window_seconds <= 0 {
+            return Ok(0);
+        }
+
+        let funding_rate_over_window = self
+            .funding_rate_ema
+            .checked_mul(cast_to_i128(mark_price)?)
+            .ok_or_else(math_error!())?
+            .checked_div(self.last_oracle_price_twap)
+            .ok_or_else(math_error!())?
+            .checked_mul(cast_to_i128(window_seconds)?)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(self.funding_period)?)
+            .ok_or_else(math_error!())?;
+
+        calculate_funding_payment_in_quote_precision(funding_rate_over_window, base_asset_amount)
+    }
+
+    /// Counts of (positive, negative, zero) funding rates across funding updates
+    /// Persistent positive counts mean the market trades in contango, negative in backwardation
+    pub fn funding_sign_counts(&self) -> (u32, u32, u32) {
+        (
+            self.funding_positive_count,
+            self.funding_negative_count,
+            self.funding_zero_count,
+        )
+    }
+
+    /// Limits funding_rate (in FUNDING_RATE_PRECISION) to [-max_funding_rate, max_funding_rate]
+    /// A max_funding_rate of zero means the market has no ceiling
+    pub fn cap_funding_rate(&self, funding_rate: i128) -> ClearingHouseResult<i128> {
+        if self.max_funding_rate == 0 {
+            return Ok(funding_rate);
+        }
+
+        let max_funding_rate = cast_to_i128(self.max_funding_rate)?;
+        Ok(max(-max_funding_rate, min(funding_rate, max_funding_rate)))
+    }
+
+    /// Oracle prices must be positive unless the market allows negative prices (e.g. basis/spread markets)
+    /// A price of zero is never valid
+    pub fn is_valid_oracle_price(&self, oracle_price: i128) -> bool {
+        oracle_price > 0 || (self.allow_negative_oracle && oracle_price < 0)
+    }
+
+    /// An amm with zeroed reserves or peg hasn't been initialized and can't be used for funding or pricing
+    pub fn is_initialized(&self) -> bool {
+        self.base_asset_reserve != 0
+            && self.quote_asset_reserve != 0
+            && self.sqrt_k != 0
+            && self.peg_multiplier != 0
+    }
+
+    /// Peg (in PEG_PRECISION) that would have closed the average mark/oracle spread over the twap window
+    /// The mark price scales linearly with the peg, so this rescales the peg by oracle twap / mark twap
+    pub fn suggested_repeg(&self) -> ClearingHouseResult<u128> {
+        validate!(
+            self.last_oracle_price_twap > 0 && self.last_mark_price_twap > 0,
+            ErrorCode::InvalidOracle,
+            "Can not suggest a peg without positive mark and oracle twaps"
+        )?;
+
+        bn::U192::from(self.peg_multiplier)
+            .checked_mul(bn::U192::from(self.last_oracle_price_twap.unsigned_abs()))
+            .ok_or_else(math_error!())?
+            .checked_div(bn::U192::from(self.last_mark_price_twap))
+            .ok_or_else(math_error!())?
+            .try_to_u128()
+    }
+
     pub fn mark_price(&self) -> ClearingHouseResult<u128> {
         amm::calculate_price(
             self.quote_asset_reserve,
@@ -155,15 +616,25 @@ impl AMM {
         )
     }
 
+    /// Bid and ask quoted around mark_price with the short and long spreads
+    /// Returns (bid_price, ask_price)
     pub fn bid_ask_price(&self, mark_price: u128) -> ClearingHouseResult<(u128, u128)> {
         let ask_price = mark_price
-            .checked_mul(BID_ASK_SPREAD_PRECISION + self.long_spread)
+            .checked_mul(
+                BID_ASK_SPREAD_PRECISION
+                    .checked_add(self.long_spread)
+                    .ok_or_else(math_error!())?,
+            )
             .ok_or_else(math_error!())?
             .checked_div(BID_ASK_SPREAD_PRECISION)
             .ok_or_else(math_error!())?;
 
         let bid_price = mark_price
-            .checked_mul(BID_ASK_SPREAD_PRECISION + self.short_spread)
+            .checked_mul(
+                BID_ASK_SPREAD_PRECISION
+                    .checked_sub(self.short_spread)
+                    .ok_or_else(math_error!())?,
+            )
             .ok_or_else(math_error!())?
             .checked_div(BID_ASK_SPREAD_PRECISION)
             .ok_or_else(math_error!())?;
@@ -171,109 +642,299 @@ impl AMM {
         Ok((bid_price, ask_price))
     }
 
+    /// Change between the last two funding rates (in FUNDING_RATE_PRECISION)
+    pub fn funding_acceleration(&self) -> ClearingHouseResult<i128> {
+        self.last_funding_rate
+            .checked_sub(self.previous_funding_rate)
+            .ok_or_else(math_error!())
+    }
+
+    /// Open interest in QUOTE_PRECISION weighted over window_seconds, smoothing out spikes from individual trades
+    pub fn time_weighted_oi(&self, now: i64, window_seconds: i64) -> ClearingHouseResult<u128> {
+        amm::calculate_open_interest_twap(self, now, window_seconds)
+    }
+
+    /// Mark price offset against the amm's inventory: when users are net long (the amm is short) the price is raised,
+    /// when users are net short it is lowered. The offset is skew_factor (in BID_ASK_SPREAD_PRECISION)
+    /// for each sqrt_k of net base asset amount
+    pub fn skewed_mark_price(&self) -> ClearingHouseResult<u128> {
+        let mark_price = self.mark_price()?;
+        if self.skew_factor == 0 || self.sqrt_k == 0 {
+            return Ok(mark_price);
+        }
+
+        let skew = cast_to_i128(self.skew_factor)?
+            .checked_mul(self.net_base_asset_amount)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(self.sqrt_k)?)
+            .ok_or_else(math_error!())?;
+
+        let skew_multiplier = max(
+            0,
+            BID_ASK_SPREAD_PRECISION_I128
+                .checked_add(skew)
+                .ok_or_else(math_error!())?,
+        );
+
+        mark_price
+            .checked_mul(cast_to_u128(skew_multiplier)?)
+            .ok_or_else(math_error!())?
+            .checked_div(BID_ASK_SPREAD_PRECISION)
+            .ok_or_else(math_error!())
+    }
+
+    /// Width of the quoted spread around the mark price in bps: (ask - bid) * 10000 / mid
+    pub fn spread_bps(&self) -> ClearingHouseResult<u128> {
+        let (bid_price, ask_price) = self.bid_ask_price(self.mark_price()?)?;
+
+        let mid_price = ask_price
+            .checked_add(bid_price)
+            .ok_or_else(math_error!())?
+            .checked_div(2)
+            .ok_or_else(math_error!())?;
+
+        ask_price
+            .checked_sub(bid_price)
+            .ok_or_else(math_error!())?
+            .checked_mul(BPS_PRECISION)
+            .ok_or_else(math_error!())?
+            .checked_div(mid_price)
+            .ok_or_else(math_error!())
+    }
+
+    /// Size weighted mid of the quoted bid and ask: (bid * ask_size + ask * bid_size) / (bid_size + ask_size)
+    /// Leans toward the thinner side, falls back to the simple mid if both sizes are zero
+    pub fn microprice(&self, bid_size: u128, ask_size: u128) -> ClearingHouseResult<u128> {
+        let (bid_price, ask_price) = self.bid_ask_price(self.mark_price()?)?;
+
+        if bid_size == 0 && ask_size == 0 {
+            return ask_price
+                .checked_add(bid_price)
+                .ok_or_else(math_error!())?
+                .checked_div(2)
+                .ok_or_else(math_error!());
+        }
+
+        let weighted_bid = bn::U192::from(bid_price)
+            .checked_mul(bn::U192::from(ask_size))
+            .ok_or_else(math_error!())?;
+        let weighted_ask = bn::U192::from(ask_price)
+            .checked_mul(bn::U192::from(bid_size))
+            .ok_or_else(math_error!())?;
+
+        weighted_bid
+            .checked_add(weighted_ask)
+            .ok_or_else(math_error!())?
+            .checked_div(bn::U192::from(
+                bid_size.checked_add(ask_size).ok_or_else(math_error!())?,
+            ))
+            .ok_or_else(math_error!())?
+            .try_to_u128()
+    }
+
+    /// Reserves after a trade of base_amount against the curve, without mutating the amm
+    /// A positive base_amount is a long (base removed from the amm), a negative base_amount is a short
+    /// Returns (base_asset_reserve, quote_asset_reserve)
+    pub fn reserves_after_trade(&self, base_amount: i128) -> ClearingHouseResult<(u128, u128)> {
+        if base_amount == 0 {
+            return Ok((self.base_asset_reserve, self.quote_asset_reserve));
+        }
+
+        let swap_direction = if base_amount > 0 {
+            SwapDirection::Remove
+        } else {
+            SwapDirection::Add
+        };
+
+        let (new_quote_asset_reserve, new_base_asset_reserve) = amm::calculate_swap_output(
+            base_amount.unsigned_abs(),
+            self.base_asset_reserve,
+            swap_direction,
+            self.sqrt_k,
+        )?;
+
+        Ok((new_base_asset_reserve, new_quote_asset_reserve))
+    }
+
+    /// Notional of the net open interest relative to the quote notional in the amm's reserves, in bps
+    pub fn utilization_bps(&self) -> ClearingHouseResult<u128> {
+        let liquidity = reserve_to_asset_amount(self.quote_asset_reserve, self.peg_multiplier)?;
+        if liquidity == 0 {
+            return Ok(0);
+        }
+
+        let open_interest_notional = self
+            .net_base_asset_amount
+            .unsigned_abs()
+            .checked_mul(self.mark_price()?)
+            .ok_or_else(math_error!())?
+            .checked_div(MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO)
+            .ok_or_else(math_error!())?;
+
+        open_interest_notional
+            .checked_mul(BPS_PRECISION)
+            .ok_or_else(math_error!())?
+            .checked_div(liquidity)
+            .ok_or_else(math_error!())
+    }
+
+    /// Whether either reserve has been drawn down to threshold_bps of sqrt_k (the balanced reserve) or less,
+    /// i.e. reserve <= sqrt_k * threshold_bps / 10000
+    /// The curve has no configured floor, trades revert as a reserve approaches zero
+    pub fn is_near_reserve_floor(&self, threshold_bps: u128) -> ClearingHouseResult<bool> {
+        let floor = self
+            .sqrt_k
+            .checked_mul(threshold_bps)
+            .ok_or_else(math_error!())?
+            .checked_div(BPS_PRECISION)
+            .ok_or_else(math_error!())?;
+
+        Ok(self.base_asset_reserve <= floor || self.quote_asset_reserve <= floor)
+    }
+
+    /// Signed deviation of the spot mark price from the mark twap, in bps of the twap
+    pub fn mark_twap_deviation_bps(&self, spot_mark: u128) -> ClearingHouseResult<i128> {
+        let mark_twap = cast_to_i128(self.last_mark_price_twap)?;
+
+        cast_to_i128(spot_mark)?
+            .checked_sub(mark_twap)
+            .ok_or_else(math_error!())?
+            .checked_mul(BPS_PRECISION_I128)
+            .ok_or_else(math_error!())?
+            .checked_div(mark_twap)
+            .ok_or_else(math_error!())
+    }
+
     pub fn get_oracle_price(
         &self,
         price_oracle: &AccountInfo,
         clock_slot: u64,
     ) -> ClearingHouseResult<OraclePriceData> {
-        match self.oracle_source {
+        let oracle_price_data = match self.oracle_source {
             OracleSource::Pyth => self.get_pyth_price(price_oracle, clock_slot),
             OracleSource::Switchboard => self.get_switchboard_price(price_oracle, clock_slot),
             OracleSource::QuoteAsset => panic!(),
+            OracleSource::Index | OracleSource::Chainlink => {
+                oracle::get_oracle_price(&self.oracle_source, price_oracle, clock_slot)
+            }
+        }?;
+
+        let oracle_price_data = self.convert_oracle_price_data(oracle_price_data)?;
+
+        if !self.is_valid_oracle_price(oracle_price_data.price) {
+            msg!("Invalid oracle price {}", oracle_price_data.price);
+            return Err(ErrorCode::InvalidOraclePrice);
+        }
+
+        if self.oracle_source == OracleSource::Switchboard {
+            self.apply_oracle_outage_recovery_floor(oracle_price_data)
+        } else {
+            Ok(oracle_price_data)
         }
     }
 
-    pub fn get_pyth_price(
+    /// Following an outage the first readings may be tight but shouldn't be fully trusted,
+    /// so the confidence floor is raised and decays back over the next ORACLE_OUTAGE_RECOVERY_UPDATES funding updates
+    pub fn apply_oracle_outage_recovery_floor(
         &self,
-        price_oracle: &AccountInfo,
-        clock_slot: u64,
+        oracle_price_data: OraclePriceData,
     ) -> ClearingHouseResult<OraclePriceData> {
-        let pyth_price_data = price_oracle
-            .try_borrow_data()
-            .or(Err(ErrorCode::UnableToLoadOracle))?;
-        let price_data = pyth_client::cast::<pyth_client::Price>(&pyth_price_data);
+        if self.oracle_outage_recovery_count == 0 {
+            return Ok(oracle_price_data);
+        }
 
-        let oracle_price = cast_to_i128(price_data.agg.price)?;
-        let oracle_conf = cast_to_u128(price_data.agg.conf)?;
+        let confidence_floor = oracle_price_data
+            .price
+            .unsigned_abs()
+            .checked_mul(ORACLE_OUTAGE_RECOVERY_CONFIDENCE_STEP_BPS)
+            .ok_or_else(math_error!())?
+            .checked_mul(self.oracle_outage_recovery_count as u128)
+            .ok_or_else(math_error!())?
+            .checked_div(BPS_PRECISION)
+            .ok_or_else(math_error!())?;
 
-        let oracle_precision = 10_u128.pow(price_data.expo.unsigned_abs());
+        Ok(OraclePriceData {
+            confidence: max(oracle_price_data.confidence, confidence_floor),
+            ..oracle_price_data
+        })
+    }
 
-        let mut oracle_scale_mult = 1;
-        let mut oracle_scale_div = 1;
+    /// convert_oracle_price_data for a bare price (e.g. an oracle twap)
+    pub fn convert_oracle_price(&self, oracle_price: i128) -> ClearingHouseResult<i128> {
+        let multiplier = max(self.oracle_price_multiplier, 1);
+        let divisor = max(self.oracle_price_divisor, 1);
 
-        if oracle_precision > MARK_PRICE_PRECISION {
-            oracle_scale_div = oracle_precision
-                .checked_div(MARK_PRICE_PRECISION)
-                .ok_or_else(math_error!())?;
-        } else {
-            oracle_scale_mult = MARK_PRICE_PRECISION
-                .checked_div(oracle_precision)
-                .ok_or_else(math_error!())?;
+        oracle_price
+            .checked_mul(cast_to_i128(multiplier)?)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(divisor)?)
+            .ok_or_else(math_error!())
+    }
+
+    /// Converts the oracle price from the feed's unit (e.g. per 1000 units) to the market's per-contract price
+    /// A multiplier or divisor of zero (markets initialized before they were added) is treated as one
+    pub fn convert_oracle_price_data(
+        &self,
+        oracle_price_data: OraclePriceData,
+    ) -> ClearingHouseResult<OraclePriceData> {
+        let multiplier = max(self.oracle_price_multiplier, 1);
+        let divisor = max(self.oracle_price_divisor, 1);
+
+        if multiplier == divisor {
+            return Ok(oracle_price_data);
         }
 
-        let oracle_price_scaled = (oracle_price)
-            .checked_mul(cast(oracle_scale_mult)?)
-            .ok_or_else(math_error!())?
-            .checked_div(cast(oracle_scale_div)?)
-            .ok_or_else(math_error!())?;
+        let price = self.convert_oracle_price(oracle_price_data.price)?;
 
-        let oracle_conf_scaled = (oracle_conf)
-            .checked_mul(oracle_scale_mult)
+        let confidence = oracle_price_data
+            .confidence
+            .checked_mul(multiplier)
             .ok_or_else(math_error!())?
-            .checked_div(oracle_scale_div)
-            .ok_or_else(math_error!())?;
-
-        let oracle_delay: i64 = cast_to_i64(clock_slot)?
-            .checked_sub(cast(price_data.valid_slot)?)
+            .checked_div(divisor)
             .ok_or_else(math_error!())?;
 
         Ok(OraclePriceData {
-            price: oracle_price_scaled,
-            confidence: oracle_conf_scaled,
-            delay: oracle_delay,
-            has_sufficient_number_of_data_points: true,
+            price,
+            confidence,
+            ..oracle_price_data
         })
     }
 
-    pub fn get_switchboard_price(
+    pub fn get_pyth_price(
         &self,
         price_oracle: &AccountInfo,
         clock_slot: u64,
     ) -> ClearingHouseResult<OraclePriceData> {
-        let aggregator_data =
-            AggregatorAccountData::new(price_oracle).or(Err(ErrorCode::UnableToLoadOracle))?;
-
-        let price = convert_switchboard_decimal(&aggregator_data.latest_confirmed_round.result)?;
-        let confidence =
-            convert_switchboard_decimal(&aggregator_data.latest_confirmed_round.std_deviation)?;
-
-        // std deviation should always be positive, if we get a negative make it u128::MAX so it's flagged as bad value
-        let confidence = if confidence < 0 {
-            u128::MAX
+        let oracle_price_data = if self.use_pyth_ema_price {
+            oracle::get_pyth_ema_price(price_oracle, clock_slot)?
         } else {
-            let price_10bps = price
-                .unsigned_abs()
-                .checked_div(1000)
-                .ok_or_else(math_error!())?;
-            max(confidence.unsigned_abs(), price_10bps)
+            oracle::get_pyth_price(price_oracle, clock_slot)?
         };
 
-        let delay: i64 = cast_to_i64(clock_slot)?
-            .checked_sub(cast(
-                aggregator_data.latest_confirmed_round.round_open_slot,
-            )?)
-            .ok_or_else(math_error!())?;
+        if self.pyth_max_confidence_bps == 0 {
+            return Ok(oracle_price_data);
+        }
 
-        let has_sufficient_number_of_data_points =
-            aggregator_data.latest_confirmed_round.num_success
-                >= aggregator_data.min_oracle_results;
+        // reject obviously broken readings at the source, independent of the guard rails
+        oracle_price_data.with_max_confidence_bps(self.pyth_max_confidence_bps)
+    }
 
-        Ok(OraclePriceData {
-            price,
-            confidence,
-            delay,
-            has_sufficient_number_of_data_points,
-        })
+    pub fn get_switchboard_price(
+        &self,
+        price_oracle: &AccountInfo,
+        clock_slot: u64,
+    ) -> ClearingHouseResult<OraclePriceData> {
+        let max_confidence_bps = if self.switchboard_max_confidence_bps == 0 {
+            DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS
+        } else {
+            self.switchboard_max_confidence_bps
+        };
+
+        oracle::get_switchboard_price_with_max_confidence(
+            price_oracle,
+            clock_slot,
+            max_confidence_bps,
+        )
     }
 
     pub fn get_oracle_twap(&self, price_oracle: &AccountInfo) -> ClearingHouseResult<Option<i128>> {
@@ -281,58 +942,13 @@ impl AMM {
             OracleSource::Pyth => Ok(Some(self.get_pyth_twap(price_oracle)?)),
             OracleSource::Switchboard => Ok(None),
             OracleSource::QuoteAsset => panic!(),
+            OracleSource::Index => Ok(None),
+            OracleSource::Chainlink => Ok(None),
         }
     }
 
+    /// The pyth twap, converted to the market's per-contract price like the spot price
     pub fn get_pyth_twap(&self, price_oracle: &AccountInfo) -> ClearingHouseResult<i128> {
-        let pyth_price_data = price_oracle
-            .try_borrow_data()
-            .or(Err(ErrorCode::UnableToLoadOracle))?;
-        let price_data = pyth_client::cast::<pyth_client::Price>(&pyth_price_data);
-
-        let oracle_twap = cast_to_i128(price_data.twap.val)?;
-
-        let oracle_precision = 10_u128.pow(price_data.expo.unsigned_abs());
-
-        let mut oracle_scale_mult = 1;
-        let mut oracle_scale_div = 1;
-
-        if oracle_precision > MARK_PRICE_PRECISION {
-            oracle_scale_div = oracle_precision
-                .checked_div(MARK_PRICE_PRECISION)
-                .ok_or_else(math_error!())?;
-        } else {
-            oracle_scale_mult = MARK_PRICE_PRECISION
-                .checked_div(oracle_precision)
-                .ok_or_else(math_error!())?;
-        }
-
-        let oracle_twap_scaled = (oracle_twap)
-            .checked_mul(cast(oracle_scale_mult)?)
-            .ok_or_else(math_error!())?
-            .checked_div(cast(oracle_scale_div)?)
-            .ok_or_else(math_error!())?;
-
-        Ok(oracle_twap_scaled)
-    }
-}
-
-/// Given a decimal number represented as a mantissa (the digits) plus an
-/// original_precision (10.pow(some number of decimals)), scale the
-/// mantissa/digits to make sense with a new_precision.
-fn convert_switchboard_decimal(
-    switchboard_decimal: &SwitchboardDecimal,
-) -> ClearingHouseResult<i128> {
-    let switchboard_precision = 10_u128.pow(switchboard_decimal.scale);
-    if switchboard_precision > MARK_PRICE_PRECISION {
-        switchboard_decimal
-            .mantissa
-            .checked_div((switchboard_precision / MARK_PRICE_PRECISION) as i128)
-            .ok_or_else(math_error!())
-    } else {
-        switchboard_decimal
-            .mantissa
-            .checked_mul((MARK_PRICE_PRECISION / switchboard_precision) as i128)
-            .ok_or_else(math_error!())
+        self.convert_oracle_price(oracle::get_pyth_twap(price_oracle)?)
     }
 }
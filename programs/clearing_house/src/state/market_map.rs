@@ -99,6 +99,29 @@ pub fn get_writable_markets_for_user_positions(user_positions: &UserPositions) -
     writable_markets
 }
 
+/// Markets of the user's open positions, which settle_funding_payment adds funding rounding dust to
+pub fn get_writable_markets_for_funding_settlement(
+    user_positions: &UserPositions,
+) -> WritableMarkets {
+    let mut writable_markets = WritableMarkets::new();
+    for position in user_positions.iter() {
+        if position.base_asset_amount != 0 {
+            writable_markets.insert(position.market_index);
+        }
+    }
+    writable_markets
+}
+
+/// get_writable_markets plus the markets settle_funding_payment writes to
+pub fn get_writable_markets_with_funding_settlement(
+    market_index: u64,
+    user_positions: &UserPositions,
+) -> WritableMarkets {
+    let mut writable_markets = get_writable_markets_for_funding_settlement(user_positions);
+    writable_markets.insert(market_index);
+    writable_markets
+}
+
 pub type MarketOracles<'a, 'b> = BTreeMap<u64, &'a AccountInfo<'b>>;
 
 pub fn get_market_oracles<'a, 'b>(
@@ -1,11 +1,17 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use arrayref::array_ref;
 
-use crate::error::ClearingHouseResult;
+use crate::error::{ClearingHouseResult, ErrorCode};
+use crate::ids::{chainlink_program, pyth_program, pyth_receiver_program, switchboard_program};
 use crate::math::casting::{cast, cast_to_i128, cast_to_i64, cast_to_u128};
-use crate::math::constants::{MARK_PRICE_PRECISION, MARK_PRICE_PRECISION_I128};
+use crate::math::constants::{
+    BPS_PRECISION, DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS, MARK_PRICE_PRECISION,
+    MARK_PRICE_PRECISION_I128, SWITCHBOARD_MAX_ROUND_AGE_SLOTS,
+};
 use crate::math_error;
 use solana_program::msg;
-use std::cmp::max;
+use std::cmp::{max, min};
 use switchboard_v2::decimal::SwitchboardDecimal;
 use switchboard_v2::AggregatorAccountData;
 
@@ -14,6 +20,9 @@ pub enum OracleSource {
     Pyth,
     Switchboard,
     QuoteAsset,
+    Index,
+    // appended so the ordinals of the existing sources stored on markets don't shift
+    Chainlink,
 }
 
 impl Default for OracleSource {
@@ -31,6 +40,99 @@ pub struct OraclePriceData {
     pub has_sufficient_number_of_data_points: bool,
 }
 
+impl OraclePriceData {
+    /// Confidence relative to the price, in BPS_PRECISION
+    /// Saturates instead of overflowing, so an unbounded confidence (e.g. u128::MAX) reads as very wide
+    pub fn confidence_bps(&self) -> ClearingHouseResult<u128> {
+        let price = self.price.unsigned_abs();
+        if price == 0 {
+            msg!("Can not calculate confidence bps for oracle price of zero");
+            return Err(ErrorCode::InvalidOracle);
+        }
+
+        self.confidence
+            .saturating_mul(BPS_PRECISION)
+            .checked_div(price)
+            .ok_or_else(math_error!())
+    }
+
+    /// Flags the reading as not having sufficient data points if its confidence is wider than max_confidence_bps
+    /// A zero price has no meaningful confidence, so it is flagged too
+    pub fn with_max_confidence_bps(self, max_confidence_bps: u128) -> ClearingHouseResult<Self> {
+        if self.price != 0 && self.confidence_bps()? <= max_confidence_bps {
+            return Ok(self);
+        }
+
+        msg!(
+            "Oracle confidence {} exceeds {} bps of price {}",
+            self.confidence,
+            max_confidence_bps,
+            self.price
+        );
+
+        Ok(OraclePriceData {
+            has_sufficient_number_of_data_points: false,
+            ..self
+        })
+    }
+
+    /// Flags the reading as not having sufficient data points if its confidence is wider than the price itself
+    pub fn sanitized(self) -> ClearingHouseResult<Self> {
+        self.with_max_confidence_bps(BPS_PRECISION)
+    }
+
+    /// Flags the reading as not having sufficient data points if its confidence is zero
+    /// Only for sources that publish a confidence (pyth, switchboard), where zero means a broken feed
+    pub fn with_published_confidence(self) -> ClearingHouseResult<Self> {
+        if self.confidence != 0 {
+            return Ok(self);
+        }
+
+        msg!("Oracle confidence is zero for price {}", self.price);
+
+        Ok(OraclePriceData {
+            has_sufficient_number_of_data_points: false,
+            ..self
+        })
+    }
+
+    /// Price rescaled from MARK_PRICE_PRECISION to target_decimals
+    pub fn price_in_decimals(&self, target_decimals: u32) -> ClearingHouseResult<i128> {
+        let target_precision = 10_u128
+            .checked_pow(target_decimals)
+            .ok_or_else(math_error!())?;
+
+        if target_precision > MARK_PRICE_PRECISION {
+            self.price
+                .checked_mul(cast(target_precision / MARK_PRICE_PRECISION)?)
+                .ok_or_else(math_error!())
+        } else {
+            self.price
+                .checked_div(cast(MARK_PRICE_PRECISION / target_precision)?)
+                .ok_or_else(math_error!())
+        }
+    }
+
+    /// Price minus confidence, the worst case for a long (saturates instead of overflowing)
+    pub fn price_lower_bound(&self) -> i128 {
+        self.price.saturating_sub(self.confidence_i128())
+    }
+
+    /// Price plus confidence, the worst case for a short (saturates instead of overflowing)
+    pub fn price_upper_bound(&self) -> i128 {
+        self.price.saturating_add(self.confidence_i128())
+    }
+
+    fn confidence_i128(&self) -> i128 {
+        min(self.confidence, i128::MAX as u128) as i128
+    }
+
+    /// Slots left before the reading exceeds max_delay and is considered stale (zero if already stale)
+    pub fn slots_until_stale(&self, max_delay: i64) -> i64 {
+        max_delay.saturating_sub(self.delay).max(0)
+    }
+}
+
 pub fn get_oracle_price(
     oracle_source: &OracleSource,
     price_oracle: &AccountInfo,
@@ -39,41 +141,196 @@ pub fn get_oracle_price(
     match oracle_source {
         OracleSource::Pyth => get_pyth_price(price_oracle, clock_slot),
         OracleSource::Switchboard => get_switchboard_price(price_oracle, clock_slot),
-        OracleSource::QuoteAsset => Ok(OraclePriceData {
-            price: MARK_PRICE_PRECISION_I128,
-            confidence: 1,
-            delay: 0,
-            has_sufficient_number_of_data_points: true,
-        }),
+        OracleSource::QuoteAsset => get_quote_asset_price(Some(price_oracle), clock_slot),
+        OracleSource::Index => get_index_price(price_oracle, clock_slot),
+        OracleSource::Chainlink => get_chainlink_price(price_oracle, clock_slot),
+    }
+}
+
+/// The oracle price with its confidence band as (lower, mid, upper)
+pub fn get_oracle_price_with_bounds(
+    oracle_source: &OracleSource,
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+) -> ClearingHouseResult<(i128, i128, i128)> {
+    let oracle_price_data = get_oracle_price(oracle_source, price_oracle, clock_slot)?;
+
+    Ok((
+        oracle_price_data.price_lower_bound(),
+        oracle_price_data.price,
+        oracle_price_data.price_upper_bound(),
+    ))
+}
+
+/// Like get_oracle_price, but rejects a reading older than max_delay slots with StaleOracle
+pub fn get_oracle_price_checked(
+    oracle_source: &OracleSource,
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+    max_delay: i64,
+) -> ClearingHouseResult<OraclePriceData> {
+    let oracle_price_data = get_oracle_price(oracle_source, price_oracle, clock_slot)?;
+
+    if oracle_price_data.delay > max_delay {
+        msg!(
+            "Oracle {} delay {} exceeds max delay {}",
+            price_oracle.key,
+            oracle_price_data.delay,
+            max_delay
+        );
+        return Err(ErrorCode::StaleOracle);
+    }
+
+    Ok(oracle_price_data)
+}
+
+/// The quote asset is priced at 1 unless a pyth or switchboard feed for it (e.g. USDC/USD) is provided,
+/// in which case the feed is used so a depeg is reflected
+pub fn get_quote_asset_price(
+    price_oracle: Option<&AccountInfo>,
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    if let Some(price_oracle) = price_oracle {
+        if is_pyth_owner(price_oracle.owner) {
+            return get_pyth_price(price_oracle, clock_slot);
+        }
+
+        if price_oracle.owner == &switchboard_program::id() {
+            return get_switchboard_price(price_oracle, clock_slot);
+        }
+    }
+
+    Ok(OraclePriceData {
+        price: MARK_PRICE_PRECISION_I128,
+        confidence: 1,
+        delay: 0,
+        has_sufficient_number_of_data_points: true,
+    })
+}
+
+/// The individual readings feeding an aggregate (median) oracle price, in the order of sources
+/// A source that couldn't be read is reported with None
+pub fn get_oracle_price_contributors(
+    sources: &[(OracleSource, &AccountInfo)],
+    clock_slot: u64,
+) -> Vec<(OracleSource, Option<OraclePriceData>)> {
+    sources
+        .iter()
+        .map(|(oracle_source, price_oracle)| {
+            (
+                *oracle_source,
+                get_oracle_price(oracle_source, price_oracle, clock_slot).ok(),
+            )
+        })
+        .collect()
+}
+
+/// Reads each source and returns the median price of those with sufficient data points,
+/// with the widest confidence and longest delay of the contributing feeds
+pub fn get_oracle_price_median(
+    sources: &[(OracleSource, &AccountInfo)],
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    let mut valid_price_data: Vec<OraclePriceData> =
+        get_oracle_price_contributors(sources, clock_slot)
+            .into_iter()
+            .filter_map(|(_, price_data)| price_data)
+            .filter(|price_data| price_data.has_sufficient_number_of_data_points)
+            .collect();
+
+    if valid_price_data.is_empty() {
+        msg!("None of the {} oracles could be read", sources.len());
+        return Err(ErrorCode::UnableToLoadOracle);
+    }
+
+    if valid_price_data.len() == 1 {
+        return Ok(valid_price_data[0]);
     }
+
+    valid_price_data.sort_by_key(|price_data| price_data.price);
+
+    let middle = valid_price_data.len() / 2;
+    let price = if valid_price_data.len() % 2 == 0 {
+        valid_price_data[middle - 1]
+            .price
+            .checked_add(valid_price_data[middle].price)
+            .ok_or_else(math_error!())?
+            .checked_div(2)
+            .ok_or_else(math_error!())?
+    } else {
+        valid_price_data[middle].price
+    };
+
+    let confidence = valid_price_data
+        .iter()
+        .map(|price_data| price_data.confidence)
+        .max()
+        .unwrap_or(0);
+
+    let delay = valid_price_data
+        .iter()
+        .map(|price_data| price_data.delay)
+        .max()
+        .unwrap_or(0);
+
+    Ok(OraclePriceData {
+        price,
+        confidence,
+        delay,
+        has_sufficient_number_of_data_points: true,
+    })
 }
 
 pub fn get_pyth_price(
     price_oracle: &AccountInfo,
     clock_slot: u64,
 ) -> ClearingHouseResult<OraclePriceData> {
-    let pyth_price_data = price_oracle
+    let price_data = load_pyth_price_account(price_oracle)?;
+
+    convert_pyth_price(&price_data, clock_slot)?.with_published_confidence()
+}
+
+/// Like get_pyth_price, but with pyth's ema price in place of the aggregate price
+/// The confidence is still the aggregate's
+pub fn get_pyth_ema_price(
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    let price_data = load_pyth_price_account(price_oracle)?;
+
+    convert_pyth_price(
+        &PythPrice {
+            price: price_data.twap,
+            ..price_data
+        },
+        clock_slot,
+    )?
+    .with_published_confidence()
+}
+
+/// Reads a precomputed index (e.g. a basket) from an IndexPrice account, scaled the same way as pyth
+pub fn get_index_price(
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    validate_oracle_owner(price_oracle, &crate::id())?;
+
+    let index_price_data = price_oracle
         .try_borrow_data()
         .or(Err(crate::error::ErrorCode::UnableToLoadOracle))?;
-    let price_data = pyth_client::cast::<pyth_client::Price>(&pyth_price_data);
+    let price_data = load_index_price(&index_price_data)?;
 
-    let oracle_price = cast_to_i128(price_data.agg.price)?;
-    let oracle_conf = cast_to_u128(price_data.agg.conf)?;
-
-    let oracle_precision = 10_u128.pow(price_data.expo.unsigned_abs());
+    convert_pyth_price(&price_data, clock_slot)
+}
 
-    let mut oracle_scale_mult = 1;
-    let mut oracle_scale_div = 1;
+fn convert_pyth_price(
+    price_data: &PythPrice,
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    let oracle_price = cast_to_i128(price_data.price)?;
+    let oracle_conf = cast_to_u128(price_data.conf)?;
 
-    if oracle_precision > MARK_PRICE_PRECISION {
-        oracle_scale_div = oracle_precision
-            .checked_div(MARK_PRICE_PRECISION)
-            .ok_or_else(math_error!())?;
-    } else {
-        oracle_scale_mult = MARK_PRICE_PRECISION
-            .checked_div(oracle_precision)
-            .ok_or_else(math_error!())?;
-    }
+    let (oracle_scale_mult, oracle_scale_div) = calculate_pyth_price_scale(price_data.expo)?;
 
     let oracle_price_scaled = (oracle_price)
         .checked_mul(cast(oracle_scale_mult)?)
@@ -91,11 +348,204 @@ pub fn get_pyth_price(
         .checked_sub(cast(price_data.valid_slot)?)
         .ok_or_else(math_error!())?;
 
-    Ok(OraclePriceData {
+    OraclePriceData {
         price: oracle_price_scaled,
         confidence: oracle_conf_scaled,
         delay: oracle_delay,
         has_sufficient_number_of_data_points: true,
+    }
+    .sanitized()
+}
+
+pub fn get_pyth_twap(price_oracle: &AccountInfo) -> ClearingHouseResult<i128> {
+    let price_data = load_pyth_price_account(price_oracle)?;
+
+    let oracle_twap = cast_to_i128(price_data.twap)?;
+
+    let (oracle_scale_mult, oracle_scale_div) = calculate_pyth_price_scale(price_data.expo)?;
+
+    let oracle_twap_scaled = (oracle_twap)
+        .checked_mul(cast(oracle_scale_mult)?)
+        .ok_or_else(math_error!())?
+        .checked_div(cast(oracle_scale_div)?)
+        .ok_or_else(math_error!())?;
+
+    Ok(oracle_twap_scaled)
+}
+
+/// Multiplier and divisor taking a pyth price with exponent expo to MARK_PRICE_PRECISION
+/// A zero or positive exponent means the raw price is in whole units (times 10^expo)
+fn calculate_pyth_price_scale(expo: i32) -> ClearingHouseResult<(u128, u128)> {
+    let oracle_precision = 10_u128
+        .checked_pow(expo.unsigned_abs())
+        .ok_or_else(math_error!())?;
+
+    if expo >= 0 {
+        let oracle_scale_mult = MARK_PRICE_PRECISION
+            .checked_mul(oracle_precision)
+            .ok_or_else(math_error!())?;
+        return Ok((oracle_scale_mult, 1));
+    }
+
+    if oracle_precision > MARK_PRICE_PRECISION {
+        let oracle_scale_div = oracle_precision
+            .checked_div(MARK_PRICE_PRECISION)
+            .ok_or_else(math_error!())?;
+        Ok((1, oracle_scale_div))
+    } else {
+        let oracle_scale_mult = MARK_PRICE_PRECISION
+            .checked_div(oracle_precision)
+            .ok_or_else(math_error!())?;
+        Ok((oracle_scale_mult, 1))
+    }
+}
+
+/// Legacy pyth price accounts are owned by the pyth oracle program, PriceUpdateV2 accounts by the pyth receiver
+pub fn is_pyth_owner(owner: &Pubkey) -> bool {
+    owner == &pyth_program::id() || owner == &pyth_receiver_program::id()
+}
+
+/// Loads a pyth account of either layout, checking it is owned by the program that writes that layout
+fn load_pyth_price_account(price_oracle: &AccountInfo) -> ClearingHouseResult<PythPrice> {
+    let pyth_price_data = price_oracle
+        .try_borrow_data()
+        .or(Err(crate::error::ErrorCode::UnableToLoadOracle))?;
+
+    let expected_owner = if pyth_price_data.len() >= 8
+        && array_ref![pyth_price_data, 0, 8] == &PYTH_PRICE_UPDATE_DISCRIMINATOR
+    {
+        pyth_receiver_program::id()
+    } else {
+        pyth_program::id()
+    };
+    validate_oracle_owner(price_oracle, &expected_owner)?;
+
+    load_pyth_price(&pyth_price_data)
+}
+
+fn validate_oracle_owner(
+    price_oracle: &AccountInfo,
+    expected_owner: &Pubkey,
+) -> ClearingHouseResult {
+    if price_oracle.owner != expected_owner {
+        msg!(
+            "Oracle {} owned by {} not {}",
+            price_oracle.key,
+            price_oracle.owner,
+            expected_owner
+        );
+        return Err(ErrorCode::OracleAccountWrongOwner);
+    }
+
+    Ok(())
+}
+
+/// Index value maintained by its authority, read with OracleSource::Index
+#[account(zero_copy)]
+#[derive(Default)]
+#[repr(packed)]
+pub struct IndexPrice {
+    pub authority: Pubkey,
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub valid_slot: u64,
+}
+
+pub fn is_index_price_account(data: &[u8]) -> bool {
+    data.len() >= 8 && array_ref![data, 0, 8] == &IndexPrice::discriminator()
+}
+
+/// IndexPrice layout (packed):
+/// discriminator (8), authority (32), price (8), conf (8), expo (4), valid_slot (8)
+fn load_index_price(data: &[u8]) -> ClearingHouseResult<PythPrice> {
+    if !is_index_price_account(data) || data.len() < 8 + std::mem::size_of::<IndexPrice>() {
+        msg!("Index account does not match the IndexPrice layout");
+        return Err(ErrorCode::InvalidOracle);
+    }
+
+    let price = i64::from_le_bytes(*array_ref![data, 40, 8]);
+
+    Ok(PythPrice {
+        price,
+        conf: u64::from_le_bytes(*array_ref![data, 48, 8]),
+        expo: i32::from_le_bytes(*array_ref![data, 56, 4]),
+        valid_slot: u64::from_le_bytes(*array_ref![data, 60, 8]),
+        twap: price,
+    })
+}
+
+/// The fields we read from a pyth account, independent of the account layout
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub valid_slot: u64,
+    pub twap: i64, // pyth's ema price
+}
+
+// sha256("account:PriceUpdateV2")[..8]
+pub const PYTH_PRICE_UPDATE_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+/// Loads either a legacy pyth price account (`pyth_client::Price`) or a pyth price feed account (`PriceUpdateV2`)
+pub fn load_pyth_price(data: &[u8]) -> ClearingHouseResult<PythPrice> {
+    if data.len() >= std::mem::size_of::<pyth_client::Price>()
+        && u32::from_le_bytes(*array_ref![data, 0, 4]) == pyth_client::MAGIC
+        && u32::from_le_bytes(*array_ref![data, 4, 4]) == pyth_client::VERSION_2
+    {
+        let price_data = pyth_client::cast::<pyth_client::Price>(data);
+        return Ok(PythPrice {
+            price: price_data.agg.price,
+            conf: price_data.agg.conf,
+            expo: price_data.expo,
+            valid_slot: price_data.valid_slot,
+            twap: price_data.twap.val,
+        });
+    }
+
+    if data.len() >= 8 && array_ref![data, 0, 8] == &PYTH_PRICE_UPDATE_DISCRIMINATOR {
+        return load_pyth_price_update(data);
+    }
+
+    msg!("Pyth account does not match a known layout");
+    Err(ErrorCode::InvalidOracle)
+}
+
+/// PriceUpdateV2 layout:
+/// discriminator (8), write_authority (32), verification_level (1 for Full, 2 for Partial),
+/// feed_id (32), price (8), conf (8), exponent (4), publish_time (8), prev_publish_time (8),
+/// ema_price (8), ema_conf (8), posted_slot (8)
+fn load_pyth_price_update(data: &[u8]) -> ClearingHouseResult<PythPrice> {
+    let verification_level_offset = 8 + 32;
+    let verification_level_size = match data.get(verification_level_offset) {
+        Some(0) => 2, // Partial { num_signatures: u8 }
+        Some(1) => 1, // Full
+        _ => {
+            msg!("Invalid pyth price feed verification level");
+            return Err(ErrorCode::InvalidOracle);
+        }
+    };
+
+    let message_offset = verification_level_offset + verification_level_size;
+    let price_offset = message_offset + 32;
+    let posted_slot_offset = price_offset + 8 + 8 + 4 + 8 + 8 + 8 + 8;
+    if data.len() < posted_slot_offset + 8 {
+        msg!("Pyth price feed account too small");
+        return Err(ErrorCode::InvalidOracle);
+    }
+
+    let price = i64::from_le_bytes(*array_ref![data, price_offset, 8]);
+    let conf = u64::from_le_bytes(*array_ref![data, price_offset + 8, 8]);
+    let expo = i32::from_le_bytes(*array_ref![data, price_offset + 16, 4]);
+    let ema_price = i64::from_le_bytes(*array_ref![data, price_offset + 36, 8]);
+    let posted_slot = u64::from_le_bytes(*array_ref![data, posted_slot_offset, 8]);
+
+    Ok(PythPrice {
+        price,
+        conf,
+        expo,
+        valid_slot: posted_slot,
+        twap: ema_price,
     })
 }
 
@@ -103,6 +553,20 @@ pub fn get_switchboard_price(
     price_oracle: &AccountInfo,
     clock_slot: u64,
 ) -> ClearingHouseResult<OraclePriceData> {
+    get_switchboard_price_with_max_confidence(
+        price_oracle,
+        clock_slot,
+        DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS,
+    )
+}
+
+pub fn get_switchboard_price_with_max_confidence(
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+    max_confidence_bps: u128,
+) -> ClearingHouseResult<OraclePriceData> {
+    validate_oracle_owner(price_oracle, &switchboard_program::id())?;
+
     let aggregator_data = AggregatorAccountData::new(price_oracle)
         .or(Err(crate::error::ErrorCode::UnableToLoadOracle))?;
 
@@ -127,15 +591,97 @@ pub fn get_switchboard_price(
         )?)
         .ok_or_else(math_error!())?;
 
-    let has_sufficient_number_of_data_points =
-        aggregator_data.latest_confirmed_round.num_success >= aggregator_data.min_oracle_results;
+    let is_round_stale = delay > SWITCHBOARD_MAX_ROUND_AGE_SLOTS;
 
-    Ok(OraclePriceData {
+    let has_sufficient_number_of_data_points = !is_round_stale
+        && aggregator_data.latest_confirmed_round.num_success >= aggregator_data.min_oracle_results;
+
+    // confidence_bps saturates, so the u128::MAX negative std deviation marker is flagged too
+    OraclePriceData {
         price,
         confidence,
         delay,
         has_sufficient_number_of_data_points,
-    })
+    }
+    .sanitized()?
+    .with_published_confidence()?
+    .with_max_confidence_bps(max_confidence_bps)
+}
+
+/// Chainlink store transmissions account layout (packed):
+/// discriminator (8), header (192): version (1), state (1), owner (32), proposed_owner (32), writer (32),
+/// description (32), decimals (1), flagging_threshold (4), latest_round_id (4), granularity (1), live_length (4),
+/// live_cursor (4), historical_cursor (4), ...
+/// followed by a ring buffer of live_length transmissions (48): slot (8), timestamp (4), padding (4), answer (16), padding (16)
+const CHAINLINK_HEADER_SIZE: usize = 8 + 192;
+const CHAINLINK_TRANSMISSION_SIZE: usize = 48;
+
+pub fn get_chainlink_price(
+    price_oracle: &AccountInfo,
+    clock_slot: u64,
+) -> ClearingHouseResult<OraclePriceData> {
+    validate_oracle_owner(price_oracle, &chainlink_program::id())?;
+
+    let data = price_oracle
+        .try_borrow_data()
+        .or(Err(crate::error::ErrorCode::UnableToLoadOracle))?;
+
+    if data.len() < CHAINLINK_HEADER_SIZE {
+        msg!("Chainlink account does not match the transmissions layout");
+        return Err(ErrorCode::InvalidOracle);
+    }
+
+    let decimals = data[8 + 130];
+    let latest_round_id = u32::from_le_bytes(*array_ref![data, 8 + 135, 4]);
+    let live_length = u32::from_le_bytes(*array_ref![data, 8 + 140, 4]) as usize;
+    let live_cursor = u32::from_le_bytes(*array_ref![data, 8 + 144, 4]) as usize;
+
+    if live_length == 0 {
+        msg!("Chainlink feed has no transmissions");
+        return Err(ErrorCode::InvalidOracle);
+    }
+
+    // the cursor points at the next slot to write, the latest transmission is the one before it
+    let latest_index = (live_cursor + live_length - 1) % live_length;
+    let transmission_offset = CHAINLINK_HEADER_SIZE + latest_index * CHAINLINK_TRANSMISSION_SIZE;
+    if data.len() < transmission_offset + CHAINLINK_TRANSMISSION_SIZE {
+        msg!("Chainlink account does not match the transmissions layout");
+        return Err(ErrorCode::InvalidOracle);
+    }
+
+    let update_slot = u64::from_le_bytes(*array_ref![data, transmission_offset, 8]);
+    let answer = i128::from_le_bytes(*array_ref![data, transmission_offset + 16, 16]);
+
+    let chainlink_precision = 10_u128
+        .checked_pow(decimals as u32)
+        .ok_or_else(math_error!())?;
+    let price = if chainlink_precision > MARK_PRICE_PRECISION {
+        answer
+            .checked_div(cast(chainlink_precision / MARK_PRICE_PRECISION)?)
+            .ok_or_else(math_error!())?
+    } else {
+        answer
+            .checked_mul(cast(MARK_PRICE_PRECISION / chainlink_precision)?)
+            .ok_or_else(math_error!())?
+    };
+
+    // the feed doesn't publish a deviation, use the same 10bps of price floor as switchboard
+    let confidence = price
+        .unsigned_abs()
+        .checked_div(1000)
+        .ok_or_else(math_error!())?;
+
+    let delay: i64 = cast_to_i64(clock_slot)?
+        .checked_sub(cast(update_slot)?)
+        .ok_or_else(math_error!())?;
+
+    OraclePriceData {
+        price,
+        confidence,
+        delay,
+        has_sufficient_number_of_data_points: latest_round_id > 0,
+    }
+    .sanitized()
 }
 
 /// Given a decimal number represented as a mantissa (the digits) plus an
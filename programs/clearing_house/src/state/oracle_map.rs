@@ -1,7 +1,11 @@
 use crate::error::{ClearingHouseResult, ErrorCode};
-use crate::ids::pyth_program;
-use crate::math::constants::MARK_PRICE_PRECISION_I128;
-use crate::state::oracle::{get_oracle_price, OraclePriceData, OracleSource};
+use crate::ids::{chainlink_program, switchboard_program};
+use crate::state::market::AMM;
+use crate::state::oracle::{
+    get_oracle_price, get_oracle_price_checked, get_oracle_price_contributors,
+    get_oracle_price_median, get_oracle_price_with_bounds, get_quote_asset_price,
+    is_index_price_account, is_pyth_owner, OraclePriceData, OracleSource,
+};
 use anchor_lang::prelude::{AccountInfo, Pubkey};
 use anchor_lang::Key;
 use std::collections::BTreeMap;
@@ -18,7 +22,6 @@ pub struct OracleMap<'a> {
     oracles: BTreeMap<Pubkey, AccountInfoAndOracleSource<'a>>,
     price_data: BTreeMap<Pubkey, OraclePriceData>,
     slot: u64,
-    quote_asset_price_data: OraclePriceData,
 }
 
 impl<'a> OracleMap<'a> {
@@ -36,31 +39,121 @@ impl<'a> OracleMap<'a> {
     }
 
     pub fn get_price_data(&mut self, pubkey: &Pubkey) -> ClearingHouseResult<&OraclePriceData> {
-        if pubkey == &Pubkey::default() {
-            return Ok(&self.quote_asset_price_data);
-        }
-
         if self.price_data.contains_key(pubkey) {
             return Ok(self.price_data.get(pubkey).unwrap());
         }
 
-        let (account_info, oracle_source) = match self.oracles.get(pubkey) {
-            Some(AccountInfoAndOracleSource {
-                account_info,
-                oracle_source,
-            }) => (account_info, oracle_source),
-            None => {
-                return Err(ErrorCode::OracleNotFound);
-            }
-        };
+        // the quote asset without a backing feed is pegged
+        let price_data = if pubkey == &Pubkey::default() {
+            get_quote_asset_price(None, self.slot)?
+        } else {
+            let (account_info, oracle_source) = match self.oracles.get(pubkey) {
+                Some(AccountInfoAndOracleSource {
+                    account_info,
+                    oracle_source,
+                }) => (account_info, oracle_source),
+                None => {
+                    return Err(ErrorCode::OracleNotFound);
+                }
+            };
 
-        let price_data = get_oracle_price(oracle_source, account_info, self.slot)?;
+            get_oracle_price(oracle_source, account_info, self.slot)?
+        };
 
         self.price_data.insert(*pubkey, price_data);
 
         Ok(self.price_data.get(pubkey).unwrap())
     }
 
+    /// The oracle's price data as it would read at slot, e.g. to see how far off a reading is from going stale
+    /// Not cached, unlike get_price_data
+    pub fn get_price_data_at_slot(
+        &self,
+        pubkey: &Pubkey,
+        slot: u64,
+    ) -> ClearingHouseResult<OraclePriceData> {
+        if pubkey == &Pubkey::default() {
+            return get_quote_asset_price(None, slot);
+        }
+
+        let AccountInfoAndOracleSource {
+            account_info,
+            oracle_source,
+        } = self.oracles.get(pubkey).ok_or(ErrorCode::OracleNotFound)?;
+
+        get_oracle_price(oracle_source, account_info, slot)
+    }
+
+    /// The oracle's price data, rejected with StaleOracle if it's more than max_delay slots old
+    /// Not cached, unlike get_price_data
+    pub fn get_price_data_checked(
+        &self,
+        pubkey: &Pubkey,
+        max_delay: i64,
+    ) -> ClearingHouseResult<OraclePriceData> {
+        let AccountInfoAndOracleSource {
+            account_info,
+            oracle_source,
+        } = self.oracles.get(pubkey).ok_or(ErrorCode::OracleNotFound)?;
+
+        get_oracle_price_checked(oracle_source, account_info, self.slot, max_delay)
+    }
+
+    /// The oracle's price with its confidence band as (lower, mid, upper), so risk checks can take the
+    /// bound adverse to the account. Not cached, unlike get_price_data
+    pub fn get_price_with_bounds(
+        &self,
+        pubkey: &Pubkey,
+    ) -> ClearingHouseResult<(i128, i128, i128)> {
+        let AccountInfoAndOracleSource {
+            account_info,
+            oracle_source,
+        } = self.oracles.get(pubkey).ok_or(ErrorCode::OracleNotFound)?;
+
+        get_oracle_price_with_bounds(oracle_source, account_info, self.slot)
+    }
+
+    /// The quote asset's price, from the backing feed quote_oracle (e.g. USDC/USD) so a depeg is reflected,
+    /// or pegged at 1 if quote_oracle is the default pubkey
+    pub fn get_quote_asset_price_data(
+        &self,
+        quote_oracle: &Pubkey,
+    ) -> ClearingHouseResult<OraclePriceData> {
+        if quote_oracle == &Pubkey::default() {
+            return get_quote_asset_price(None, self.slot);
+        }
+
+        let account_info = self.get_account_info(quote_oracle)?;
+        get_quote_asset_price(Some(&account_info), self.slot)
+    }
+
+    /// The price of the amm's oracle read with its market's oracle settings (AMM::get_oracle_price),
+    /// so margin prices a position the same way funding and fills do
+    pub fn get_amm_price_data(&self, amm: &AMM) -> ClearingHouseResult<OraclePriceData> {
+        let account_info = self.get_account_info(&amm.oracle)?;
+
+        amm.get_oracle_price(&account_info, self.slot)
+    }
+
+    /// The median price across every loaded oracle (get_oracle_price_median), for a market quoted off
+    /// several feeds of the same asset
+    pub fn get_median_price_data(&self) -> ClearingHouseResult<OraclePriceData> {
+        get_oracle_price_median(&self.get_sources(), self.slot)
+    }
+
+    /// The readings behind get_median_price_data, one per loaded oracle in pubkey order
+    pub fn get_contributor_price_data(&self) -> Vec<(OracleSource, Option<OraclePriceData>)> {
+        get_oracle_price_contributors(&self.get_sources(), self.slot)
+    }
+
+    /// Every loaded oracle with its source, in pubkey order
+    fn get_sources(&self) -> Vec<(OracleSource, &AccountInfo<'a>)> {
+        self.oracles
+            .values()
+            .map(|oracle| (oracle.oracle_source, &oracle.account_info))
+            .collect()
+    }
+
     pub fn load<'c>(
         account_info_iter: &'c mut Peekable<Iter<AccountInfo<'a>>>,
         slot: u64,
@@ -68,7 +161,7 @@ impl<'a> OracleMap<'a> {
         let mut oracles: BTreeMap<Pubkey, AccountInfoAndOracleSource<'a>> = BTreeMap::new();
 
         while let Some(account_info) = account_info_iter.peek() {
-            if account_info.owner == &pyth_program::id() {
+            if is_pyth_owner(account_info.owner) {
                 let account_info = account_info_iter.next().unwrap();
                 let pubkey = account_info.key();
                 oracles.insert(
@@ -82,6 +175,54 @@ impl<'a> OracleMap<'a> {
                 continue;
             }
 
+            if account_info.owner == &switchboard_program::id() {
+                let account_info = account_info_iter.next().unwrap();
+                let pubkey = account_info.key();
+                oracles.insert(
+                    pubkey,
+                    AccountInfoAndOracleSource {
+                        account_info: account_info.clone(),
+                        oracle_source: OracleSource::Switchboard,
+                    },
+                );
+
+                continue;
+            }
+
+            if account_info.owner == &chainlink_program::id() {
+                let account_info = account_info_iter.next().unwrap();
+                let pubkey = account_info.key();
+                oracles.insert(
+                    pubkey,
+                    AccountInfoAndOracleSource {
+                        account_info: account_info.clone(),
+                        oracle_source: OracleSource::Chainlink,
+                    },
+                );
+
+                continue;
+            }
+
+            if account_info.owner == &crate::id()
+                && is_index_price_account(
+                    &account_info
+                        .try_borrow_data()
+                        .or(Err(ErrorCode::UnableToLoadOracle))?,
+                )
+            {
+                let account_info = account_info_iter.next().unwrap();
+                let pubkey = account_info.key();
+                oracles.insert(
+                    pubkey,
+                    AccountInfoAndOracleSource {
+                        account_info: account_info.clone(),
+                        oracle_source: OracleSource::Index,
+                    },
+                );
+
+                continue;
+            }
+
             break;
         }
 
@@ -89,12 +230,6 @@ impl<'a> OracleMap<'a> {
             oracles,
             price_data: BTreeMap::new(),
             slot,
-            quote_asset_price_data: OraclePriceData {
-                price: MARK_PRICE_PRECISION_I128,
-                confidence: 1,
-                delay: 0,
-                has_sufficient_number_of_data_points: true,
-            },
         })
     }
 }
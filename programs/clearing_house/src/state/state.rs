@@ -31,8 +31,7 @@ pub struct State {
     pub number_of_banks: u64,
 
     // upgrade-ability
-    pub padding0: u128,
-    pub padding1: u128,
+    pub funding_block_guard_rails: FundingBlockGuardRails,
     pub padding2: u128,
     pub padding3: u128,
 }
@@ -50,6 +49,16 @@ pub struct PriceDivergenceGuardRails {
     pub mark_oracle_divergence_denominator: u128,
 }
 
+/// Mark/oracle divergence thresholds (in BID_ASK_SPREAD_PRECISION) for blocking funding rate updates.
+/// Once blocked, funding stays blocked until the divergence falls to the lower release threshold,
+/// so funding doesn't flicker on and off around a single threshold.
+/// If either is zero, funding uses the price_divergence guard rails without hysteresis
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct FundingBlockGuardRails {
+    pub block_divergence_pct: u128,
+    pub release_divergence_pct: u128,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct ValidityGuardRails {
     pub slots_before_stale: i64,
@@ -6,9 +6,18 @@ use solana_program::msg;
 
 use crate::controller::position::{get_position_index, PositionDirection};
 use crate::error::{ClearingHouseResult, ErrorCode};
-use crate::math::constants::QUOTE_ASSET_BANK_INDEX;
+use crate::math::casting::cast_to_i128;
+use crate::math::constants::{
+    BPS_PRECISION_I128, MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO_I128, QUOTE_ASSET_BANK_INDEX,
+};
+use crate::math::funding::{
+    calculate_funding_payment_in_quote_precision, calculate_position_funding_payment,
+    calculate_unsettled_funding,
+};
 use crate::math_error;
 use crate::state::bank::{BankBalance, BankBalanceType};
+use crate::state::market::{Market, AMM};
+use crate::state::market_map::MarketMap;
 
 #[account(zero_copy)]
 #[derive(Default)]
@@ -26,6 +35,7 @@ pub struct User {
     pub next_order_id: u64,
     pub positions: [MarketPosition; 5],
     pub orders: [Order; 32],
+    pub funding_payment_history: FundingPaymentHistory,
 }
 
 impl User {
@@ -81,6 +91,11 @@ impl User {
     ) -> ClearingHouseResult<&mut MarketPosition> {
         Ok(&mut self.positions[get_position_index(&self.positions, market_index)?])
     }
+
+    /// Net quote change (QUOTE_PRECISION) settle_funding_payment would apply across all positions, without mutating state
+    pub fn preview_funding_settlement(&self, market_map: &MarketMap) -> ClearingHouseResult<i128> {
+        calculate_unsettled_funding(self, market_map)
+    }
 }
 
 #[zero_copy]
@@ -117,6 +132,33 @@ impl BankBalance for UserBankBalance {
     }
 }
 
+/// The user's most recent funding payments, so they can be queried without relying on event retention
+/// Once full, each new payment overwrites the oldest
+#[zero_copy]
+#[derive(Default)]
+#[repr(packed)]
+pub struct FundingPaymentHistory {
+    pub head: u8, // index the next payment is written to
+    pub entries: [FundingPaymentHistoryEntry; 8],
+}
+
+impl FundingPaymentHistory {
+    pub fn push(&mut self, entry: FundingPaymentHistoryEntry) {
+        let head = self.head as usize % self.entries.len();
+        self.entries[head] = entry;
+        self.head = ((head + 1) % self.entries.len()) as u8;
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+#[repr(packed)]
+pub struct FundingPaymentHistoryEntry {
+    pub ts: i64,
+    pub market_index: u64,
+    pub funding_payment: i128, // QUOTE_PRECISION, negative when the user paid
+}
+
 #[zero_copy]
 #[derive(Default)]
 #[repr(packed)]
@@ -131,13 +173,13 @@ pub struct MarketPosition {
     pub unsettled_pnl: i128,
 
     // upgrade-ability
+    pub max_funding_per_period: u128, // QUOTE_PRECISION, zero means no cap
+    pub uncollected_funding: u128, // QUOTE_PRECISION, funding over the cap the position didn't pay
     pub padding0: u128,
     pub padding1: u128,
     pub padding2: u128,
     pub padding3: u128,
     pub padding4: u128,
-    pub padding5: u128,
-    pub padding6: u128,
 }
 
 impl MarketPosition {
@@ -161,6 +203,104 @@ impl MarketPosition {
     pub fn has_unsettled_pnl(&self) -> bool {
         self.unsettled_pnl != 0
     }
+
+    /// Funding received over the last funding period as bps of margin (margin in QUOTE_PRECISION)
+    /// Negative means the position paid funding
+    pub fn funding_as_margin_return_bps(
+        &self,
+        amm: &AMM,
+        margin: u128,
+    ) -> ClearingHouseResult<i128> {
+        if margin == 0 {
+            msg!("Can not calculate funding return on zero margin");
+            return Err(ErrorCode::MathError);
+        }
+
+        calculate_funding_payment_in_quote_precision(amm.last_funding_rate, self.base_asset_amount)?
+            .checked_mul(BPS_PRECISION_I128)
+            .ok_or_else(math_error!())?
+            .checked_div(cast_to_i128(margin)?)
+            .ok_or_else(math_error!())
+    }
+
+    /// Pnl (in QUOTE_PRECISION) of closing the position at exit_price (in MARK_PRICE_PRECISION), including the
+    /// funding that settles on close. Excludes pnl already in unsettled_pnl
+    pub fn realized_pnl_with_funding(
+        &self,
+        amm: &AMM,
+        exit_price: i128,
+    ) -> ClearingHouseResult<i128> {
+        if self.base_asset_amount == 0 {
+            return Ok(0);
+        }
+
+        // signed like the base, so a short's exit value is what it owes to buy back
+        let exit_value = self
+            .base_asset_amount
+            .checked_mul(exit_price)
+            .ok_or_else(math_error!())?
+            .checked_div(MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO_I128)
+            .ok_or_else(math_error!())?;
+
+        let entry_value = cast_to_i128(self.quote_asset_amount)?;
+        let price_pnl = if self.base_asset_amount > 0 {
+            exit_value.checked_sub(entry_value)
+        } else {
+            exit_value.checked_add(entry_value)
+        }
+        .ok_or_else(math_error!())?;
+
+        // capped by max_funding_per_period like the settlement on close
+        let funding_payment = calculate_position_funding_payment(self, amm)?
+            .map_or(0, |position_funding_payment| {
+                position_funding_payment.funding_payment
+            });
+
+        price_pnl
+            .checked_add(funding_payment)
+            .ok_or_else(math_error!())
+    }
+
+    /// Annualized funding carry of the position at the last funding rate, relative to the oracle twap (in BPS_PRECISION)
+    /// Positive means the position earns funding, negative means it pays
+    pub fn carry_apr_bps(&self, market: &Market) -> ClearingHouseResult<i128> {
+        if self.base_asset_amount == 0 {
+            return Ok(0);
+        }
+
+        // a positive funding rate means longs pay shorts
+        let funding_apr_bps = market.annualize_funding_rate_bps(market.amm.last_funding_rate)?;
+
+        if self.base_asset_amount > 0 {
+            funding_apr_bps.checked_neg().ok_or_else(math_error!())
+        } else {
+            Ok(funding_apr_bps)
+        }
+    }
+
+    /// Number of funding periods at the last funding rate before funding payments use up maintenance_margin,
+    /// the margin held above the maintenance requirement (in QUOTE_PRECISION)
+    /// Returns u64::MAX if the position receives (or doesn't pay) funding
+    pub fn periods_to_funding_liquidation(
+        &self,
+        amm: &AMM,
+        maintenance_margin: u128,
+    ) -> ClearingHouseResult<u64> {
+        let funding_payment = calculate_funding_payment_in_quote_precision(
+            amm.last_funding_rate,
+            self.base_asset_amount,
+        )?;
+
+        if funding_payment >= 0 {
+            return Ok(u64::MAX);
+        }
+
+        let periods = maintenance_margin
+            .checked_div(funding_payment.unsigned_abs())
+            .ok_or_else(math_error!())?;
+
+        Ok(min(periods, u64::MAX as u128) as u64)
+    }
 }
 
 pub type UserPositions = [MarketPosition; 5];
@@ -116,3 +116,18 @@ export async function getBankVaultAuthorityPublicKey(
 		)
 	)[0];
 }
+
+export async function getIndexPricePublicKey(
+	programId: PublicKey,
+	authority: PublicKey
+): Promise<PublicKey> {
+	return (
+		await anchor.web3.PublicKey.findProgramAddress(
+			[
+				Buffer.from(anchor.utils.bytes.utf8.encode('index_price')),
+				authority.toBuffer(),
+			],
+			programId
+		)
+	)[0];
+}
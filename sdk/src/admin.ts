@@ -7,6 +7,7 @@ import {
 } from '@solana/web3.js';
 import {
 	FeeStructure,
+	FundingBlockGuardRails,
 	IWallet,
 	OracleGuardRails,
 	OracleSource,
@@ -20,6 +21,7 @@ import {
 	getBankVaultAuthorityPublicKey,
 	getBankPublicKey,
 	getBankVaultPublicKey,
+	getIndexPricePublicKey,
 	getMarketPublicKey,
 	getOrderStateAccountPublicKeyAndNonce,
 } from './addresses/pda';
@@ -506,6 +508,136 @@ export class Admin extends ClearingHouse {
 		});
 	}
 
+	public async updateMarketFundingFrozen(
+		marketIndex: BN,
+		fundingFrozen: boolean
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketFundingFrozen(fundingFrozen, {
+			accounts: {
+				admin: this.wallet.publicKey,
+				state: await this.getStatePublicKey(),
+				market: await getMarketPublicKey(this.program.programId, marketIndex),
+			},
+		});
+	}
+
+	public async updateMarketAllowNegativeOracle(
+		marketIndex: BN,
+		allowNegativeOracle: boolean
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketAllowNegativeOracle(
+			allowNegativeOracle,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					market: await getMarketPublicKey(this.program.programId, marketIndex),
+				},
+			}
+		);
+	}
+
+	public async updateUserMaxFundingPerPeriod(
+		userAccountPublicKey: PublicKey,
+		marketIndex: BN,
+		maxFundingPerPeriod: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateUserMaxFundingPerPeriod(
+			marketIndex,
+			maxFundingPerPeriod,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					user: userAccountPublicKey,
+				},
+			}
+		);
+	}
+
+	public async initializeIndexPrice(
+		expo: number,
+		authority: PublicKey = this.wallet.publicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.initializeIndexPrice(expo, {
+			accounts: {
+				admin: this.wallet.publicKey,
+				state: await this.getStatePublicKey(),
+				indexPrice: await getIndexPricePublicKey(
+					this.program.programId,
+					authority
+				),
+				authority,
+				rent: SYSVAR_RENT_PUBKEY,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
+	public async updateMarketOracleEmaHalflife(
+		marketIndex: BN,
+		oracleEmaHalflife: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketOracleEmaHalflife(
+			oracleEmaHalflife,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					market: await getMarketPublicKey(this.program.programId, marketIndex),
+				},
+			}
+		);
+	}
+
+	public async updateMarketFundingRateEmaAlpha(
+		marketIndex: BN,
+		fundingRateEmaAlpha: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketFundingRateEmaAlpha(
+			fundingRateEmaAlpha,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					market: await getMarketPublicKey(this.program.programId, marketIndex),
+				},
+			}
+		);
+	}
+
+	public async updateMarketPythMaxConfidenceBps(
+		marketIndex: BN,
+		pythMaxConfidenceBps: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketPythMaxConfidenceBps(
+			pythMaxConfidenceBps,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					market: await getMarketPublicKey(this.program.programId, marketIndex),
+				},
+			}
+		);
+	}
+
+	public async updateMarketSwitchboardMaxConfidenceBps(
+		marketIndex: BN,
+		switchboardMaxConfidenceBps: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateMarketSwitchboardMaxConfidenceBps(
+			switchboardMaxConfidenceBps,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+					market: await getMarketPublicKey(this.program.programId, marketIndex),
+				},
+			}
+		);
+	}
+
 	public async updatePartialLiquidationClosePercentage(
 		numerator: BN,
 		denominator: BN
@@ -617,6 +749,31 @@ export class Admin extends ClearingHouse {
 		});
 	}
 
+	public async migrateMarket(marketIndex: BN): Promise<TransactionSignature> {
+		return await this.program.rpc.migrateMarket({
+			accounts: {
+				admin: this.wallet.publicKey,
+				state: await this.getStatePublicKey(),
+				market: await getMarketPublicKey(this.program.programId, marketIndex),
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
+	public async updateFundingBlockGuardRails(
+		fundingBlockGuardRails: FundingBlockGuardRails
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateFundingBlockGuardRails(
+			fundingBlockGuardRails,
+			{
+				accounts: {
+					admin: this.wallet.publicKey,
+					state: await this.getStatePublicKey(),
+				},
+			}
+		);
+	}
+
 	public async updateMarketOracle(
 		marketIndex: BN,
 		oracle: PublicKey,
@@ -11,6 +11,7 @@ import {
 	Order,
 	BankAccount,
 	UserBankBalance,
+	ViewParams,
 } from './types';
 import * as anchor from '@project-serum/anchor';
 import clearingHouseIDL from './idl/clearing_house.json';
@@ -30,6 +31,7 @@ import { EventEmitter } from 'events';
 import StrictEventEmitter from 'strict-event-emitter-types';
 import {
 	getClearingHouseStateAccountPublicKey,
+	getIndexPricePublicKey,
 	getMarketPublicKey,
 	getOrderStateAccountPublicKey,
 	getUserAccountPublicKey,
@@ -281,6 +283,34 @@ export class ClearingHouse {
 		return [userAccountPublicKey, initializeUserAccountIx];
 	}
 
+	/**
+	 * Resizes a user account created before the funding payment history was added to it
+	 * @param userId
+	 * @returns
+	 */
+	public async migrateUserAccount(
+		userId = this.userId
+	): Promise<TransactionSignature> {
+		const userAccountPublicKey = await getUserAccountPublicKey(
+			this.program.programId,
+			this.wallet.publicKey,
+			userId
+		);
+
+		const migrateUserIx = await this.program.instruction.migrateUser(userId, {
+			accounts: {
+				user: userAccountPublicKey,
+				authority: this.wallet.publicKey,
+				payer: this.wallet.publicKey,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+
+		const tx = new Transaction().add(migrateUserIx);
+		const { txSig } = await this.txSender.send(tx, [], this.opts);
+		return txSig;
+	}
+
 	userAccountPublicKey?: PublicKey;
 	/**
 	 * Get the address for the Clearing House User's account. NOT the user's wallet address.
@@ -369,7 +399,8 @@ export class ClearingHouse {
 				marketAccountMap.set(marketIndexNum, {
 					pubkey: marketAccount.pubkey,
 					isSigner: false,
-					isWritable: false,
+					// funding settlement adds rounding dust to the markets of open positions
+					isWritable: !position.baseAssetAmount.eq(ZERO),
 				});
 				oracleAccountMap.set(marketAccount.pubkey.toString(), {
 					pubkey: marketAccount.amm.oracle,
@@ -1092,7 +1123,7 @@ export class ClearingHouse {
 				);
 				marketAccountInfos.push({
 					pubkey: marketPublicKey,
-					isWritable: false,
+					isWritable: !position.baseAssetAmount.eq(ZERO),
 					isSigner: false,
 				});
 				oracleAccountInfos.push({
@@ -1484,6 +1515,26 @@ export class ClearingHouse {
 		});
 	}
 
+	/**
+	 * Publishes a new value to the index price account the wallet is the authority of
+	 * @param price in the index's expo, like a pyth price
+	 * @param conf in the index's expo
+	 */
+	public async updateIndexPrice(
+		price: BN,
+		conf: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateIndexPrice(price, conf, {
+			accounts: {
+				authority: this.wallet.publicKey,
+				indexPrice: await getIndexPricePublicKey(
+					this.program.programId,
+					this.wallet.publicKey
+				),
+			},
+		});
+	}
+
 	public async updateFundingRate(
 		oracle: PublicKey,
 		marketIndex: BN
@@ -1509,6 +1560,47 @@ export class ClearingHouse {
 		});
 	}
 
+	public async updateFundingRates(
+		marketIndexes: BN[]
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.txSender.send(
+			wrapInTx(await this.getUpdateFundingRatesIx(marketIndexes)),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getUpdateFundingRatesIx(
+		marketIndexes: BN[]
+	): Promise<TransactionInstruction> {
+		const oracleAccountMap = new Map<string, AccountMeta>();
+		const marketAccountMap = new Map<number, AccountMeta>();
+		for (const marketIndex of marketIndexes) {
+			const marketAccount = this.getMarketAccount(marketIndex);
+			marketAccountMap.set(marketIndex.toNumber(), {
+				pubkey: marketAccount.pubkey,
+				isSigner: false,
+				isWritable: true,
+			});
+			oracleAccountMap.set(marketAccount.amm.oracle.toString(), {
+				pubkey: marketAccount.amm.oracle,
+				isSigner: false,
+				isWritable: false,
+			});
+		}
+
+		return await this.program.instruction.updateFundingRates(marketIndexes, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+			},
+			remainingAccounts: [
+				...oracleAccountMap.values(),
+				...marketAccountMap.values(),
+			],
+		});
+	}
+
 	public async settleFundingPayment(
 		userAccount: PublicKey
 	): Promise<TransactionSignature> {
@@ -1538,7 +1630,7 @@ export class ClearingHouse {
 				);
 				remainingAccounts.push({
 					pubkey: marketPublicKey,
-					isWritable: false,
+					isWritable: !position.baseAssetAmount.eq(ZERO),
 					isSigner: false,
 				});
 			}
@@ -1553,6 +1645,117 @@ export class ClearingHouse {
 		});
 	}
 
+	public async settleFundingPayments(
+		userAccounts: PublicKey[]
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.txSender.send(
+			wrapInTx(await this.getSettleFundingPaymentsIx(userAccounts)),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getSettleFundingPaymentsIx(
+		userAccounts: PublicKey[]
+	): Promise<TransactionInstruction> {
+		const marketAccountMap = new Map<number, AccountMeta>();
+		for (const userAccount of userAccounts) {
+			const user = (await this.program.account.user.fetch(
+				userAccount
+			)) as UserAccount;
+
+			for (const position of user.positions) {
+				if (!position.baseAssetAmount.eq(ZERO)) {
+					marketAccountMap.set(position.marketIndex.toNumber(), {
+						pubkey: await getMarketPublicKey(
+							this.program.programId,
+							position.marketIndex
+						),
+						isSigner: false,
+						isWritable: true,
+					});
+				}
+			}
+		}
+
+		return await this.program.instruction.settleFundingPayments({
+			accounts: {
+				state: await this.getStatePublicKey(),
+			},
+			remainingAccounts: [
+				...marketAccountMap.values(),
+				...userAccounts.map((userAccount) => {
+					return {
+						pubkey: userAccount,
+						isSigner: false,
+						isWritable: true,
+					};
+				}),
+			],
+		});
+	}
+
+	/**
+	 * Simulates the read-only view instruction and returns the query's result
+	 * @param params : the query, e.g. { periodFundingFlow: { marketIndex } }
+	 * @param marketIndexes : markets the query reads, their oracles are passed too
+	 * @param oracles : additional oracles the query reads
+	 * @returns Promise<BN[]> : the result, in the precision of the method queried
+	 */
+	public async view(
+		params: ViewParams,
+		marketIndexes: BN[] = [],
+		oracles: PublicKey[] = [],
+		user?: PublicKey
+	): Promise<BN[]> {
+		const oracleAccountMap = new Map<string, AccountMeta>();
+		const marketAccountMap = new Map<number, AccountMeta>();
+		for (const oracle of oracles) {
+			oracleAccountMap.set(oracle.toString(), {
+				pubkey: oracle,
+				isSigner: false,
+				isWritable: false,
+			});
+		}
+		for (const marketIndex of marketIndexes) {
+			const marketAccount = this.getMarketAccount(marketIndex);
+			marketAccountMap.set(marketIndex.toNumber(), {
+				pubkey: marketAccount.pubkey,
+				isSigner: false,
+				isWritable: false,
+			});
+			oracleAccountMap.set(marketAccount.amm.oracle.toString(), {
+				pubkey: marketAccount.amm.oracle,
+				isSigner: false,
+				isWritable: false,
+			});
+		}
+
+		const remainingAccounts = [
+			...oracleAccountMap.values(),
+			...marketAccountMap.values(),
+		];
+		// a query about a user reads the user account after the markets
+		if (user) {
+			remainingAccounts.push({
+				pubkey: user,
+				isSigner: false,
+				isWritable: false,
+			});
+		}
+
+		const { events } = await this.program.simulate.view(params, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+			},
+			remainingAccounts,
+		});
+
+		return events.find((event) => event.name === 'ViewRecord').data
+			.result as BN[];
+	}
+
 	public triggerEvent(eventName: keyof ClearingHouseAccountEvents, data?: any) {
 		this.eventEmitter.emit(eventName, data);
 	}
@@ -1,8 +1,11 @@
 import { Commitment, TransactionSignature } from '@solana/web3.js';
 import {
 	DepositRecord,
+	FundingBatchRecord,
 	FundingPaymentRecord,
 	FundingRateRecord,
+	FundingSettlementRecord,
+	FundingUpdateSkippedRecord,
 	LiquidationRecord,
 	OrderRecord,
 	TradeRecord,
@@ -29,6 +32,9 @@ export const DefaultEventSubscriptionOptions: EventSubscriptionOptions = {
 		'LiquidationRecord',
 		'OrderRecord',
 		'FundingRateRecord',
+		'FundingUpdateSkippedRecord',
+		'FundingBatchRecord',
+		'FundingSettlementRecord',
 	],
 	maxEventsPerType: 4096,
 	orderBy: 'blockchain',
@@ -62,6 +68,9 @@ export type EventMap = {
 	LiquidationRecord: Event<LiquidationRecord>;
 	FundingRateRecord: Event<FundingRateRecord>;
 	OrderRecord: Event<OrderRecord>;
+	FundingUpdateSkippedRecord: Event<FundingUpdateSkippedRecord>;
+	FundingBatchRecord: Event<FundingBatchRecord>;
+	FundingSettlementRecord: Event<FundingSettlementRecord>;
 };
 
 export type EventType = keyof EventMap;
@@ -4,6 +4,7 @@ import { OracleClient } from '../oracles/types';
 import { PythClient } from '../oracles/pythClient';
 import { SwitchboardClient } from '../oracles/switchboardClient';
 import { QuoteAssetOracleClient } from '../oracles/quoteAssetOracleClient';
+import { IndexPriceClient } from '../oracles/indexPriceClient';
 
 export function getOracleClient(
 	oracleSource: OracleSource,
@@ -21,5 +22,9 @@ export function getOracleClient(
 		return new QuoteAssetOracleClient();
 	}
 
+	if (isVariant(oracleSource, 'index')) {
+		return new IndexPriceClient(connection);
+	}
+
 	throw new Error(`Unknown oracle source ${oracleSource}`);
 }
@@ -5,6 +5,7 @@ export * from './mockUSDCFaucet';
 export * from './oracles/types';
 export * from './oracles/pythClient';
 export * from './oracles/switchboardClient';
+export * from './oracles/indexPriceClient';
 export * from './types';
 export * from './constants/markets';
 export * from './accounts/fetch';
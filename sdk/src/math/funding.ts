@@ -2,6 +2,7 @@ import { BN } from '@project-serum/anchor';
 import {
 	AMM_RESERVE_PRECISION,
 	MARK_PRICE_PRECISION,
+	ONE,
 	QUOTE_PRECISION,
 	ZERO,
 } from '../constants/numericConstants';
@@ -303,3 +304,64 @@ export function calculateFundingPool(market: MarketAccount): BN {
 	);
 	return feePool;
 }
+
+/**
+ * Mirrors calculate_next_funding_time in the program: updates are rounded to the funding period boundary,
+ * and an update more than a third of a period late pushes the next one to the following boundary
+ *
+ * @param market
+ * @returns Earliest unix timestamp at which updateFundingRate will apply the next funding update
+ */
+export function calculateNextFundingTime(market: MarketAccount): BN {
+	const fundingPeriod = market.amm.fundingPeriod;
+	const lastFundingRateTs = market.amm.lastFundingRateTs;
+
+	let nextUpdateWait = fundingPeriod;
+	if (fundingPeriod.gt(ONE)) {
+		const lastUpdateDelay = lastFundingRateTs.umod(fundingPeriod);
+		if (!lastUpdateDelay.eq(ZERO)) {
+			const maxDelayForNextPeriod = fundingPeriod.div(new BN(3));
+			const twoFundingPeriods = fundingPeriod.mul(new BN(2));
+
+			if (lastUpdateDelay.gt(maxDelayForNextPeriod)) {
+				// too late for on the hour next period, delay to following period
+				nextUpdateWait = twoFundingPeriods.sub(lastUpdateDelay);
+			} else {
+				// allow update on the hour
+				nextUpdateWait = fundingPeriod.sub(lastUpdateDelay);
+			}
+
+			if (nextUpdateWait.gt(twoFundingPeriods)) {
+				nextUpdateWait = nextUpdateWait.sub(fundingPeriod);
+			}
+		}
+	}
+
+	return lastFundingRateTs.add(nextUpdateWait);
+}
+
+const FNV_PRIME = new BN('100000001b3', 16);
+
+/**
+ * Mirrors calculate_funding_rate_checksum in the program: FNV-1a over the little endian bytes of the funding rate
+ * (i128) and record id (u64), starting from the previous record's checksum
+ *
+ * @param previousChecksum
+ * @param fundingRate
+ * @param recordId
+ * @returns The checksum the next FundingRateRecord should carry, a gap in the records breaks the chain
+ */
+export function calculateFundingRateChecksum(
+	previousChecksum: BN,
+	fundingRate: BN,
+	recordId: BN
+): BN {
+	const bytes = [
+		...fundingRate.toTwos(128).toArray('le', 16),
+		...recordId.toArray('le', 8),
+	];
+	return bytes.reduce(
+		(checksum, byte) => checksum.xor(new BN(byte)).mul(FNV_PRIME).maskn(64),
+		previousChecksum
+	);
+}
@@ -0,0 +1,41 @@
+import { Connection, PublicKey } from '@solana/web3.js';
+import { OracleClient, OraclePriceData } from './types';
+import { BN } from '@project-serum/anchor';
+import { MARK_PRICE_PRECISION, TEN } from '../constants/numericConstants';
+
+/**
+ * Reads an IndexPrice account (OracleSource.INDEX), which the program scales the same way as pyth
+ * Layout (packed): discriminator (8), authority (32), price (8), conf (8), expo (4), valid_slot (8)
+ */
+export class IndexPriceClient implements OracleClient {
+	private connection: Connection;
+
+	public constructor(connection: Connection) {
+		this.connection = connection;
+	}
+
+	public async getOraclePriceData(
+		pricePublicKey: PublicKey
+	): Promise<OraclePriceData> {
+		const accountInfo = await this.connection.getAccountInfo(pricePublicKey);
+		return this.getOraclePriceDataFromBuffer(accountInfo.data);
+	}
+
+	public getOraclePriceDataFromBuffer(buffer: Buffer): OraclePriceData {
+		const price = new BN(buffer.subarray(40, 48), 'le').fromTwos(64);
+		const conf = new BN(buffer.subarray(48, 56), 'le');
+		const expo = buffer.readInt32LE(56);
+		const validSlot = new BN(buffer.subarray(60, 68), 'le');
+
+		const indexPrecision = TEN.pow(new BN(Math.abs(expo)));
+		const scaledPrice = price.mul(MARK_PRICE_PRECISION).div(indexPrecision);
+		return {
+			price: scaledPrice,
+			slot: validSlot,
+			confidence: conf.mul(MARK_PRICE_PRECISION).div(indexPrecision),
+			// the index has no separate twap, the program uses the price
+			twap: scaledPrice,
+			hasSufficientNumberOfDataPoints: true,
+		};
+	}
+}
@@ -17,10 +17,17 @@ export class PositionDirection {
 	static readonly SHORT = { short: {} };
 }
 
+export class PriceRounding {
+	static readonly TRUNCATE = { truncate: {} };
+	static readonly HALF_UP = { halfUp: {} };
+}
+
 export class OracleSource {
 	static readonly PYTH = { pyth: {} };
 	static readonly SWITCHBOARD = { switchboard: {} };
 	static readonly QUOTE_ASSET = { quoteAsset: {} };
+	static readonly INDEX = { index: {} };
+	static readonly CHAINLINK = { chainlink: {} };
 }
 
 export class OrderType {
@@ -55,6 +62,13 @@ export class OrderTriggerCondition {
 	static readonly BELOW = { below: {} };
 }
 
+export class FundingUpdateSkipReason {
+	static readonly ORACLE_INVALID = { oracleInvalid: {} };
+	static readonly FUNDING_PAUSED = { fundingPaused: {} };
+	static readonly ORACLE_PRICE_ZERO = { oraclePriceZero: {} };
+	static readonly TOO_SOON = { tooSoon: {} };
+}
+
 export function isVariant(object: unknown, type: string) {
 	return object.hasOwnProperty(type);
 }
@@ -141,6 +155,39 @@ export type FundingRateRecord = {
 	cumulativeFundingRateShort: BN;
 	oraclePriceTwap: BN;
 	markPriceTwap: BN;
+	oracleVolatilityBps: BN;
+	checksum: BN;
+	rawPriceSpread: BN;
+	clampedPriceSpread: BN;
+};
+
+export type FundingUpdateSkippedRecord = {
+	ts: BN;
+	marketIndex: BN;
+	reason: FundingUpdateSkipReason;
+};
+
+export type FundingRateSummary = {
+	recordId: BN;
+	marketIndex: BN;
+	fundingRate: BN;
+	cumulativeFundingRateLong: BN;
+	cumulativeFundingRateShort: BN;
+	oraclePriceTwap: BN;
+	markPriceTwap: BN;
+};
+
+export type FundingBatchRecord = {
+	ts: BN;
+	fundingRates: FundingRateSummary[];
+};
+
+export type FundingSettlementRecord = {
+	ts: BN;
+	numberOfUsers: BN;
+	totalFundingPayment: BN;
+	positionsIterated: BN;
+	positionsSettled: BN;
 };
 
 export type FundingPaymentRecord = {
@@ -155,6 +202,7 @@ export type FundingPaymentRecord = {
 	userLastFundingRateTs: BN;
 	ammCumulativeFundingLong: BN;
 	ammCumulativeFundingShort: BN;
+	fundingPaymentDust: BN;
 };
 
 export type LiquidationRecord = {
@@ -219,6 +267,7 @@ export type StateAccount = {
 	orderState: PublicKey;
 	numberOfMarkets: BN;
 	numberOfBanks: BN;
+	fundingBlockGuardRails: FundingBlockGuardRails;
 };
 
 export type OrderStateAccount = {
@@ -289,22 +338,68 @@ export type AMM = {
 	minimumQuoteAssetTradeSize: BN;
 	minimumBaseAssetTradeSize: BN;
 	lastOraclePrice: BN;
+	lastOracleNormalisedPrice: BN;
 	baseSpread: number;
+	longSpread: BN;
+	shortSpread: BN;
+	lastBidPriceTwap: BN;
+	lastAskPriceTwap: BN;
 	curveUpdateIntensity: number;
 	netBaseAssetAmount: BN;
 	quoteAssetAmountLong: BN;
 	quoteAssetAmountShort: BN;
 	terminalQuoteAssetReserve: BN;
+	fundingFrozen: boolean;
+	fundingUpdateCount: BN;
+	oracleSquaredReturnSum: BN;
+	previousFundingRate: BN;
+	oraclePriceMultiplier: BN;
+	oraclePriceDivisor: BN;
+	lastOpenInterestTwap: BN;
+	lastOpenInterestTwapTs: BN;
+	fundingRateChecksum: BN;
+	skewFactor: BN;
+	fundingBlocked: boolean;
+	fundingUseMicroprice: boolean;
+	oracleOutageRecoveryCount: number;
+	fundingClampDivisor: BN;
+	maxFundingRate: BN;
+	cumulativeFundingClampSuppression: BN;
+	allowNegativeOracle: boolean;
+	oraclePriceEma: BN;
+	oracleEmaHalflife: BN;
+	fundingUseOracleEma: boolean;
+	fundingRateEma: BN;
+	fundingRateEmaAlpha: BN;
+	lastFundingOraclePriceTwap: BN;
+	pythMaxConfidenceBps: BN;
+	fundingPositiveCount: number;
+	fundingNegativeCount: number;
+	fundingZeroCount: number;
+	fundingPaymentDust: BN;
+	usePythEmaPrice: boolean;
+	switchboardMaxConfidenceBps: BN;
+};
+
+export type IndexPriceAccount = {
+	authority: PublicKey;
+	price: BN;
+	conf: BN;
+	expo: number;
+	validSlot: BN;
 };
 
 // # User Account Types
 export type UserPosition = {
 	baseAssetAmount: BN;
 	lastCumulativeFundingRate: BN;
+	lastFundingRateTs: BN;
 	marketIndex: BN;
 	quoteAssetAmount: BN;
 	openOrders: BN;
 	unsettledPnl: BN;
+	maxFundingPerPeriod: BN;
+	uncollectedFunding: BN;
 };
 
 export type UserAccount = {
@@ -321,6 +416,18 @@ export type UserAccount = {
 	totalRefereeDiscount: BN;
 	positions: UserPosition[];
 	orders: Order[];
+	fundingPaymentHistory: FundingPaymentHistory;
+};
+
+export type FundingPaymentHistory = {
+	head: number;
+	entries: FundingPaymentHistoryEntry[];
+};
+
+export type FundingPaymentHistoryEntry = {
+	ts: BN;
+	marketIndex: BN;
+	fundingPayment: BN;
 };
 
 export type UserBankBalance = {
@@ -354,6 +461,9 @@ export type Order = {
 	oraclePriceOffset: BN;
 };
 
+// a query of the view instruction, e.g. { periodFundingFlow: { marketIndex } }
+export type ViewParams = Record<string, Record<string, unknown>>;
+
 export type OrderParams = {
 	orderType: OrderType;
 	userOrderId: number;
@@ -430,6 +540,11 @@ export type OracleGuardRails = {
 	useForLiquidations: boolean;
 };
 
+export type FundingBlockGuardRails = {
+	blockDivergencePct: BN;
+	releaseDivergencePct: BN;
+};
+
 export type OrderFillerRewardStructure = {
 	rewardNumerator: BN;
 	rewardDenominator: BN;
@@ -4,7 +4,7 @@ if [ "$1" != "--skip-build" ]
     cp target/idl/clearing_house.json sdk/src/idl/
 fi
 
-test_files=(bankDepositWithdraw.ts prepegMarketOrderBaseAssetAmount.ts clearingHouse.ts ordersWithSpread.ts order.ts orderReferrer.ts marketOrder.ts triggerOrders.ts stopLimits.ts userOrderId.ts makerOrder.ts roundInFavorBaseAsset.ts marketOrderBaseAssetAmount.ts oracleOffsetOrders.ts subaccounts.ts pyth.ts userAccount.ts admin.ts updateK.ts adminWithdraw.ts curve.ts fees.ts idempotentCurve.ts maxPositions.ts maxReserves.ts twapDivergenceLiquidation.ts oraclePnlLiquidation.ts whaleLiquidation.ts roundInFavor.ts minimumTradeSize.ts cappedSymFunding.ts)
+test_files=(bankDepositWithdraw.ts prepegMarketOrderBaseAssetAmount.ts clearingHouse.ts ordersWithSpread.ts order.ts orderReferrer.ts marketOrder.ts triggerOrders.ts stopLimits.ts userOrderId.ts makerOrder.ts roundInFavorBaseAsset.ts marketOrderBaseAssetAmount.ts oracleOffsetOrders.ts subaccounts.ts pyth.ts userAccount.ts admin.ts updateK.ts adminWithdraw.ts curve.ts fees.ts idempotentCurve.ts maxPositions.ts maxReserves.ts twapDivergenceLiquidation.ts oraclePnlLiquidation.ts whaleLiquidation.ts roundInFavor.ts minimumTradeSize.ts cappedSymFunding.ts fundingPayment.ts bidAskTwap.ts oracleEma.ts fundingViews.ts oracleViews.ts oracleVolatility.ts openInterestTwap.ts indexOracle.ts fundingBlockHysteresis.ts switchboardOutageRecovery.ts fundingCap.ts negativeOracle.ts pythMaxConfidence.ts)
 
 for test_file in ${test_files[@]}; do
   export ANCHOR_TEST_FILE=${test_file} && anchor test --skip-build || exit 1;
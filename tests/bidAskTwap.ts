@@ -0,0 +1,240 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import {
+	Admin,
+	BID_ASK_SPREAD_PRECISION,
+	BN,
+	EventSubscriber,
+	FUNDING_PAYMENT_PRECISION,
+	MARK_PRICE_PRECISION,
+	PositionDirection,
+	QUOTE_PRECISION,
+	OracleSource,
+	calculatePrice,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('bid ask twap', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	// 500 / BID_ASK_SPREAD_PRECISION, split evenly between the long and short spread
+	const baseSpread = 500;
+	let solUsd;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		solUsd = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[{ publicKey: solUsd, source: OracleSource.PYTH }]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			solUsd,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+		await clearingHouse.updateMarketBaseSpread(marketIndex, baseSpread);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('quotes the bid below and the ask above the mark', async () => {
+		// fills recompute the spreads and update the bid, ask and mark twaps
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(10)),
+			marketIndex
+		);
+		await clearingHouse.fetchAccounts();
+		const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+
+		assert(amm.longSpread.eq(new BN(baseSpread / 2)));
+		assert(amm.shortSpread.eq(new BN(baseSpread / 2)));
+		assert(amm.lastBidPriceTwap.lt(amm.lastMarkPriceTwap));
+		assert(amm.lastAskPriceTwap.gt(amm.lastMarkPriceTwap));
+	});
+
+	it('prices funding off the mid of the bid and ask twaps', async () => {
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 1, solUsd);
+
+		const txSig = await clearingHouse.updateFundingRate(solUsd, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingRateRecord =
+			eventSubscriber.getEventsArray('FundingRateRecord')[0];
+
+		await clearingHouse.fetchAccounts();
+		const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+
+		const midTwap = amm.lastBidPriceTwap
+			.add(amm.lastAskPriceTwap)
+			.div(new BN(2));
+		assert(fundingRateRecord.markPriceTwap.eq(midTwap));
+		// symmetric spreads leave the mid on the mark, up to rounding
+		assert(midTwap.sub(amm.lastMarkPriceTwap).abs().lte(new BN(2)));
+
+		assert(
+			fundingRateRecord.rawPriceSpread.eq(
+				midTwap.sub(fundingRateRecord.oraclePriceTwap)
+			)
+		);
+		// a 60s funding period pays the hourly share of the daily rate
+		assert(
+			fundingRateRecord.fundingRate.eq(
+				fundingRateRecord.clampedPriceSpread
+					.mul(FUNDING_PAYMENT_PRECISION)
+					.div(new BN(24))
+			)
+		);
+	});
+
+	it('spread bps for a couple of base spreads', async () => {
+		// the first is the market's, the second 2% split evenly
+		for (const [spread, expectedSpreadBps] of [
+			[baseSpread, 5],
+			[20000, 200],
+		]) {
+			await clearingHouse.updateMarketBaseSpread(marketIndex, spread);
+			// fills recompute the spreads from the base spread
+			await clearingHouse.openPosition(
+				PositionDirection.LONG,
+				QUOTE_PRECISION.mul(new BN(10)),
+				marketIndex
+			);
+			await clearingHouse.fetchAccounts();
+			const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+
+			const [spreadBps] = await clearingHouse.view(
+				{ spreadBps: { marketIndex } },
+				[marketIndex]
+			);
+
+			const markPrice = calculatePrice(
+				amm.baseAssetReserve,
+				amm.quoteAssetReserve,
+				amm.pegMultiplier
+			);
+			const askPrice = markPrice
+				.mul(BID_ASK_SPREAD_PRECISION.add(amm.longSpread))
+				.div(BID_ASK_SPREAD_PRECISION);
+			const bidPrice = markPrice
+				.mul(BID_ASK_SPREAD_PRECISION.sub(amm.shortSpread))
+				.div(BID_ASK_SPREAD_PRECISION);
+			const midPrice = askPrice.add(bidPrice).div(new BN(2));
+			assert(
+				spreadBps.eq(askPrice.sub(bidPrice).mul(new BN(10000)).div(midPrice))
+			);
+			// up to a bp of rounding
+			assert(spreadBps.sub(new BN(expectedSpreadBps)).abs().lte(new BN(1)));
+		}
+	});
+
+	it('microprice leans toward the thinner side', async () => {
+		// the 2% spread left by the test above
+		const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+		const markPrice = calculatePrice(
+			amm.baseAssetReserve,
+			amm.quoteAssetReserve,
+			amm.pegMultiplier
+		);
+		const askPrice = markPrice
+			.mul(BID_ASK_SPREAD_PRECISION.add(amm.longSpread))
+			.div(BID_ASK_SPREAD_PRECISION);
+		const bidPrice = markPrice
+			.mul(BID_ASK_SPREAD_PRECISION.sub(amm.shortSpread))
+			.div(BID_ASK_SPREAD_PRECISION);
+		const midPrice = askPrice.add(bidPrice).div(new BN(2));
+
+		const microprice = async (bidSize: number, askSize: number) => {
+			const [price] = await clearingHouse.view(
+				{
+					microprice: {
+						marketIndex,
+						bidSize: new BN(bidSize),
+						askSize: new BN(askSize),
+					},
+				},
+				[marketIndex]
+			);
+			return price;
+		};
+
+		// balanced (or no) size is the simple mid
+		assert((await microprice(1, 1)).eq(midPrice));
+		assert((await microprice(0, 0)).eq(midPrice));
+
+		// a thin ask pulls the price up toward it, a thin bid pulls it down
+		const thinAskMicroprice = await microprice(3, 1);
+		assert(
+			thinAskMicroprice.eq(bidPrice.add(askPrice.mul(new BN(3))).div(new BN(4)))
+		);
+		assert(thinAskMicroprice.gt(midPrice));
+		assert(thinAskMicroprice.lt(askPrice));
+
+		const thinBidMicroprice = await microprice(1, 3);
+		assert(thinBidMicroprice.lt(midPrice));
+		assert(thinBidMicroprice.gt(bidPrice));
+	});
+});
@@ -9,6 +9,7 @@ import {
 	mockUSDCMint,
 	setFeedPrice,
 	initializeQuoteAssetBank,
+	waitForNextFundingTime,
 } from './testHelpers';
 import {
 	Admin,
@@ -37,7 +38,7 @@ async function updateFundingRateHelper(
 	prices: Array<number>
 ) {
 	for (let i = 0; i < prices.length; i++) {
-		await new Promise((r) => setTimeout(r, 1000)); // wait 1 second
+		await waitForNextFundingTime(clearingHouse, marketIndex);
 
 		const newprice = prices[i];
 		await setFeedPrice(anchor.workspace.Pyth, newprice, priceFeedAddress);
@@ -184,7 +185,7 @@ async function cappedSymFundingScenario(
 	fees = 0
 ) {
 	const priceFeedAddress = await mockOracle(priceAction[1], -10);
-	const periodicity = new BN(0);
+	const periodicity = new BN(60);
 
 	await clearingHouse.initializeMarket(
 		priceFeedAddress,
@@ -0,0 +1,129 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BID_ASK_SPREAD_PRECISION,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	isVariant,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	setFeedPrice,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('funding block hysteresis', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const marketIndex = new BN(0);
+	let oracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+
+		oracle = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[{ publicKey: oracle, source: OracleSource.PYTH }]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		// the mark stays at 1, only the oracle moves
+		await clearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+
+		// block above 10% divergence, release below 5%
+		await clearingHouse.updateFundingBlockGuardRails({
+			blockDivergencePct: BID_ASK_SPREAD_PRECISION.div(new BN(10)),
+			releaseDivergencePct: BID_ASK_SPREAD_PRECISION.div(new BN(20)),
+		});
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	// returns whether the update was blocked, checking it against the amm's funding_blocked
+	const updateFundingRateAtOraclePrice = async (oraclePrice: number) => {
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, oraclePrice, oracle);
+		const txSig = await clearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+
+		const blocked = eventSubscriber
+			.getEventsArray('FundingUpdateSkippedRecord')
+			.some(
+				(record) =>
+					record.txSig === txSig && isVariant(record.reason, 'oracleInvalid')
+			);
+		const updated = eventSubscriber
+			.getEventsArray('FundingRateRecord')
+			.some((record) => record.txSig === txSig);
+		assert(blocked !== updated);
+
+		await clearingHouse.fetchAccounts();
+		assert(
+			clearingHouse.getMarketAccount(marketIndex).amm.fundingBlocked === blocked
+		);
+		return blocked;
+	};
+
+	it('stays blocked until the divergence falls below the release threshold', async () => {
+		// 15% divergence blocks
+		assert(await updateFundingRateAtOraclePrice(0.85));
+		// 7% is under the block threshold but above the release one, so funding stays blocked
+		assert(await updateFundingRateAtOraclePrice(0.93));
+		// 3% releases it
+		assert(!(await updateFundingRateAtOraclePrice(0.97)));
+		// once released, 7% no longer blocks
+		assert(!(await updateFundingRateAtOraclePrice(0.93)));
+	});
+});
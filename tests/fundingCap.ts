@@ -0,0 +1,197 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PositionDirection,
+	QUOTE_PRECISION,
+	ZERO,
+} from '../sdk/src';
+
+import {
+	expectedFundingPayment,
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+// MIN_FUNDING_PERIOD, the shortest period update_funding_rate accepts
+const FUNDING_PERIOD = new BN(60);
+
+describe('funding cap', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+	let user: PublicKey;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	let oracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		oracle = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[{ publicKey: oracle, source: OracleSource.PYTH }]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			FUNDING_PERIOD
+		);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+		user = await clearingHouse.getUserAccountPublicKey();
+
+		// the only position is long, so it pays funding while the mark is above the oracle
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(100)),
+			marketIndex
+		);
+		await setFeedPrice(anchor.workspace.Pyth, 0.98, oracle);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	const getPosition = async () => {
+		await clearingHouse.fetchAccounts();
+		return clearingHouse
+			.getUserAccount()
+			.positions.find((position) => position.marketIndex.eq(marketIndex));
+	};
+
+	// cranks a funding update and settles it, returning the uncapped payment and the settled record
+	const updateAndSettleFunding = async () => {
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		let txSig = await clearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+
+		const position = await getPosition();
+		const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+		const uncappedFundingPayment = expectedFundingPayment(
+			amm.cumulativeFundingRateLong.sub(position.lastCumulativeFundingRate),
+			position.baseAssetAmount
+		);
+		const periods = BN.max(
+			new BN(1),
+			amm.lastFundingRateTs.sub(position.lastFundingRateTs).div(FUNDING_PERIOD)
+		);
+
+		txSig = await clearingHouse.settleFundingPayment(user);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingPaymentRecord = eventSubscriber.getEventsArray(
+			'FundingPaymentRecord'
+		)[0];
+		assert(fundingPaymentRecord.user.equals(user));
+
+		return { uncappedFundingPayment, periods, fundingPaymentRecord };
+	};
+
+	it('routes funding over the cap to uncollected funding', async () => {
+		// far below the funding a $100 position pays per period
+		const maxFundingPerPeriod = new BN(100);
+		await clearingHouse.updateUserMaxFundingPerPeriod(
+			user,
+			marketIndex,
+			maxFundingPerPeriod
+		);
+
+		const { uncappedFundingPayment, periods, fundingPaymentRecord } =
+			await updateAndSettleFunding();
+		const maxFundingPayment = maxFundingPerPeriod.mul(periods);
+		assert(uncappedFundingPayment.abs().gt(maxFundingPayment));
+		assert(fundingPaymentRecord.fundingPayment.eq(maxFundingPayment.neg()));
+
+		const position = await getPosition();
+		assert(position.maxFundingPerPeriod.eq(maxFundingPerPeriod));
+		assert(
+			position.uncollectedFunding.eq(
+				uncappedFundingPayment.abs().sub(maxFundingPayment)
+			)
+		);
+	});
+
+	it('collects all the funding while under the cap', async () => {
+		const uncollectedFundingBefore = (await getPosition()).uncollectedFunding;
+		await clearingHouse.updateUserMaxFundingPerPeriod(
+			user,
+			marketIndex,
+			QUOTE_PRECISION
+		);
+
+		const { uncappedFundingPayment, fundingPaymentRecord } =
+			await updateAndSettleFunding();
+		assert(uncappedFundingPayment.lt(ZERO));
+		assert(fundingPaymentRecord.fundingPayment.eq(uncappedFundingPayment));
+
+		const position = await getPosition();
+		assert(position.uncollectedFunding.eq(uncollectedFundingBefore));
+	});
+
+	it('clears the cap and uncollected funding once the position is closed', async () => {
+		await clearingHouse.closePosition(marketIndex);
+
+		const position = await getPosition();
+		assert(position.baseAssetAmount.eq(ZERO));
+		assert(position.maxFundingPerPeriod.eq(ZERO));
+		assert(position.uncollectedFunding.eq(ZERO));
+	});
+});
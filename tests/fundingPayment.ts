@@ -0,0 +1,698 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { Keypair, PublicKey } from '@solana/web3.js';
+
+import {
+	AMM_TO_QUOTE_PRECISION_RATIO,
+	Admin,
+	BN,
+	ClearingHouse,
+	ClearingHouseUser,
+	EventSubscriber,
+	FundingPaymentRecord,
+	MARK_PRICE_PRECISION,
+	FUNDING_PAYMENT_PRECISION,
+	PositionDirection,
+	QUOTE_PRECISION,
+	QUOTE_ASSET_BANK_INDEX,
+	ZERO,
+	isVariant,
+} from '../sdk/src';
+
+import {
+	expectedFundingPayment,
+	initializeQuoteAssetBank,
+	initUserAccounts,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+// MIN_FUNDING_PERIOD, the shortest period update_funding_rate accepts
+const FUNDING_PERIOD = new BN(60);
+
+describe('funding payment', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let longClearingHouse: Admin;
+	let longUser: ClearingHouseUser;
+
+	let shortClearingHouse: ClearingHouse;
+	let shortUser: ClearingHouseUser;
+
+	let usdcMint: Keypair;
+	let userUSDCAccount: Keypair;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetAmount = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetAmount = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(10000 * 10 ** 6);
+	const positionSize = QUOTE_PRECISION.mul(new BN(100));
+
+	let nextMarketIndex = 0;
+
+	async function initializeMarketWithPositions(): Promise<[BN, PublicKey]> {
+		const marketIndex = new BN(nextMarketIndex);
+		nextMarketIndex += 1;
+
+		const oracle = await mockOracle(1);
+		await longClearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetAmount,
+			ammInitialQuoteAssetAmount,
+			FUNDING_PERIOD
+		);
+
+		await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			positionSize,
+			marketIndex
+		);
+		await shortClearingHouse.fetchAccounts();
+		await shortClearingHouse.openPosition(
+			PositionDirection.SHORT,
+			positionSize,
+			marketIndex
+		);
+
+		return [marketIndex, oracle];
+	}
+
+	async function updateFundingRate(
+		marketIndex: BN,
+		oracle: PublicKey,
+		oraclePrice: number
+	) {
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		// refresh the feed after the wait so the update isn't skipped for a stale oracle
+		await setFeedPrice(anchor.workspace.Pyth, oraclePrice, oracle);
+
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		return eventSubscriber.getEventsArray('FundingRateRecord')[0];
+	}
+
+	async function settleFundingPayment(
+		clearingHouse: ClearingHouse,
+		user: ClearingHouseUser,
+		marketIndex: BN
+	) {
+		await user.fetchAccounts();
+		const unsettledPnlBefore = user.getUserPosition(marketIndex).unsettledPnl;
+
+		const txSig = await clearingHouse.settleFundingPayment(
+			await clearingHouse.getUserAccountPublicKey()
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingPaymentRecord = eventSubscriber.getEventsArray(
+			'FundingPaymentRecord'
+		)[0];
+
+		await user.fetchAccounts();
+		const unsettledPnlAfter = user.getUserPosition(marketIndex).unsettledPnl;
+
+		return {
+			fundingPaymentRecord,
+			unsettledPnlChange: unsettledPnlAfter.sub(unsettledPnlBefore),
+		};
+	}
+
+	before(async () => {
+		usdcMint = await mockUSDCMint(provider);
+		userUSDCAccount = await mockUserUSDCAccount(usdcMint, usdcAmount, provider);
+
+		const marketIndexes = [
+			new BN(0),
+			new BN(1),
+			new BN(2),
+			new BN(3),
+			new BN(4),
+			new BN(5),
+			new BN(6),
+		];
+		const bankIndexes = [new BN(0)];
+		longClearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			marketIndexes,
+			bankIndexes
+		);
+		await longClearingHouse.initialize(usdcMint.publicKey, true);
+		await longClearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(longClearingHouse, usdcMint.publicKey);
+
+		await longClearingHouse.initializeUserAccount();
+		longUser = ClearingHouseUser.from(
+			longClearingHouse,
+			provider.wallet.publicKey
+		);
+		await longUser.subscribe();
+
+		await longClearingHouse.deposit(
+			usdcAmount,
+			QUOTE_ASSET_BANK_INDEX,
+			userUSDCAccount.publicKey
+		);
+
+		const [_userUSDCAccounts, _userKeys, clearingHouses, userAccountInfos] =
+			await initUserAccounts(
+				1,
+				usdcMint,
+				usdcAmount,
+				provider,
+				marketIndexes,
+				bankIndexes,
+				[]
+			);
+		shortClearingHouse = clearingHouses[0];
+		shortUser = userAccountInfos[0];
+	});
+
+	after(async () => {
+		await longClearingHouse.unsubscribe();
+		await longUser.unsubscribe();
+		await shortClearingHouse.unsubscribe();
+		await shortUser.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('rejects a funding period of one second', async () => {
+		const oracle = await mockOracle(1);
+		try {
+			await longClearingHouse.initializeMarket(
+				oracle,
+				ammInitialBaseAssetAmount,
+				ammInitialQuoteAssetAmount,
+				new BN(1)
+			);
+			assert(false, 'initializeMarket should reject a one second period');
+		} catch (e) {
+			// InvalidFundingPeriod
+			assert(e.message.includes('0x17bd'));
+		}
+
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse.getStateAccount().numberOfMarkets.eq(ZERO),
+			'no market should have been initialized'
+		);
+	});
+
+	it('rejects a ten second funding period', async () => {
+		const oracle = await mockOracle(1);
+		try {
+			await longClearingHouse.initializeMarket(
+				oracle,
+				ammInitialBaseAssetAmount,
+				ammInitialQuoteAssetAmount,
+				new BN(10)
+			);
+			assert(false, 'initializeMarket should reject a ten second period');
+		} catch (e) {
+			// InvalidFundingPeriod
+			assert(e.message.includes('0x17bd'));
+		}
+
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse.getStateAccount().numberOfMarkets.eq(ZERO),
+			'no market should have been initialized'
+		);
+	});
+
+	it('rejects a zero and a two day twap window', async () => {
+		const oracle = await mockOracle(1);
+		// the funding period is the twap window, a zero period fails the funding period check first
+		for (const [fundingPeriod, errorCode] of [
+			[new BN(0), '0x17bd'],
+			[new BN(2 * 24 * 60 * 60), '0x17c0'],
+		] as [BN, string][]) {
+			try {
+				await longClearingHouse.initializeMarket(
+					oracle,
+					ammInitialBaseAssetAmount,
+					ammInitialQuoteAssetAmount,
+					fundingPeriod
+				);
+				assert(false, 'initializeMarket should reject the twap window');
+			} catch (e) {
+				assert(e.message.includes(errorCode));
+			}
+		}
+
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse.getStateAccount().numberOfMarkets.eq(ZERO),
+			'no market should have been initialized'
+		);
+	});
+
+	it('mark above oracle: longs pay, shorts receive', async () => {
+		const [marketIndex, oracle] = await initializeMarketWithPositions();
+
+		const fundingRateRecord = await updateFundingRate(
+			marketIndex,
+			oracle,
+			0.98
+		);
+		assert(
+			fundingRateRecord.markPriceTwap.gt(fundingRateRecord.oraclePriceTwap)
+		);
+
+		// a sub-hour funding period pays the hourly share of the daily rate (24 periods a day)
+		const expectedFundingRate = fundingRateRecord.markPriceTwap
+			.sub(fundingRateRecord.oraclePriceTwap)
+			.mul(FUNDING_PAYMENT_PRECISION)
+			.div(new BN(24));
+		assert(fundingRateRecord.fundingRate.eq(expectedFundingRate));
+
+		const long = await settleFundingPayment(
+			longClearingHouse,
+			longUser,
+			marketIndex
+		);
+		const longRecord = long.fundingPaymentRecord;
+		const longDelta = longRecord.ammCumulativeFundingLong.sub(
+			longRecord.userLastCumulativeFunding
+		);
+		assert(longRecord.baseAssetAmount.gt(ZERO));
+		assert(longDelta.gt(ZERO));
+		assert(longRecord.fundingPayment.lt(ZERO));
+		assert(long.unsettledPnlChange.eq(longRecord.fundingPayment));
+
+		const short = await settleFundingPayment(
+			shortClearingHouse,
+			shortUser,
+			marketIndex
+		);
+		const shortRecord = short.fundingPaymentRecord;
+		const shortDelta = shortRecord.ammCumulativeFundingShort.sub(
+			shortRecord.userLastCumulativeFunding
+		);
+		assert(shortRecord.baseAssetAmount.lt(ZERO));
+		assert(shortDelta.gt(ZERO));
+		assert(shortRecord.fundingPayment.gt(ZERO));
+		assert(short.unsettledPnlChange.eq(shortRecord.fundingPayment));
+	});
+
+	it('mark below oracle: longs receive, shorts pay', async () => {
+		const [marketIndex, oracle] = await initializeMarketWithPositions();
+
+		const fundingRateRecord = await updateFundingRate(
+			marketIndex,
+			oracle,
+			1.02
+		);
+		assert(
+			fundingRateRecord.markPriceTwap.lt(fundingRateRecord.oraclePriceTwap)
+		);
+		assert(fundingRateRecord.fundingRate.lt(ZERO));
+
+		const long = await settleFundingPayment(
+			longClearingHouse,
+			longUser,
+			marketIndex
+		);
+		const longRecord = long.fundingPaymentRecord;
+		const longDelta = longRecord.ammCumulativeFundingLong.sub(
+			longRecord.userLastCumulativeFunding
+		);
+		assert(longRecord.baseAssetAmount.gt(ZERO));
+		assert(longDelta.lt(ZERO));
+		assert(longRecord.fundingPayment.gt(ZERO));
+		assert(long.unsettledPnlChange.eq(longRecord.fundingPayment));
+
+		const short = await settleFundingPayment(
+			shortClearingHouse,
+			shortUser,
+			marketIndex
+		);
+		const shortRecord = short.fundingPaymentRecord;
+		const shortDelta = shortRecord.ammCumulativeFundingShort.sub(
+			shortRecord.userLastCumulativeFunding
+		);
+		assert(shortRecord.baseAssetAmount.lt(ZERO));
+		assert(shortDelta.lt(ZERO));
+		assert(shortRecord.fundingPayment.lt(ZERO));
+		assert(short.unsettledPnlChange.eq(shortRecord.fundingPayment));
+	});
+
+	it('funding payment records are in the documented precisions', async () => {
+		const fundingPaymentRecords = eventSubscriber.getEventsArray(
+			'FundingPaymentRecord'
+		);
+		assert(fundingPaymentRecords.length == 4);
+
+		for (const record of fundingPaymentRecords) {
+			// cumulative funding rates are in FUNDING_RATE_PRECISION, base in AMM_RESERVE_PRECISION,
+			// so the payment lands in QUOTE_PRECISION
+			const ammCumulativeFunding = record.baseAssetAmount.gt(ZERO)
+				? record.ammCumulativeFundingLong
+				: record.ammCumulativeFundingShort;
+			const fundingRateDelta = ammCumulativeFunding.sub(
+				record.userLastCumulativeFunding
+			);
+
+			assert(
+				record.fundingPayment.eq(
+					expectedFundingPayment(fundingRateDelta, record.baseAssetAmount)
+				)
+			);
+		}
+	});
+
+	it('frozen market: cumulative rates stop, accrued funding still settles', async () => {
+		const [marketIndex, oracle] = await initializeMarketWithPositions();
+		await updateFundingRate(marketIndex, oracle, 0.98);
+
+		await longClearingHouse.updateMarketFundingFrozen(marketIndex, true);
+		await longClearingHouse.fetchAccounts();
+		const frozenAmm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(frozenAmm.fundingFrozen);
+
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 0.98, oracle);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.marketIndex.eq(marketIndex));
+		assert(isVariant(skippedRecord.reason, 'fundingPaused'));
+
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(
+			amm.cumulativeFundingRateLong.eq(frozenAmm.cumulativeFundingRateLong)
+		);
+		assert(
+			amm.cumulativeFundingRateShort.eq(frozenAmm.cumulativeFundingRateShort)
+		);
+		assert(amm.lastFundingRateTs.eq(frozenAmm.lastFundingRateTs));
+
+		// the funding accrued before the freeze settles as usual
+		const long = await settleFundingPayment(
+			longClearingHouse,
+			longUser,
+			marketIndex
+		);
+		const longRecord = long.fundingPaymentRecord;
+		assert(
+			longRecord.ammCumulativeFundingLong.eq(
+				frozenAmm.cumulativeFundingRateLong
+			)
+		);
+		assert(longRecord.fundingPayment.lt(ZERO));
+		assert(long.unsettledPnlChange.eq(longRecord.fundingPayment));
+
+		const short = await settleFundingPayment(
+			shortClearingHouse,
+			shortUser,
+			marketIndex
+		);
+		const shortRecord = short.fundingPaymentRecord;
+		assert(
+			shortRecord.ammCumulativeFundingShort.eq(
+				frozenAmm.cumulativeFundingRateShort
+			)
+		);
+		assert(shortRecord.fundingPayment.gt(ZERO));
+		assert(short.unsettledPnlChange.eq(shortRecord.fundingPayment));
+
+		// the pause is the market's own, unfreezing it resumes funding without touching the exchange
+		assert(!longClearingHouse.getStateAccount().fundingPaused);
+		await longClearingHouse.updateMarketFundingFrozen(marketIndex, false);
+		const fundingRateRecord = await updateFundingRate(
+			marketIndex,
+			oracle,
+			0.98
+		);
+		assert(fundingRateRecord.marketIndex.eq(marketIndex));
+		await longClearingHouse.fetchAccounts();
+		const unfrozenAmm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(!unfrozenAmm.fundingFrozen);
+		assert(unfrozenAmm.lastFundingRateTs.gt(frozenAmm.lastFundingRateTs));
+		assert(
+			!unfrozenAmm.cumulativeFundingRateLong.eq(
+				frozenAmm.cumulativeFundingRateLong
+			)
+		);
+	});
+
+	it('batched funding update emits one record for both markets', async () => {
+		const [firstMarketIndex, firstOracle] =
+			await initializeMarketWithPositions();
+		const [secondMarketIndex, secondOracle] =
+			await initializeMarketWithPositions();
+
+		await waitForNextFundingTime(longClearingHouse, firstMarketIndex);
+		await waitForNextFundingTime(longClearingHouse, secondMarketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 0.98, firstOracle);
+		await setFeedPrice(anchor.workspace.Pyth, 1.02, secondOracle);
+
+		const fundingRateRecordCount =
+			eventSubscriber.getEventsArray('FundingRateRecord').length;
+
+		await longClearingHouse.fetchAccounts();
+		const txSig = await longClearingHouse.updateFundingRates([
+			firstMarketIndex,
+			secondMarketIndex,
+		]);
+		await eventSubscriber.awaitTx(txSig);
+
+		assert(
+			eventSubscriber.getEventsArray('FundingRateRecord').length ==
+				fundingRateRecordCount,
+			'a batched update should not emit per-market records'
+		);
+		const fundingBatchRecord =
+			eventSubscriber.getEventsArray('FundingBatchRecord')[0];
+		assert(fundingBatchRecord.fundingRates.length == 2);
+
+		await longClearingHouse.fetchAccounts();
+		for (const [i, marketIndex] of [
+			firstMarketIndex,
+			secondMarketIndex,
+		].entries()) {
+			const summary = fundingBatchRecord.fundingRates[i];
+			const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+			assert(summary.marketIndex.eq(marketIndex));
+			assert(summary.fundingRate.eq(amm.lastFundingRate));
+			assert(
+				summary.cumulativeFundingRateLong.eq(amm.cumulativeFundingRateLong)
+			);
+			assert(
+				summary.cumulativeFundingRateShort.eq(amm.cumulativeFundingRateShort)
+			);
+		}
+		assert(fundingBatchRecord.fundingRates[0].fundingRate.gt(ZERO));
+		assert(fundingBatchRecord.fundingRates[1].fundingRate.lt(ZERO));
+	});
+
+	it('settles funding for longs and shorts together, netting near zero', async () => {
+		const userAccounts = [
+			await longUser.getUserAccountPublicKey(),
+			await shortUser.getUserAccountPublicKey(),
+		];
+		// the batched update above left both users with unsettled funding
+		const txSig = await longClearingHouse.settleFundingPayments(userAccounts);
+		await eventSubscriber.awaitTx(txSig);
+
+		const fundingSettlementRecord = eventSubscriber.getEventsArray(
+			'FundingSettlementRecord'
+		)[0];
+		assert(fundingSettlementRecord.numberOfUsers.eq(new BN(2)));
+
+		const fundingPaymentRecords = eventSubscriber
+			.getEventsArray('FundingPaymentRecord')
+			.filter((record) => record.txSig === txSig);
+		assert(fundingPaymentRecords.length > 0);
+		let totalFundingPayment = ZERO;
+		let grossFundingPayment = ZERO;
+		for (const record of fundingPaymentRecords) {
+			totalFundingPayment = totalFundingPayment.add(record.fundingPayment);
+			grossFundingPayment = grossFundingPayment.add(
+				record.fundingPayment.abs()
+			);
+		}
+		assert(fundingSettlementRecord.totalFundingPayment.eq(totalFundingPayment));
+
+		// the users hold equal and opposite positions, so what one pays the other receives, up to rounding
+		assert(
+			fundingPaymentRecords.some((record) => record.fundingPayment.lt(ZERO))
+		);
+		assert(
+			fundingPaymentRecords.some((record) => record.fundingPayment.gt(ZERO))
+		);
+		assert(totalFundingPayment.abs().mul(new BN(100)).lt(grossFundingPayment));
+	});
+
+	it('settlement stats count the open positions and those settled', async () => {
+		await longUser.fetchAccounts();
+		await shortUser.fetchAccounts();
+		const openPositions = [longUser, shortUser]
+			.flatMap((user) => user.getUserAccount().positions)
+			.filter((position) => !position.baseAssetAmount.eq(ZERO)).length;
+		assert(openPositions > 0);
+
+		const [marketIndex, oracle] = await initializeMarketWithPositions();
+		await updateFundingRate(marketIndex, oracle, 0.98);
+
+		const userAccounts = [
+			await longUser.getUserAccountPublicKey(),
+			await shortUser.getUserAccountPublicKey(),
+		];
+		const settle = async () => {
+			const txSig = await longClearingHouse.settleFundingPayments(userAccounts);
+			await eventSubscriber.awaitTx(txSig);
+			return eventSubscriber.getEventsArray('FundingSettlementRecord')[0];
+		};
+
+		// everything settled before, so only the new market's two positions have funding to settle
+		const positionsIterated = new BN(openPositions + 2);
+		let fundingSettlementRecord = await settle();
+		assert(fundingSettlementRecord.positionsIterated.eq(positionsIterated));
+		assert(fundingSettlementRecord.positionsSettled.eq(new BN(2)));
+
+		// settling again finds nothing new
+		fundingSettlementRecord = await settle();
+		assert(fundingSettlementRecord.positionsIterated.eq(positionsIterated));
+		assert(fundingSettlementRecord.positionsSettled.eq(ZERO));
+		assert(fundingSettlementRecord.totalFundingPayment.eq(ZERO));
+	});
+
+	it('reports the user that failed to settle in a batch', async () => {
+		const userAccounts = [
+			await longUser.getUserAccountPublicKey(),
+			await shortUser.getUserAccountPublicKey(),
+		];
+		const { keys } =
+			await longClearingHouse.getSettleFundingPaymentsIx(userAccounts);
+		// the markets passed read-only, so the first user can't settle against them
+		const remainingAccounts = keys.slice(1).map((key) => ({
+			...key,
+			isWritable: userAccounts.some((userAccount) =>
+				userAccount.equals(key.pubkey)
+			),
+		}));
+
+		let logs: string[] = [];
+		try {
+			await chProgram.simulate.settleFundingPayments({
+				accounts: {
+					state: await longClearingHouse.getStatePublicKey(),
+				},
+				remainingAccounts,
+			});
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) =>
+				log.includes(
+					`Failed to settle funding for user ${userAccounts[0].toBase58()}`
+				)
+			),
+			'settlement should fail naming the first user'
+		);
+		assert(
+			!logs.some((log) => log.includes(userAccounts[1].toBase58())),
+			'settlement should stop at the first user'
+		);
+	});
+
+	it('rounding dust conserves the exact funding across many settles', async () => {
+		const [marketIndex, oracle] = await initializeMarketWithPositions();
+		const userAccounts = [
+			await longUser.getUserAccountPublicKey(),
+			await shortUser.getUserAccountPublicKey(),
+		];
+
+		// the exact payment (QUOTE_PRECISION * AMM_TO_QUOTE_PRECISION_RATIO) before settlement truncates it
+		const exactFundingPayment = (record: FundingPaymentRecord) => {
+			const ammCumulativeFunding = record.baseAssetAmount.gt(ZERO)
+				? record.ammCumulativeFundingLong
+				: record.ammCumulativeFundingShort;
+			const fundingRateDelta = ammCumulativeFunding.sub(
+				record.userLastCumulativeFunding
+			);
+			const magnitude = fundingRateDelta
+				.abs()
+				.mul(record.baseAssetAmount.abs())
+				.div(MARK_PRICE_PRECISION)
+				.div(FUNDING_PAYMENT_PRECISION);
+			return fundingRateDelta.isNeg() === record.baseAssetAmount.isNeg()
+				? magnitude.neg()
+				: magnitude;
+		};
+
+		for (const oraclePrice of [0.98, 1.01, 0.97]) {
+			await updateFundingRate(marketIndex, oracle, oraclePrice);
+			await longClearingHouse.fetchAccounts();
+			const dustBefore =
+				longClearingHouse.getMarketAccount(marketIndex).amm.fundingPaymentDust;
+
+			const txSig = await longClearingHouse.settleFundingPayments(userAccounts);
+			await eventSubscriber.awaitTx(txSig);
+			const fundingPaymentRecords = eventSubscriber
+				.getEventsArray('FundingPaymentRecord')
+				.filter(
+					(record) =>
+						record.txSig === txSig && record.marketIndex.eq(marketIndex)
+				);
+			assert(fundingPaymentRecords.length === 2);
+
+			let settledDust = ZERO;
+			for (const record of fundingPaymentRecords) {
+				assert(
+					record.fundingPaymentDust.abs().lt(AMM_TO_QUOTE_PRECISION_RATIO)
+				);
+				assert(
+					record.fundingPayment
+						.mul(AMM_TO_QUOTE_PRECISION_RATIO)
+						.add(record.fundingPaymentDust)
+						.eq(exactFundingPayment(record))
+				);
+				settledDust = settledDust.add(record.fundingPaymentDust);
+			}
+
+			// nothing truncated goes missing, the market keeps it
+			await longClearingHouse.fetchAccounts();
+			const dustAfter =
+				longClearingHouse.getMarketAccount(marketIndex).amm.fundingPaymentDust;
+			assert(dustAfter.sub(dustBefore).eq(settledDust));
+		}
+	});
+});
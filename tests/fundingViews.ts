@@ -0,0 +1,2210 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	AMM_RESERVE_PRECISION,
+	AMM_TIMES_PEG_TO_QUOTE_PRECISION_RATIO,
+	AMM_TO_QUOTE_PRECISION_RATIO,
+	BID_ASK_SPREAD_PRECISION,
+	BN,
+	ClearingHouse,
+	EventSubscriber,
+	FUNDING_PAYMENT_PRECISION,
+	MARGIN_PRECISION,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PEG_PRECISION,
+	PositionDirection,
+	PriceRounding,
+	QUOTE_PRECISION,
+	QUOTE_ASSET_BANK_INDEX,
+	ZERO,
+	calculateFundingRateChecksum,
+	calculateNextFundingTime,
+	calculatePrice,
+	isVariant,
+} from '../sdk/src';
+
+import {
+	expectedFundingPayment,
+	initializeQuoteAssetBank,
+	initUserAccounts,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	setFeedTwap,
+	sleep,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+// MIN_FUNDING_PERIOD, the shortest period update_funding_rate accepts
+const FUNDING_PERIOD = new BN(60);
+
+describe('funding views', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let longClearingHouse: Admin;
+	let shortClearingHouse: ClearingHouse;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetAmount = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetAmount = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(10000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	// a thin market, for views that compare against the deep one above
+	const thinMarketIndex = new BN(1);
+	let oracle: PublicKey;
+	let thinOracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		oracle = await mockOracle(1);
+		thinOracle = await mockOracle(1);
+		const marketIndexes = [marketIndex, thinMarketIndex];
+		const bankIndexes = [QUOTE_ASSET_BANK_INDEX];
+		const oracleInfos = [{ publicKey: oracle, source: OracleSource.PYTH }];
+
+		longClearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			marketIndexes,
+			bankIndexes,
+			oracleInfos
+		);
+		await longClearingHouse.initialize(usdcMint.publicKey, true);
+		await longClearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(longClearingHouse, usdcMint.publicKey);
+
+		await longClearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetAmount,
+			ammInitialQuoteAssetAmount,
+			FUNDING_PERIOD
+		);
+		// 1000 of base and quote, where the deep market has 500000
+		await longClearingHouse.initializeMarket(
+			thinOracle,
+			AMM_RESERVE_PRECISION.mul(new BN(1000)),
+			AMM_RESERVE_PRECISION.mul(new BN(1000)),
+			FUNDING_PERIOD
+		);
+
+		await longClearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+
+		const [_userUSDCAccounts, _userKeys, clearingHouses, _users] =
+			await initUserAccounts(
+				1,
+				usdcMint,
+				usdcAmount,
+				provider,
+				marketIndexes,
+				bankIndexes,
+				[]
+			);
+		shortClearingHouse = clearingHouses[0];
+
+		// the long side is larger, so the market is skewed long
+		await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(100)),
+			marketIndex
+		);
+		await shortClearingHouse.fetchAccounts();
+		await shortClearingHouse.openPosition(
+			PositionDirection.SHORT,
+			QUOTE_PRECISION.mul(new BN(40)),
+			marketIndex
+		);
+		// moves the thin market's mark about 4% above its oracle
+		await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(20)),
+			thinMarketIndex
+		);
+
+		// mark above oracle, so longs pay, within the spread clamp on the deep market and beyond it on the thin one
+		for (const [index, feed, oraclePrice] of [
+			[marketIndex, oracle, 0.98],
+			[thinMarketIndex, thinOracle, 0.97],
+		] as [BN, PublicKey, number][]) {
+			await waitForNextFundingTime(longClearingHouse, index);
+			await setFeedPrice(anchor.workspace.Pyth, oraclePrice, feed);
+			const txSig = await longClearingHouse.updateFundingRate(feed, index);
+			await eventSubscriber.awaitTx(txSig);
+		}
+		await longClearingHouse.fetchAccounts();
+	});
+
+	after(async () => {
+		await longClearingHouse.unsubscribe();
+		await shortClearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('period funding flow nets both sides of the last funding rate', async () => {
+		const market = longClearingHouse.getMarketAccount(marketIndex);
+		assert(market.amm.lastFundingRate.gt(ZERO));
+
+		const [periodFundingFlow] = await longClearingHouse.view(
+			{ periodFundingFlow: { marketIndex } },
+			[marketIndex]
+		);
+
+		const expectedPeriodFundingFlow = expectedFundingPayment(
+			market.amm.lastFundingRate,
+			market.baseAssetAmountLong
+		)
+			.add(
+				expectedFundingPayment(
+					market.amm.lastFundingRate,
+					market.baseAssetAmountShort
+				)
+			)
+			.neg();
+		assert(periodFundingFlow.eq(expectedPeriodFundingFlow));
+		// the skewed long side pays more than the short side receives
+		assert(periodFundingFlow.gt(ZERO));
+	});
+
+	it('funding records carry the raw and clamped price spreads', async () => {
+		for (const [index, clamped] of [
+			[marketIndex, false],
+			[thinMarketIndex, true],
+		] as [BN, boolean][]) {
+			// the update in before is still the latest for each market
+			const fundingRateRecord = eventSubscriber
+				.getEventsArray('FundingRateRecord')
+				.find((record) => record.marketIndex.eq(index));
+			const rawPriceSpread = fundingRateRecord.markPriceTwap.sub(
+				fundingRateRecord.oraclePriceTwap
+			);
+			assert(fundingRateRecord.rawPriceSpread.eq(rawPriceSpread));
+			assert(rawPriceSpread.gt(ZERO));
+
+			// DEFAULT_FUNDING_CLAMP_DIVISOR
+			const maxPriceSpread = fundingRateRecord.oraclePriceTwap.div(new BN(33));
+			if (clamped) {
+				assert(rawPriceSpread.gt(maxPriceSpread));
+				assert(fundingRateRecord.clampedPriceSpread.eq(maxPriceSpread));
+			} else {
+				assert(rawPriceSpread.lte(maxPriceSpread));
+				assert(fundingRateRecord.clampedPriceSpread.eq(rawPriceSpread));
+			}
+		}
+	});
+
+	it('funding per base unit for the long and short sides', async () => {
+		const market = longClearingHouse.getMarketAccount(marketIndex);
+
+		for (const [long, baseAssetAmount] of [
+			[true, AMM_RESERVE_PRECISION],
+			[false, AMM_RESERVE_PRECISION.neg()],
+		] as [boolean, BN][]) {
+			const [fundingPerBaseUnit] = await longClearingHouse.view(
+				{ fundingPerBaseUnit: { marketIndex, long } },
+				[marketIndex]
+			);
+			assert(
+				fundingPerBaseUnit.eq(
+					expectedFundingPayment(market.amm.lastFundingRate, baseAssetAmount)
+				)
+			);
+			// mark above oracle, so longs pay and shorts receive
+			assert(fundingPerBaseUnit.isNeg() === long);
+		}
+	});
+
+	it('implied mark twap round trips the unclamped mark twap', async () => {
+		for (const [index, clamped] of [
+			[marketIndex, false],
+			[thinMarketIndex, true],
+		] as [BN, boolean][]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			const [impliedMarkTwap] = await longClearingHouse.view(
+				{ impliedMarkTwap: { marketIndex: index } },
+				[index]
+			);
+
+			if (clamped) {
+				// the rate only carries the clamped spread
+				assert(impliedMarkTwap.lt(amm.lastMarkPriceTwap));
+				assert(impliedMarkTwap.gt(amm.lastOraclePriceTwap));
+			} else {
+				// the rate truncates the spread scaled down by the period adjustment
+				assert(impliedMarkTwap.sub(amm.lastMarkPriceTwap).abs().lte(new BN(1)));
+			}
+		}
+	});
+
+	it('funding cost for a position at a few leverage levels', async () => {
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const collateral = QUOTE_PRECISION.mul(new BN(1000));
+
+		const fundingCosts = [];
+		for (const leverage of [1, 5, 10]) {
+			const [fundingCost] = await longClearingHouse.view(
+				{
+					fundingCostForPosition: {
+						marketIndex,
+						collateral,
+						leverage: MARGIN_PRECISION.mul(new BN(leverage)),
+					},
+				},
+				[marketIndex]
+			);
+
+			// a sub-hour funding period is paid twenty four times over
+			const expectedFundingCost = collateral
+				.mul(new BN(leverage))
+				.mul(amm.lastFundingRate)
+				.mul(new BN(24))
+				.div(FUNDING_PAYMENT_PRECISION)
+				.div(amm.lastOraclePriceTwap);
+			assert(fundingCost.eq(expectedFundingCost));
+			// mark above oracle, so the long pays
+			assert(fundingCost.gt(ZERO));
+			fundingCosts.push(fundingCost);
+		}
+
+		// cost scales with leverage, up to rounding
+		const [oneX, fiveX, tenX] = fundingCosts;
+		assert(fiveX.sub(oneX.mul(new BN(5))).abs().lte(new BN(5)));
+		assert(tenX.sub(fiveX.mul(new BN(2))).abs().lte(new BN(2)));
+	});
+
+	it('basis breakeven rate for profitable and unprofitable carry', async () => {
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		// a sub-hour funding period is paid twenty four times a day
+		const fundingAprBps = amm.lastFundingRate
+			.mul(new BN(24))
+			.mul(new BN(365))
+			.mul(new BN(10000))
+			.div(FUNDING_PAYMENT_PRECISION)
+			.div(amm.lastOraclePriceTwap);
+		assert(fundingAprBps.gt(ZERO));
+
+		// carry below the funding apr leaves the short perp leg in profit, above it at a loss
+		for (const carryCostBps of [
+			fundingAprBps.div(new BN(2)),
+			fundingAprBps.mul(new BN(2)),
+		]) {
+			const [breakevenRate] = await longClearingHouse.view(
+				{ basisBreakevenRate: { marketIndex, carryCostBps } },
+				[marketIndex]
+			);
+			assert(breakevenRate.eq(fundingAprBps.sub(carryCostBps)));
+			assert(breakevenRate.gt(ZERO) === carryCostBps.lt(fundingAprBps));
+		}
+	});
+
+	it('carry apr for a funding earning short and a funding paying long', async () => {
+		const positionCarryAprBps = async (baseAssetAmount: BN) => {
+			const [carryAprBps] = await longClearingHouse.view(
+				{ positionCarryAprBps: { marketIndex, baseAssetAmount } },
+				[marketIndex]
+			);
+			return carryAprBps;
+		};
+		// the funding apr, with no carry cost to break even against
+		const [fundingAprBps] = await longClearingHouse.view(
+			{ basisBreakevenRate: { marketIndex, carryCostBps: ZERO } },
+			[marketIndex]
+		);
+		assert(fundingAprBps.gt(ZERO));
+
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+		const shortCarryAprBps = await positionCarryAprBps(baseAssetAmount.neg());
+		assert(shortCarryAprBps.eq(fundingAprBps));
+		const longCarryAprBps = await positionCarryAprBps(baseAssetAmount);
+		assert(longCarryAprBps.eq(fundingAprBps.neg()));
+
+		// annualized from the rate, so independent of the size
+		assert(
+			(await positionCarryAprBps(baseAssetAmount.mul(new BN(10)))).eq(
+				longCarryAprBps
+			)
+		);
+		assert((await positionCarryAprBps(ZERO)).eq(ZERO));
+	});
+
+	it('implied borrow and lend rates for a market where longs pay', async () => {
+		const [fundingAprBps] = await longClearingHouse.view(
+			{ basisBreakevenRate: { marketIndex, carryCostBps: ZERO } },
+			[marketIndex]
+		);
+		assert(fundingAprBps.gt(ZERO));
+
+		const [borrowRate, lendRate] = await longClearingHouse.view(
+			{ impliedBorrowLendRates: { marketIndex } },
+			[marketIndex]
+		);
+		// longs pay the full rate, shorts receive it unless the clearing house caps their side
+		assert(borrowRate.eq(fundingAprBps));
+		assert(lendRate.gt(ZERO));
+		assert(lendRate.lte(borrowRate));
+	});
+
+	it('funding as margin return bps for a leveraged long', async () => {
+		const market = longClearingHouse.getMarketAccount(marketIndex);
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+
+		// about 100 of notional on 20 and 10 of margin, 5x and 10x
+		const marginReturnsBps = [];
+		for (const margin of [
+			QUOTE_PRECISION.mul(new BN(20)),
+			QUOTE_PRECISION.mul(new BN(10)),
+		]) {
+			const [marginReturnBps] = await longClearingHouse.view(
+				{ fundingAsMarginReturnBps: { marketIndex, baseAssetAmount, margin } },
+				[marketIndex]
+			);
+			const fundingPayment = expectedFundingPayment(
+				market.amm.lastFundingRate,
+				baseAssetAmount
+			);
+			assert(marginReturnBps.eq(fundingPayment.mul(new BN(10000)).div(margin)));
+			// mark above oracle, so the long pays
+			assert(marginReturnBps.lt(ZERO));
+			marginReturnsBps.push(marginReturnBps);
+		}
+		// twice the leverage, twice the return on margin
+		const [fiveX, tenX] = marginReturnsBps;
+		assert(tenX.sub(fiveX.mul(new BN(2))).abs().lte(new BN(1)));
+
+		let failed = false;
+		try {
+			await longClearingHouse.view(
+				{
+					fundingAsMarginReturnBps: {
+						marketIndex,
+						baseAssetAmount,
+						margin: ZERO,
+					},
+				},
+				[marketIndex]
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed, 'a zero margin should be rejected');
+	});
+
+	it('mark twap deviation bps for spot above and below the twap', async () => {
+		const markTwap =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastMarkPriceTwap;
+
+		// 1% above and below the twap
+		for (const spotMark of [
+			markTwap.mul(new BN(101)).div(new BN(100)),
+			markTwap.mul(new BN(99)).div(new BN(100)),
+		]) {
+			const [deviationBps] = await longClearingHouse.view(
+				{ markTwapDeviationBps: { marketIndex, spotMark } },
+				[marketIndex]
+			);
+
+			const expectedDeviationBps = spotMark
+				.sub(markTwap)
+				.mul(new BN(10000))
+				.div(markTwap);
+			assert(deviationBps.eq(expectedDeviationBps));
+			assert(deviationBps.abs().gte(new BN(99)));
+			assert(deviationBps.isNeg() === spotMark.lt(markTwap));
+		}
+	});
+
+	it('clamp divisor for a few max aprs', async () => {
+		// divisor = ceil(ONE_YEAR * 10000 / (TWENTYFOUR_HOUR * apr bps)) = ceil(3650000 / apr bps)
+		for (const [targetAprBps, expectedDivisor] of [
+			[3650, 1000],
+			[10000, 365],
+			[36500, 100],
+			[7, 521429],
+		]) {
+			const [clampDivisor] = await longClearingHouse.view(
+				{
+					clampDivisorForMaxApr: {
+						marketIndex,
+						targetAprBps: new BN(targetAprBps),
+					},
+				},
+				[marketIndex]
+			);
+			assert(clampDivisor.eq(new BN(expectedDivisor)));
+		}
+	});
+
+	it('mark price matches a full precision reference', async () => {
+		for (const index of [marketIndex, thinMarketIndex]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			const [markPrice] = await longClearingHouse.view(
+				{ markPrice: { marketIndex: index } },
+				[index]
+			);
+
+			// one division at the end, so the only truncation is the last digit
+			const referenceMarkPrice = amm.quoteAssetReserve
+				.mul(amm.pegMultiplier)
+				.mul(MARK_PRICE_PRECISION)
+				.div(amm.baseAssetReserve.mul(PEG_PRECISION));
+			assert(markPrice.eq(referenceMarkPrice));
+		}
+	});
+
+	it('peg for a target price is closed form and errors on empty reserves', async () => {
+		const amm = longClearingHouse.getMarketAccount(thinMarketIndex).amm;
+		const pegForTargetPrice = async (
+			quoteAssetReserve: BN,
+			targetPrice: BN
+		) => {
+			const [peg] = await longClearingHouse.view({
+				pegForTargetPrice: {
+					quoteAssetReserve,
+					baseAssetReserve: amm.baseAssetReserve,
+					targetPrice,
+				},
+			});
+			return peg;
+		};
+
+		// the current mark price maps back to the current peg, doubling it doubles the peg
+		const [markPrice] = await longClearingHouse.view(
+			{ markPrice: { marketIndex: thinMarketIndex } },
+			[thinMarketIndex]
+		);
+		const peg = await pegForTargetPrice(amm.quoteAssetReserve, markPrice);
+		assert(peg.eq(amm.pegMultiplier));
+		const doubledPeg = await pegForTargetPrice(
+			amm.quoteAssetReserve,
+			markPrice.mul(new BN(2))
+		);
+		assert(doubledPeg.sub(peg.mul(new BN(2))).abs().lte(new BN(1)));
+
+		// no iteration to cap, an unreachable target fails straight away
+		let logs: string[] = [];
+		try {
+			await pegForTargetPrice(ZERO, markPrice);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('MathError')),
+			'view should fail with MathError'
+		);
+	});
+
+	it('transposed reserves silently invert the price', async () => {
+		// the thin market's reserves are far apart after the long
+		const amm = longClearingHouse.getMarketAccount(thinMarketIndex).amm;
+		const [markPrice] = await longClearingHouse.view(
+			{ markPrice: { marketIndex: thinMarketIndex } },
+			[thinMarketIndex]
+		);
+
+		const priceForReserves = async (
+			quoteAssetReserve: BN,
+			baseAssetReserve: BN
+		) => {
+			const [price] = await longClearingHouse.view({
+				priceForReserves: {
+					quoteAssetReserve,
+					baseAssetReserve,
+					pegMultiplier: amm.pegMultiplier,
+				},
+			});
+			return price;
+		};
+		const price = await priceForReserves(
+			amm.quoteAssetReserve,
+			amm.baseAssetReserve
+		);
+		assert(price.eq(markPrice));
+
+		// no error, just the reciprocal price (the peg is 1)
+		assert(amm.pegMultiplier.eq(PEG_PRECISION));
+		const transposedPrice = await priceForReserves(
+			amm.baseAssetReserve,
+			amm.quoteAssetReserve
+		);
+		assert(transposedPrice.lt(MARK_PRICE_PRECISION));
+		assert(markPrice.gt(MARK_PRICE_PRECISION));
+		assert(
+			transposedPrice.eq(
+				amm.baseAssetReserve
+					.mul(MARK_PRICE_PRECISION)
+					.div(amm.quoteAssetReserve)
+			)
+		);
+	});
+
+	it('oracle price converted from a feed quoted per 1000 units', async () => {
+		const [defaultPrice, defaultConfidence] = await longClearingHouse.view(
+			{
+				oraclePriceWithConversion: {
+					marketIndex,
+					oraclePriceMultiplier: new BN(1),
+					oraclePriceDivisor: new BN(1),
+				},
+			},
+			[marketIndex]
+		);
+		// the market's own 1 / 1 conversion leaves the feed as is
+		const [marketPrice, marketConfidence] = await longClearingHouse.view(
+			{ oraclePrice: { oracle } },
+			[],
+			[oracle]
+		);
+		assert(defaultPrice.eq(marketPrice));
+		assert(defaultConfidence.eq(marketConfidence));
+		assert(
+			defaultPrice.eq(MARK_PRICE_PRECISION.mul(new BN(98)).div(new BN(100)))
+		);
+
+		// a feed for 1000 units divides down to the per contract price
+		const [perContractPrice, perContractConfidence] =
+			await longClearingHouse.view(
+				{
+					oraclePriceWithConversion: {
+						marketIndex,
+						oraclePriceMultiplier: new BN(1),
+						oraclePriceDivisor: new BN(1000),
+					},
+				},
+				[marketIndex]
+			);
+		assert(perContractPrice.eq(defaultPrice.div(new BN(1000))));
+		assert(perContractConfidence.eq(defaultConfidence.div(new BN(1000))));
+	});
+
+	it('position funding payment errors for a market with a zeroed amm', async () => {
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const [fundingPayment] = await longClearingHouse.view(
+			{
+				positionFundingPayment: {
+					marketIndex,
+					baseAssetAmount,
+					lastCumulativeFundingRate: ZERO,
+				},
+			},
+			[marketIndex]
+		);
+		assert(
+			fundingPayment.eq(
+				expectedFundingPayment(amm.cumulativeFundingRateLong, baseAssetAmount)
+			)
+		);
+		assert(fundingPayment.lt(ZERO));
+
+		// preloaded from tests/fixtures/zeroedAmmMarket.json, market 99 is present and flagged initialized
+		// but its amm is all zeros. It isn't a subscribed market, so it's passed as a raw account
+		const zeroedAmmMarket = new PublicKey(
+			'6UWp3d7cLTCuVfvfqo3vWUievpPabpSHukBLHtEgK1jQ'
+		);
+		let logs: string[] = [];
+		try {
+			await longClearingHouse.view(
+				{
+					positionFundingPayment: {
+						marketIndex: new BN(99),
+						baseAssetAmount,
+						lastCumulativeFundingRate: ZERO,
+					},
+				},
+				[],
+				[zeroedAmmMarket]
+			);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('MarketIndexNotInitialized')),
+			'view should fail with MarketIndexNotInitialized'
+		);
+	});
+
+	it('realized pnl with funding for a winning long and a losing short', async () => {
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		// entered at 1 and closed at 1.1, holding since the market opened
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+		const quoteAssetAmount = QUOTE_PRECISION.mul(new BN(100));
+		const exitPrice = MARK_PRICE_PRECISION.mul(new BN(11)).div(new BN(10));
+		const priceProfit = QUOTE_PRECISION.mul(new BN(10));
+
+		const realizedPnlWithFunding = async (positionBaseAssetAmount: BN) => {
+			const [realizedPnl] = await longClearingHouse.view(
+				{
+					realizedPnlWithFunding: {
+						marketIndex,
+						baseAssetAmount: positionBaseAssetAmount,
+						quoteAssetAmount,
+						lastCumulativeFundingRate: ZERO,
+						exitPrice,
+					},
+				},
+				[marketIndex]
+			);
+			return realizedPnl;
+		};
+
+		// the long paid funding, giving back some of its profit
+		const longFundingPayment = expectedFundingPayment(
+			amm.cumulativeFundingRateLong,
+			baseAssetAmount
+		);
+		assert(longFundingPayment.lt(ZERO));
+		const longRealizedPnl = await realizedPnlWithFunding(baseAssetAmount);
+		assert(longRealizedPnl.eq(priceProfit.add(longFundingPayment)));
+		assert(longRealizedPnl.lt(priceProfit));
+
+		// the short earned funding, making up some of its loss
+		const shortFundingPayment = expectedFundingPayment(
+			amm.cumulativeFundingRateShort,
+			baseAssetAmount.neg()
+		);
+		assert(shortFundingPayment.gt(ZERO));
+		const shortRealizedPnl = await realizedPnlWithFunding(
+			baseAssetAmount.neg()
+		);
+		assert(shortRealizedPnl.eq(priceProfit.neg().add(shortFundingPayment)));
+		assert(shortRealizedPnl.gt(priceProfit.neg()));
+	});
+
+	it('funding rate for a few target premiums', async () => {
+		const oracleTwap =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastOraclePriceTwap;
+		const [periodAdjustment] = await longClearingHouse.view({
+			periodAdjustment: { fundingPeriod: FUNDING_PERIOD },
+		});
+
+		const fundingRateForTargetPremiumBps = async (bps: number) => {
+			const [fundingRate] = await longClearingHouse.view(
+				{
+					fundingRateForTargetPremiumBps: {
+						marketIndex,
+						targetPremiumBps: new BN(bps),
+					},
+				},
+				[marketIndex]
+			);
+			return fundingRate;
+		};
+
+		// within the ~3% spread clamp the rate is the premium scaled down to the period
+		for (const bps of [0, 10, 100, -100]) {
+			const expectedFundingRate = oracleTwap
+				.mul(new BN(bps))
+				.div(new BN(10000))
+				.mul(FUNDING_PAYMENT_PRECISION)
+				.div(periodAdjustment);
+			const fundingRate = await fundingRateForTargetPremiumBps(bps);
+			assert(fundingRate.eq(expectedFundingRate));
+		}
+
+		// beyond it the premium is clamped, so larger targets give the same rate
+		const clampedFundingRate = await fundingRateForTargetPremiumBps(5000);
+		assert(clampedFundingRate.eq(await fundingRateForTargetPremiumBps(10000)));
+		assert(
+			clampedFundingRate.lt(
+				oracleTwap
+					.div(new BN(2))
+					.mul(FUNDING_PAYMENT_PRECISION)
+					.div(periodAdjustment)
+			)
+		);
+	});
+
+	it('funding rate ema converges to a constant funding rate', async () => {
+		// 0.1% of a price of 1 per period
+		const fundingRate = MARK_PRICE_PRECISION.mul(
+			FUNDING_PAYMENT_PRECISION
+		).div(new BN(1000));
+		const fundingRateEma = async (previousEma: BN, alpha: BN) => {
+			const [ema] = await longClearingHouse.view({
+				fundingRateEma: { fundingRateEma: previousEma, fundingRate, alpha },
+			});
+			return ema;
+		};
+
+		// the default alpha tracks the raw rate
+		assert((await fundingRateEma(ZERO, ZERO)).eq(fundingRate));
+
+		// 20% of each new rate, so the gap shrinks by a fifth every update
+		let ema = ZERO;
+		let gap = fundingRate;
+		for (let i = 0; i < 40; i++) {
+			ema = await fundingRateEma(ema, new BN(2000));
+			const nextGap = fundingRate.sub(ema);
+			assert(nextGap.gte(ZERO));
+			assert(nextGap.lt(gap));
+			gap = nextGap;
+		}
+		assert(gap.lt(fundingRate.div(new BN(1000))));
+	});
+
+	it('max funding rate caps the funding rate in both directions', async () => {
+		const cappedFundingRate = async (fundingRate: BN, maxFundingRate: BN) => {
+			const [cappedRate] = await longClearingHouse.view(
+				{ cappedFundingRate: { marketIndex, fundingRate, maxFundingRate } },
+				[marketIndex]
+			);
+			return cappedRate;
+		};
+
+		// 0.1% of a price of 1 per period
+		const maxFundingRate = MARK_PRICE_PRECISION.mul(
+			FUNDING_PAYMENT_PRECISION
+		).div(new BN(1000));
+		const halfMaxFundingRate = maxFundingRate.div(new BN(2));
+		for (const [fundingRate, expectedFundingRate] of [
+			[halfMaxFundingRate, halfMaxFundingRate],
+			[halfMaxFundingRate.neg(), halfMaxFundingRate.neg()],
+			[maxFundingRate.mul(new BN(5)), maxFundingRate],
+			[maxFundingRate.mul(new BN(5)).neg(), maxFundingRate.neg()],
+		]) {
+			const cappedRate = await cappedFundingRate(fundingRate, maxFundingRate);
+			assert(cappedRate.eq(expectedFundingRate));
+		}
+
+		// no ceiling by default
+		const largeFundingRate = maxFundingRate.mul(new BN(1000));
+		for (const fundingRate of [largeFundingRate, largeFundingRate.neg()]) {
+			assert((await cappedFundingRate(fundingRate, ZERO)).eq(fundingRate));
+		}
+	});
+
+	it('expected funding calls per day for hourly and eight hourly funding', async () => {
+		for (const [fundingPeriod, expectedCalls] of [
+			[60 * 60, 24],
+			[8 * 60 * 60, 3],
+			// an unset funding period is never cranked
+			[0, 0],
+		]) {
+			const [calls] = await longClearingHouse.view(
+				{
+					expectedFundingCallsPerDay: {
+						marketIndex,
+						fundingPeriod: new BN(fundingPeriod),
+					},
+				},
+				[marketIndex]
+			);
+			assert(calls.eq(new BN(expectedCalls)));
+		}
+	});
+
+	it('min collateral for funding at high and low funding rates', async () => {
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+		const minCollateralForFunding = async (
+			positionBaseAssetAmount: BN,
+			fundingRate: BN
+		) => {
+			const [minCollateral] = await longClearingHouse.view(
+				{
+					minCollateralForFunding: {
+						marketIndex,
+						baseAssetAmount: positionBaseAssetAmount,
+						fundingRate,
+					},
+				},
+				[marketIndex]
+			);
+			return minCollateral;
+		};
+
+		// without funding only maintenance margin is needed
+		const maintenanceMargin = await minCollateralForFunding(
+			baseAssetAmount,
+			ZERO
+		);
+		assert(maintenanceMargin.gt(ZERO));
+
+		// 1% and 0.01% of a price of 1 per period, paid by longs
+		const fundingRatePrecision = MARK_PRICE_PRECISION.mul(
+			FUNDING_PAYMENT_PRECISION
+		);
+		const highFundingRate = fundingRatePrecision.div(new BN(100));
+		const lowFundingRate = fundingRatePrecision.div(new BN(10000));
+		const highMinCollateral = await minCollateralForFunding(
+			baseAssetAmount,
+			highFundingRate
+		);
+		const lowMinCollateral = await minCollateralForFunding(
+			baseAssetAmount,
+			lowFundingRate
+		);
+		assert(
+			highMinCollateral.eq(
+				maintenanceMargin.add(
+					expectedFundingPayment(highFundingRate, baseAssetAmount).abs()
+				)
+			)
+		);
+		assert(
+			lowMinCollateral.eq(
+				maintenanceMargin.add(
+					expectedFundingPayment(lowFundingRate, baseAssetAmount).abs()
+				)
+			)
+		);
+		assert(lowMinCollateral.gt(maintenanceMargin));
+		assert(lowMinCollateral.lt(highMinCollateral));
+
+		// funding a short receives isn't credited
+		const shortMinCollateral = await minCollateralForFunding(
+			baseAssetAmount.neg(),
+			highFundingRate
+		);
+		assert(shortMinCollateral.eq(maintenanceMargin));
+	});
+
+	it('funding rate for a target premium with a configured clamp', async () => {
+		const oracleTwap =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastOraclePriceTwap;
+		const [periodAdjustment] = await longClearingHouse.view({
+			periodAdjustment: { fundingPeriod: FUNDING_PERIOD },
+		});
+
+		const clampedFundingRate = async (bps: number, divisor: number) => {
+			const [fundingRate] = await longClearingHouse.view(
+				{
+					clampedFundingRateForTargetPremiumBps: {
+						marketIndex,
+						targetPremiumBps: new BN(bps),
+						fundingClampDivisor: new BN(divisor),
+					},
+				},
+				[marketIndex]
+			);
+			return fundingRate;
+		};
+		const fundingRateForSpread = (priceSpread: BN) =>
+			priceSpread.mul(FUNDING_PAYMENT_PRECISION).div(periodAdjustment);
+
+		// zero keeps the legacy divisor of 33
+		for (const divisor of [0, 33]) {
+			assert(
+				(await clampedFundingRate(5000, divisor)).eq(
+					fundingRateForSpread(oracleTwap.div(new BN(33)))
+				)
+			);
+		}
+
+		// a 5% premium is clamped at ~3% by default but passes a 10% clamp
+		const fivePercentSpread = oracleTwap.mul(new BN(500)).div(new BN(10000));
+		assert(
+			(await clampedFundingRate(500, 0)).lt(
+				fundingRateForSpread(fivePercentSpread)
+			)
+		);
+		assert(
+			(await clampedFundingRate(500, 10)).eq(
+				fundingRateForSpread(fivePercentSpread)
+			)
+		);
+		assert(
+			(await clampedFundingRate(5000, 10)).eq(
+				fundingRateForSpread(oracleTwap.div(new BN(10)))
+			)
+		);
+	});
+
+	it('funding clamp price bounds for the default and a custom divisor', async () => {
+		const oracleTwap = MARK_PRICE_PRECISION.mul(new BN(2));
+		const fundingClampPriceBounds = async (
+			twap: BN,
+			fundingClampDivisor: number
+		) =>
+			await longClearingHouse.view(
+				{
+					fundingClampPriceBounds: {
+						marketIndex,
+						oracleTwap: twap,
+						fundingClampDivisor: new BN(fundingClampDivisor),
+					},
+				},
+				[marketIndex]
+			);
+
+		// zero keeps the legacy divisor of 33 (~3%), 10 allows 10%
+		for (const [divisor, effectiveDivisor] of [
+			[0, 33],
+			[10, 10],
+		]) {
+			const maxPriceSpread = oracleTwap.div(new BN(effectiveDivisor));
+			const [lowerBound, upperBound] = await fundingClampPriceBounds(
+				oracleTwap,
+				divisor
+			);
+			assert(lowerBound.eq(oracleTwap.sub(maxPriceSpread)));
+			assert(upperBound.eq(oracleTwap.add(maxPriceSpread)));
+		}
+
+		// still a range around the twap when the twap is negative
+		const [lowerBound, upperBound] = await fundingClampPriceBounds(
+			oracleTwap.neg(),
+			10
+		);
+		assert(lowerBound.eq(oracleTwap.neg().sub(oracleTwap.div(new BN(10)))));
+		assert(upperBound.eq(oracleTwap.neg().add(oracleTwap.div(new BN(10)))));
+	});
+
+	it('periods to funding liquidation for adverse and favorable funding', async () => {
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(100));
+		const lastFundingRate =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastFundingRate;
+		const periodicPayment = expectedFundingPayment(
+			lastFundingRate,
+			baseAssetAmount
+		).abs();
+		assert(periodicPayment.gt(ZERO));
+
+		const periodsToFundingLiquidation = async (
+			positionBaseAssetAmount: BN,
+			maintenanceMargin: BN
+		) => {
+			const [periods] = await longClearingHouse.view(
+				{
+					periodsToFundingLiquidation: {
+						marketIndex,
+						baseAssetAmount: positionBaseAssetAmount,
+						maintenanceMargin,
+					},
+				},
+				[marketIndex]
+			);
+			return periods;
+		};
+
+		// longs pay, so the margin is eroded by whole periods of funding
+		for (const [maintenanceMargin, expectedPeriods] of [
+			[periodicPayment.mul(new BN(10)).add(new BN(1)), 10],
+			[periodicPayment.mul(new BN(3)), 3],
+			[periodicPayment.sub(new BN(1)), 0],
+		] as [BN, number][]) {
+			const periods = await periodsToFundingLiquidation(
+				baseAssetAmount,
+				maintenanceMargin
+			);
+			assert(periods.eq(new BN(expectedPeriods)));
+		}
+
+		// shorts receive, so funding never erodes their margin
+		const shortPeriods = await periodsToFundingLiquidation(
+			baseAssetAmount.neg(),
+			periodicPayment
+		);
+		assert(shortPeriods.eq(new BN('18446744073709551615')));
+	});
+
+	it('skewed mark price grows with the amm inventory', async () => {
+		const relativeSkewsBps = [];
+		for (const index of [marketIndex, thinMarketIndex]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			// users are net long, so the amm is short and the price is raised
+			assert(amm.netBaseAssetAmount.gt(ZERO));
+
+			const skewedMarkPrice = async (skewFactor: BN) => {
+				const [price] = await longClearingHouse.view(
+					{ skewedMarkPrice: { marketIndex: index, skewFactor } },
+					[index]
+				);
+				return price;
+			};
+
+			const [markPrice] = await longClearingHouse.view(
+				{ markPrice: { marketIndex: index } },
+				[index]
+			);
+			// the default skew factor of zero quotes the mark as is
+			assert((await skewedMarkPrice(ZERO)).eq(markPrice));
+
+			const skewFactor = BID_ASK_SPREAD_PRECISION.div(new BN(10));
+			const skewedPrice = await skewedMarkPrice(skewFactor);
+			const skew = skewFactor.mul(amm.netBaseAssetAmount).div(amm.sqrtK);
+			assert(
+				skewedPrice.eq(
+					markPrice
+						.mul(BID_ASK_SPREAD_PRECISION.add(skew))
+						.div(BID_ASK_SPREAD_PRECISION)
+				)
+			);
+			assert(skewedPrice.gt(markPrice));
+			relativeSkewsBps.push(
+				skewedPrice.sub(markPrice).mul(new BN(10000)).div(markPrice)
+			);
+		}
+
+		// the thin market holds far more inventory relative to its liquidity
+		const [deepSkewBps, thinSkewBps] = relativeSkewsBps;
+		assert(thinSkewBps.gt(deepSkewBps));
+	});
+
+	it('abs funding rate alert thresholds, including for i128::MIN', async () => {
+		const absFundingRate = longClearingHouse
+			.getMarketAccount(marketIndex)
+			.amm.lastFundingRate.abs();
+		for (const [threshold, expectedAbove] of [
+			[absFundingRate.subn(1), 1],
+			[absFundingRate, 0],
+		] as [BN, number][]) {
+			const [above] = await longClearingHouse.view(
+				{ absFundingRateAbove: { marketIndex, threshold } },
+				[marketIndex]
+			);
+			assert(above.eq(new BN(expectedAbove)));
+		}
+
+		// preloaded from tests/fixtures/minFundingRateMarket.json, market 98's last funding rate is i128::MIN,
+		// whose magnitude 2^127 is one more than i128::MAX. It isn't a subscribed market, so it's passed as a raw account
+		const minFundingRateMarket = new PublicKey(
+			'H2gF2rZgoh2B2GdAfzqJhVciDwfWSphhP2WD4bygRaRK'
+		);
+		const i128Max = new BN(2).pow(new BN(127)).subn(1);
+		for (const [threshold, expectedAbove] of [
+			[i128Max, 1],
+			[i128Max.addn(1), 0],
+		] as [BN, number][]) {
+			const [above] = await longClearingHouse.view(
+				{ absFundingRateAbove: { marketIndex: new BN(98), threshold } },
+				[],
+				[minFundingRateMarket]
+			);
+			assert(above.eq(new BN(expectedAbove)));
+		}
+	});
+
+	it('reserves after trade keep k and move in the trade direction', async () => {
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const k = amm.sqrtK.mul(amm.sqrtK);
+		const baseAmount = AMM_RESERVE_PRECISION;
+
+		// a long removes base from the amm, a short adds it
+		for (const signedBaseAmount of [baseAmount, baseAmount.neg()]) {
+			const [baseAssetReserve, quoteAssetReserve] =
+				await longClearingHouse.view(
+					{
+						reservesAfterTrade: {
+							marketIndex,
+							baseAmount: signedBaseAmount,
+						},
+					},
+					[marketIndex]
+				);
+
+			assert(
+				baseAssetReserve.eq(amm.baseAssetReserve.sub(signedBaseAmount))
+			);
+			if (signedBaseAmount.gt(ZERO)) {
+				assert(quoteAssetReserve.gt(amm.quoteAssetReserve));
+			} else {
+				assert(quoteAssetReserve.lt(amm.quoteAssetReserve));
+			}
+
+			// the quote reserve is k / base rounded down, so k is kept to within one base reserve
+			const product = baseAssetReserve.mul(quoteAssetReserve);
+			assert(product.lte(k));
+			assert(k.sub(product).lt(baseAssetReserve));
+		}
+
+		const [baseAssetReserve, quoteAssetReserve] = await longClearingHouse.view(
+			{ reservesAfterTrade: { marketIndex, baseAmount: ZERO } },
+			[marketIndex]
+		);
+		assert(baseAssetReserve.eq(amm.baseAssetReserve));
+		assert(quoteAssetReserve.eq(amm.quoteAssetReserve));
+	});
+
+	it('price after trade and slippage grow with the trade size', async () => {
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const [markPrice] = await longClearingHouse.view(
+			{ markPrice: { marketIndex } },
+			[marketIndex]
+		);
+
+		const priceAfterTrade = async (
+			baseAssetAmount: BN,
+			direction: PositionDirection
+		) =>
+			await longClearingHouse.view(
+				{ priceAfterTrade: { marketIndex, baseAssetAmount, direction } },
+				[marketIndex]
+			);
+
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(1000));
+		for (const [direction, signedBaseAmount] of [
+			[PositionDirection.LONG, baseAssetAmount],
+			[PositionDirection.SHORT, baseAssetAmount.neg()],
+		] as [PositionDirection, BN][]) {
+			const [priceAfter, slippageBps] = await priceAfterTrade(
+				baseAssetAmount,
+				direction
+			);
+
+			// the mark price of the reserves the trade leaves behind
+			const [baseAssetReserve, quoteAssetReserve] =
+				await longClearingHouse.view(
+					{
+						reservesAfterTrade: { marketIndex, baseAmount: signedBaseAmount },
+					},
+					[marketIndex]
+				);
+			const [expectedPriceAfter] = await longClearingHouse.view({
+				priceForReserves: {
+					quoteAssetReserve,
+					baseAssetReserve,
+					pegMultiplier: amm.pegMultiplier,
+				},
+			});
+			assert(priceAfter.eq(expectedPriceAfter));
+			assert(
+				slippageBps.eq(
+					priceAfter.sub(markPrice).mul(new BN(10000)).div(markPrice)
+				)
+			);
+
+			// longs push the price up and shorts push it down, more so for larger trades
+			const [largerPriceAfter, largerSlippageBps] = await priceAfterTrade(
+				baseAssetAmount.mul(new BN(10)),
+				direction
+			);
+			if (isVariant(direction, 'long')) {
+				assert(slippageBps.gt(ZERO));
+				assert(largerPriceAfter.gt(priceAfter));
+				assert(largerSlippageBps.gt(slippageBps));
+			} else {
+				assert(slippageBps.lt(ZERO));
+				assert(largerPriceAfter.lt(priceAfter));
+				assert(largerSlippageBps.lt(slippageBps));
+			}
+
+			// only the size counts, the direction sets the side
+			const [negatedPriceAfter] = await priceAfterTrade(
+				baseAssetAmount.neg(),
+				direction
+			);
+			assert(negatedPriceAfter.eq(priceAfter));
+		}
+	});
+
+	it('near reserve floor for plentiful and drawn down reserves', async () => {
+		// the net long drew the deep market's base reserve about 0.01% below sqrt k, the thin market's about 2%
+		for (const [index, thresholdBps, expectedNearFloor] of [
+			[marketIndex, 9900, false],
+			[thinMarketIndex, 9900, true],
+			[thinMarketIndex, 9500, false],
+		] as [BN, number, boolean][]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			const floor = amm.sqrtK.mul(new BN(thresholdBps)).div(new BN(10000));
+			assert(amm.baseAssetReserve.lte(floor) === expectedNearFloor);
+
+			const [nearFloor] = await longClearingHouse.view(
+				{
+					isNearReserveFloor: {
+						marketIndex: index,
+						thresholdBps: new BN(thresholdBps),
+					},
+				},
+				[index]
+			);
+			assert(nearFloor.eq(new BN(expectedNearFloor ? 1 : 0)));
+		}
+	});
+
+	it('period adjustment divides a day by the funding period', async () => {
+		// periods under an hour are treated as an hour
+		for (const [fundingPeriod, expectedPeriodAdjustment] of [
+			[60, 24],
+			[60 * 60, 24],
+			[8 * 60 * 60, 3],
+			[24 * 60 * 60, 1],
+		]) {
+			const [periodAdjustment] = await longClearingHouse.view({
+				periodAdjustment: { fundingPeriod: new BN(fundingPeriod) },
+			});
+			assert(periodAdjustment.eq(new BN(expectedPeriodAdjustment)));
+		}
+	});
+
+	it('funding since various baselines', async () => {
+		const market = longClearingHouse.getMarketAccount(marketIndex);
+		const currentCumulativeFundingRate = market.amm.cumulativeFundingRateLong;
+		const baseAssetAmount = market.baseAssetAmountLong;
+
+		for (const baselineCumulativeFundingRate of [
+			ZERO,
+			currentCumulativeFundingRate.div(new BN(2)),
+			currentCumulativeFundingRate,
+			currentCumulativeFundingRate.mul(new BN(2)),
+		]) {
+			const [fundingSince] = await longClearingHouse.view({
+				fundingSince: {
+					baselineCumulativeFundingRate,
+					currentCumulativeFundingRate,
+					baseAssetAmount,
+				},
+			});
+
+			const fundingRateDelta = currentCumulativeFundingRate.sub(
+				baselineCumulativeFundingRate
+			);
+			assert(
+				fundingSince.eq(
+					expectedFundingPayment(fundingRateDelta, baseAssetAmount)
+				)
+			);
+			// a long pays when the rate rose since the baseline and receives when it fell
+			if (!fundingRateDelta.eq(ZERO)) {
+				assert(fundingSince.isNeg() === fundingRateDelta.gt(ZERO));
+			}
+		}
+	});
+
+	it('utilization bps for a lightly and a heavily utilized amm', async () => {
+		const utilizationsBps = [];
+		for (const index of [marketIndex, thinMarketIndex]) {
+			const [utilizationBps] = await longClearingHouse.view(
+				{ utilizationBps: { marketIndex: index } },
+				[index]
+			);
+
+			const market = longClearingHouse.getMarketAccount(index);
+			const liquidity = market.amm.quoteAssetReserve
+				.mul(market.amm.pegMultiplier)
+				.div(AMM_TIMES_PEG_TO_QUOTE_PRECISION_RATIO);
+			const openInterestNotional = market.amm.netBaseAssetAmount
+				.abs()
+				.mul(
+					calculatePrice(
+						market.amm.baseAssetReserve,
+						market.amm.quoteAssetReserve,
+						market.amm.pegMultiplier
+					)
+				)
+				.div(MARK_PRICE_PRECISION)
+				.div(AMM_TO_QUOTE_PRECISION_RATIO);
+			assert(
+				utilizationBps.eq(
+					openInterestNotional.mul(new BN(10000)).div(liquidity)
+				)
+			);
+			utilizationsBps.push(utilizationBps);
+		}
+
+		const [lightUtilizationBps, heavyUtilizationBps] = utilizationsBps;
+		assert(lightUtilizationBps.lt(new BN(10)));
+		assert(heavyUtilizationBps.gt(new BN(100)));
+	});
+
+	it('funding oracle sensitivity in the clamped and unclamped regimes', async () => {
+		for (const [index, clamped] of [
+			[marketIndex, false],
+			[thinMarketIndex, true],
+		] as [BN, boolean][]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			// the legacy clamp is a thirty third of the oracle twap
+			const maxPriceSpread = amm.lastOraclePriceTwap.div(new BN(33));
+			const priceSpread = amm.lastMarkPriceTwap.sub(amm.lastOraclePriceTwap);
+			assert(priceSpread.gte(maxPriceSpread) === clamped);
+
+			const [sensitivity] = await longClearingHouse.view(
+				{ fundingOracleSensitivity: { marketIndex: index } },
+				[index]
+			);
+			// a sub-hour funding period pays a twenty fourth of the spread
+			const expectedSensitivity = clamped
+				? ZERO
+				: FUNDING_PAYMENT_PRECISION.neg().div(new BN(24));
+			assert(sensitivity.eq(expectedSensitivity));
+		}
+	});
+
+	it('funding update count increments on updates, not skipped ones', async () => {
+		// each market had one update in the setup
+		for (const index of [marketIndex, thinMarketIndex]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			assert(amm.fundingUpdateCount.eq(new BN(1)));
+		}
+
+		await longClearingHouse.updateMarketFundingFrozen(thinMarketIndex, true);
+		await waitForNextFundingTime(longClearingHouse, thinMarketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 0.97, thinOracle);
+		let txSig = await longClearingHouse.updateFundingRate(
+			thinOracle,
+			thinMarketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.marketIndex.eq(thinMarketIndex));
+		assert(isVariant(skippedRecord.reason, 'fundingPaused'));
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse
+				.getMarketAccount(thinMarketIndex)
+				.amm.fundingUpdateCount.eq(new BN(1))
+		);
+
+		await longClearingHouse.updateMarketFundingFrozen(thinMarketIndex, false);
+		await setFeedPrice(anchor.workspace.Pyth, 0.97, thinOracle);
+		txSig = await longClearingHouse.updateFundingRate(
+			thinOracle,
+			thinMarketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse
+				.getMarketAccount(thinMarketIndex)
+				.amm.fundingUpdateCount.eq(new BN(2))
+		);
+	});
+
+	it('zero oracle price skips the funding update', async () => {
+		await longClearingHouse.fetchAccounts();
+		const ammBefore = longClearingHouse.getMarketAccount(thinMarketIndex).amm;
+
+		await waitForNextFundingTime(longClearingHouse, thinMarketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 0, thinOracle);
+		const txSig = await longClearingHouse.updateFundingRate(
+			thinOracle,
+			thinMarketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.marketIndex.eq(thinMarketIndex));
+		assert(isVariant(skippedRecord.reason, 'oraclePriceZero'));
+
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(thinMarketIndex).amm;
+		assert(
+			amm.cumulativeFundingRateLong.eq(ammBefore.cumulativeFundingRateLong)
+		);
+		assert(
+			amm.cumulativeFundingRateShort.eq(ammBefore.cumulativeFundingRateShort)
+		);
+		assert(amm.lastFundingRateTs.eq(ammBefore.lastFundingRateTs));
+		assert(amm.fundingUpdateCount.eq(ammBefore.fundingUpdateCount));
+	});
+
+	it('funding acceleration across several updates', async () => {
+		for (const oraclePrice of [1.0, 0.96, 1.0]) {
+			await longClearingHouse.fetchAccounts();
+			const lastFundingRate =
+				longClearingHouse.getMarketAccount(marketIndex).amm.lastFundingRate;
+
+			await waitForNextFundingTime(longClearingHouse, marketIndex);
+			await setFeedPrice(anchor.workspace.Pyth, oraclePrice, oracle);
+			const txSig = await longClearingHouse.updateFundingRate(
+				oracle,
+				marketIndex
+			);
+			await eventSubscriber.awaitTx(txSig);
+
+			await longClearingHouse.fetchAccounts();
+			const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+			assert(amm.previousFundingRate.eq(lastFundingRate));
+
+			const [fundingAcceleration] = await longClearingHouse.view(
+				{ fundingAcceleration: { marketIndex } },
+				[marketIndex]
+			);
+			assert(
+				fundingAcceleration.eq(amm.lastFundingRate.sub(amm.previousFundingRate))
+			);
+		}
+	});
+
+	it('funding rate checksum chains across updates', async () => {
+		const fundingRateRecords = eventSubscriber
+			.getEventsArray('FundingRateRecord')
+			.filter((record) => record.marketIndex.eq(marketIndex));
+		// newest first, the last two updates of the test above
+		const [latestRecord, previousRecord] = fundingRateRecords;
+		assert(latestRecord.recordId.eq(previousRecord.recordId.addn(1)));
+		assert(
+			latestRecord.checksum.eq(
+				calculateFundingRateChecksum(
+					previousRecord.checksum,
+					latestRecord.fundingRate,
+					latestRecord.recordId
+				)
+			)
+		);
+
+		// a dropped record breaks the chain
+		assert(
+			!latestRecord.checksum.eq(
+				calculateFundingRateChecksum(
+					fundingRateRecords[2].checksum,
+					latestRecord.fundingRate,
+					latestRecord.recordId
+				)
+			)
+		);
+
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse
+				.getMarketAccount(marketIndex)
+				.amm.fundingRateChecksum.eq(latestRecord.checksum)
+		);
+	});
+
+	it('calculate funding rate predicts the recorded funding rates', async () => {
+		const calculateFundingRate = async (
+			markTwap: BN,
+			oracleTwap: BN,
+			clampDivisor: number
+		) => {
+			const [fundingRate] = await longClearingHouse.view({
+				fundingRate: {
+					markTwap,
+					oracleTwap,
+					fundingPeriod: FUNDING_PERIOD,
+					clampDivisor: new BN(clampDivisor),
+				},
+			});
+			return fundingRate;
+		};
+
+		// the updates of the acceleration test, the oracle at 0.96 clamps the spread
+		const fundingRateRecords = eventSubscriber
+			.getEventsArray('FundingRateRecord')
+			.filter((record) => record.marketIndex.eq(marketIndex))
+			.slice(0, 3);
+		assert(
+			fundingRateRecords.some(
+				(record) => !record.clampedPriceSpread.eq(record.rawPriceSpread)
+			)
+		);
+		for (const record of fundingRateRecords) {
+			const fundingRate = await calculateFundingRate(
+				record.markPriceTwap,
+				record.oraclePriceTwap,
+				0
+			);
+			assert(fundingRate.eq(record.fundingRate));
+		}
+
+		// a looser clamp lets the raw spread through
+		const [periodAdjustment] = await longClearingHouse.view({
+			periodAdjustment: { fundingPeriod: FUNDING_PERIOD },
+		});
+		for (const record of fundingRateRecords) {
+			const fundingRate = await calculateFundingRate(
+				record.markPriceTwap,
+				record.oraclePriceTwap,
+				10
+			);
+			assert(
+				fundingRate.eq(
+					record.rawPriceSpread
+						.mul(FUNDING_PAYMENT_PRECISION)
+						.div(periodAdjustment)
+				)
+			);
+		}
+	});
+
+	it('funding timing error on schedule and badly drifted', async () => {
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		const lastFundingRateTs =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastFundingRateTs;
+
+		// the update landed a few seconds after the period boundary
+		const [onScheduleErrorBps] = await longClearingHouse.view(
+			{ fundingTimingErrorBps: { marketIndex } },
+			[marketIndex]
+		);
+		const scheduleDrift = lastFundingRateTs.umod(FUNDING_PERIOD);
+		assert(
+			onScheduleErrorBps.eq(
+				scheduleDrift.mul(new BN(10000)).div(FUNDING_PERIOD)
+			)
+		);
+		assert(onScheduleErrorBps.lt(new BN(1000)));
+
+		// no keeper cranks the next update, which is half a period overdue
+		const overdueTs = lastFundingRateTs.add(
+			FUNDING_PERIOD.mul(new BN(3)).div(new BN(2))
+		);
+		for (;;) {
+			const slot = await connection.getSlot();
+			const now = await connection.getBlockTime(slot);
+			if (now !== null && overdueTs.lten(now)) {
+				break;
+			}
+			await sleep(1000);
+		}
+		const [driftedErrorBps] = await longClearingHouse.view(
+			{ fundingTimingErrorBps: { marketIndex } },
+			[marketIndex]
+		);
+		assert(driftedErrorBps.gte(onScheduleErrorBps.add(new BN(5000))));
+	});
+
+	it('clamp suppression accumulates across clamp binding updates', async () => {
+		const [periodAdjustment] = await longClearingHouse.view({
+			periodAdjustment: { fundingPeriod: FUNDING_PERIOD },
+		});
+		const cumulativeClampSuppression = async () => {
+			const [suppression] = await longClearingHouse.view(
+				{ cumulativeClampSuppression: { marketIndex } },
+				[marketIndex]
+			);
+			return suppression;
+		};
+
+		// an oracle well under the mark keeps the spread beyond the ~3% clamp,
+		// without diverging enough to block funding
+		await setFeedPrice(anchor.workspace.Pyth, 0.94, oracle);
+		let suppression = await cumulativeClampSuppression();
+		for (let i = 0; i < 2; i++) {
+			await waitForNextFundingTime(longClearingHouse, marketIndex);
+			const txSig = await longClearingHouse.updateFundingRate(
+				oracle,
+				marketIndex
+			);
+			await eventSubscriber.awaitTx(txSig);
+			const fundingRateRecord =
+				eventSubscriber.getEventsArray('FundingRateRecord')[0];
+			assert(fundingRateRecord.marketIndex.eq(marketIndex));
+
+			const unclampedFundingRate = fundingRateRecord.rawPriceSpread
+				.mul(FUNDING_PAYMENT_PRECISION)
+				.div(periodAdjustment);
+			const suppressed = unclampedFundingRate.sub(
+				fundingRateRecord.fundingRate
+			);
+			// longs would have paid more without the clamp
+			assert(suppressed.gt(ZERO));
+
+			const newSuppression = await cumulativeClampSuppression();
+			assert(newSuppression.eq(suppression.add(suppressed)));
+			suppression = newSuppression;
+		}
+
+		await setFeedPrice(anchor.workspace.Pyth, 1, oracle);
+	});
+
+	it('unsettled funding matches what settlement applies', async () => {
+		const user = await shortClearingHouse.getUserAccountPublicKey();
+		const unsettledFunding = async () => {
+			const [funding] = await shortClearingHouse.view(
+				{ unsettledFunding: { user } },
+				[marketIndex],
+				[],
+				user
+			);
+			return funding;
+		};
+
+		// the short has been receiving funding since it opened
+		const fundingBeforeSettlement = await unsettledFunding();
+		assert(fundingBeforeSettlement.gt(ZERO));
+
+		const txSig = await shortClearingHouse.settleFundingPayment(user);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingPaymentRecord = eventSubscriber.getEventsArray(
+			'FundingPaymentRecord'
+		)[0];
+		assert(fundingPaymentRecord.user.equals(user));
+		assert(fundingPaymentRecord.fundingPayment.eq(fundingBeforeSettlement));
+		assert((await unsettledFunding()).eq(ZERO));
+
+		let logs: string[] = [];
+		try {
+			await shortClearingHouse.view({ unsettledFunding: { user } }, [
+				marketIndex,
+			]);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('UnableToLoadAccountLoader')),
+			'view should fail without the user account'
+		);
+	});
+
+	it('suggested repeg scales the peg toward the oracle twap', async () => {
+		await longClearingHouse.fetchAccounts();
+		for (const index of [marketIndex, thinMarketIndex]) {
+			const amm = longClearingHouse.getMarketAccount(index).amm;
+			const [suggestedRepeg] = await longClearingHouse.view(
+				{ suggestedRepeg: { marketIndex: index } },
+				[index]
+			);
+			assert(
+				suggestedRepeg.eq(
+					amm.pegMultiplier
+						.mul(amm.lastOraclePriceTwap)
+						.div(amm.lastMarkPriceTwap)
+				)
+			);
+			// a mark twap above the oracle twap lowers the peg, and vice versa
+			assert(
+				suggestedRepeg.cmp(amm.pegMultiplier) ===
+					amm.lastOraclePriceTwap.cmp(amm.lastMarkPriceTwap)
+			);
+		}
+
+		// preloaded from tests/fixtures/zeroedAmmMarket.json, without twaps there is nothing to suggest from
+		const zeroedAmmMarket = new PublicKey(
+			'6UWp3d7cLTCuVfvfqo3vWUievpPabpSHukBLHtEgK1jQ'
+		);
+		let logs: string[] = [];
+		try {
+			await longClearingHouse.view(
+				{ suggestedRepeg: { marketIndex: new BN(99) } },
+				[],
+				[zeroedAmmMarket]
+			);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('InvalidOracle')),
+			'view should fail with InvalidOracle'
+		);
+	});
+
+	it('a keeper update before funding is due records too soon', async () => {
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		let txSig = await longClearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		const ammBefore = longClearingHouse.getMarketAccount(marketIndex).amm;
+
+		txSig = await longClearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.txSig === txSig);
+		assert(skippedRecord.marketIndex.eq(marketIndex));
+		assert(isVariant(skippedRecord.reason, 'tooSoon'));
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(amm.lastFundingRateTs.eq(ammBefore.lastFundingRateTs));
+		assert(amm.fundingUpdateCount.eq(ammBefore.fundingUpdateCount));
+
+		// fills try to update funding too, but don't record skipping it
+		txSig = await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		assert(
+			!eventSubscriber
+				.getEventsArray('FundingUpdateSkippedRecord')
+				.some((record) => record.txSig === txSig)
+		);
+	});
+
+	it('funding rate ema smooths the recorded funding rates', async () => {
+		// the default alpha tracks the raw rate
+		await longClearingHouse.fetchAccounts();
+		let amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(amm.fundingRateEmaAlpha.eq(ZERO));
+		assert(amm.fundingRateEma.eq(amm.lastFundingRate));
+
+		const alpha = new BN(5000);
+		await longClearingHouse.updateMarketFundingRateEmaAlpha(marketIndex, alpha);
+		const previousEma = amm.fundingRateEma;
+
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(
+			amm.fundingRateEma.eq(
+				amm.lastFundingRate
+					.mul(alpha)
+					.add(previousEma.mul(new BN(10000).sub(alpha)))
+					.div(new BN(10000))
+			)
+		);
+
+		// an alpha above 100% is rejected
+		let logs: string[] = [];
+		try {
+			await longClearingHouse.updateMarketFundingRateEmaAlpha(
+				marketIndex,
+				new BN(10001)
+			);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('InvalidMarketParameter')),
+			'update should fail with InvalidMarketParameter'
+		);
+
+		await longClearingHouse.updateMarketFundingRateEmaAlpha(marketIndex, ZERO);
+	});
+
+	it('oracle move since last funding for up and down moves', async () => {
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingRateRecord =
+			eventSubscriber.getEventsArray('FundingRateRecord')[0];
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const lastFundingOraclePriceTwap = amm.lastFundingOraclePriceTwap;
+		assert(lastFundingOraclePriceTwap.eq(fundingRateRecord.oraclePriceTwap));
+
+		// 5% either way of the twap the update funded against
+		const move = lastFundingOraclePriceTwap.div(new BN(20));
+		for (const signedMove of [move, move.neg()]) {
+			const [oracleMove] = await longClearingHouse.view(
+				{
+					oracleMoveSinceLastFunding: {
+						marketIndex,
+						currentOracle: lastFundingOraclePriceTwap.add(signedMove),
+					},
+				},
+				[marketIndex]
+			);
+			assert(oracleMove.eq(signedMove));
+		}
+	});
+
+	it('oracle twap brought forward to now without writing it', async () => {
+		// the twap lags an oracle move, so it has somewhere to go
+		await setFeedPrice(anchor.workspace.Pyth, 1.02, oracle);
+		const txSig = await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		const ammBefore = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(
+			!ammBefore.lastOraclePriceTwap.eq(ammBefore.lastOracleNormalisedPrice)
+		);
+		await sleep(2000);
+
+		const [oraclePriceTwap] = await longClearingHouse.view(
+			{ oraclePriceTwap: { marketIndex } },
+			[marketIndex]
+		);
+		// moved toward the last oracle price without reaching it
+		const lowerBound = BN.min(
+			ammBefore.lastOraclePriceTwap,
+			ammBefore.lastOracleNormalisedPrice
+		);
+		const upperBound = BN.max(
+			ammBefore.lastOraclePriceTwap,
+			ammBefore.lastOracleNormalisedPrice
+		);
+		assert(!oraclePriceTwap.eq(ammBefore.lastOraclePriceTwap));
+		assert(oraclePriceTwap.gt(lowerBound));
+		assert(oraclePriceTwap.lt(upperBound));
+
+		// only read, the stored twap stays put
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(amm.lastOraclePriceTwap.eq(ammBefore.lastOraclePriceTwap));
+		assert(amm.lastOraclePriceTwapTs.eq(ammBefore.lastOraclePriceTwapTs));
+	});
+
+	it('mark twap age for a fresh and a stale mark twap', async () => {
+		const markTwapAge = async () => {
+			const [age] = await longClearingHouse.view(
+				{ markTwapAge: { marketIndex } },
+				[marketIndex]
+			);
+			return age;
+		};
+
+		// a trade refreshes the mark twap
+		const txSig = await longClearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		const lastMarkPriceTwapTs =
+			longClearingHouse.getMarketAccount(marketIndex).amm.lastMarkPriceTwapTs;
+		const freshAge = await markTwapAge();
+		assert(freshAge.gte(ZERO));
+		assert(freshAge.lt(new BN(5)));
+
+		// without trading it keeps aging
+		await sleep(3000);
+		const staleAge = await markTwapAge();
+		assert(staleAge.gt(freshAge));
+		await longClearingHouse.fetchAccounts();
+		assert(
+			longClearingHouse
+				.getMarketAccount(marketIndex)
+				.amm.lastMarkPriceTwapTs.eq(lastMarkPriceTwapTs)
+		);
+	});
+
+	it('funding sign counts tally every funding update', async () => {
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+
+		const [positive, negative, zero] = await longClearingHouse.view(
+			{ fundingSignCounts: { marketIndex } },
+			[marketIndex]
+		);
+		assert(positive.eq(new BN(amm.fundingPositiveCount)));
+		assert(negative.eq(new BN(amm.fundingNegativeCount)));
+		assert(zero.eq(new BN(amm.fundingZeroCount)));
+
+		// the updates in before had the mark above the oracle
+		assert(positive.gt(ZERO));
+		assert(positive.add(negative).add(zero).eq(amm.fundingUpdateCount));
+	});
+
+	it('pricing degenerate reserves fails cleanly', async () => {
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		const priceForReserves = async (
+			quoteAssetReserve: BN,
+			baseAssetReserve: BN,
+			pegMultiplier: BN
+		) =>
+			await longClearingHouse.view({
+				priceForReserves: {
+					quoteAssetReserve,
+					baseAssetReserve,
+					pegMultiplier,
+				},
+			});
+
+		const expectError = async (reserves: [BN, BN, BN], errorName: string) => {
+			let logs: string[] = [];
+			try {
+				await priceForReserves(...reserves);
+			} catch (e) {
+				logs = e.simulationResponse?.logs ?? e.logs ?? [];
+			}
+			assert(
+				logs.some((log) => log.includes(errorName)),
+				`view should fail with ${errorName}`
+			);
+		};
+
+		await expectError(
+			[amm.quoteAssetReserve, ZERO, amm.pegMultiplier],
+			'DivideByZero'
+		);
+		// u128::MAX quote reserve, the peg multiplication overflows
+		const u128Max = new BN(2).pow(new BN(128)).sub(new BN(1));
+		await expectError(
+			[u128Max, amm.baseAssetReserve, amm.pegMultiplier],
+			'MathError'
+		);
+
+		// the market's own reserves still price
+		const [price] = await priceForReserves(
+			amm.quoteAssetReserve,
+			amm.baseAssetReserve,
+			amm.pegMultiplier
+		);
+		assert(price.gt(ZERO));
+	});
+
+	it('funding settlement preview matches the change settlement applies', async () => {
+		// fresh funding on both markets the long holds
+		for (const [index, feed, oraclePrice] of [
+			[marketIndex, oracle, 0.98],
+			[thinMarketIndex, thinOracle, 0.97],
+		] as [BN, PublicKey, number][]) {
+			await waitForNextFundingTime(longClearingHouse, index);
+			await setFeedPrice(anchor.workspace.Pyth, oraclePrice, feed);
+			const txSig = await longClearingHouse.updateFundingRate(feed, index);
+			await eventSubscriber.awaitTx(txSig);
+		}
+
+		const user = await longClearingHouse.getUserAccountPublicKey();
+		const marketIndexes = [marketIndex, thinMarketIndex];
+		const unsettledPnl = async () => {
+			await longClearingHouse.fetchAccounts();
+			return longClearingHouse
+				.getUserAccount()
+				.positions.filter((position) =>
+					marketIndexes.some((index) => index.eq(position.marketIndex))
+				)
+				.reduce((total, position) => total.add(position.unsettledPnl), ZERO);
+		};
+
+		const [preview] = await longClearingHouse.view(
+			{ unsettledFunding: { user } },
+			marketIndexes,
+			[],
+			user
+		);
+		assert(!preview.eq(ZERO));
+
+		const unsettledPnlBefore = await unsettledPnl();
+		const txSig = await longClearingHouse.settleFundingPayment(user);
+		await eventSubscriber.awaitTx(txSig);
+		assert((await unsettledPnl()).sub(unsettledPnlBefore).eq(preview));
+	});
+
+	it('reserve price rounding at exactly half a tick', async () => {
+		// MARK_PRICE_PRECISION / PEG_PRECISION
+		const priceToPegPrecisionRatio = MARK_PRICE_PRECISION.div(PEG_PRECISION);
+		const priceForReserves = async (
+			quoteAssetReserve: BN,
+			rounding: PriceRounding
+		) => {
+			const [price] = await longClearingHouse.view({
+				priceForReservesWithRounding: {
+					quoteAssetReserve,
+					baseAssetReserve: priceToPegPrecisionRatio.mul(new BN(2)),
+					pegMultiplier: new BN(1),
+					rounding,
+				},
+			});
+			return price;
+		};
+
+		// 1.5 ticks, truncation drops the half and half up rounds it away
+		const halfTickReserve = new BN(3);
+		const truncated = await priceForReserves(
+			halfTickReserve,
+			PriceRounding.TRUNCATE
+		);
+		assert(truncated.eq(new BN(1)));
+		const roundedUp = await priceForReserves(
+			halfTickReserve,
+			PriceRounding.HALF_UP
+		);
+		assert(roundedUp.eq(new BN(2)));
+
+		// a whole tick is the same either way
+		for (const rounding of [PriceRounding.TRUNCATE, PriceRounding.HALF_UP]) {
+			assert((await priceForReserves(new BN(2), rounding)).eq(new BN(1)));
+		}
+	});
+
+	it('next funding time lands on a funding period boundary', async () => {
+		await longClearingHouse.fetchAccounts();
+		const market = longClearingHouse.getMarketAccount(marketIndex);
+		const [nextFundingTime] = await longClearingHouse.view(
+			{ nextFundingTime: { marketIndex } },
+			[marketIndex]
+		);
+		assert(nextFundingTime.eq(calculateNextFundingTime(market)));
+		assert(nextFundingTime.umod(FUNDING_PERIOD).eq(ZERO));
+		assert(nextFundingTime.gt(market.amm.lastFundingRateTs));
+		assert(
+			nextFundingTime.lte(
+				market.amm.lastFundingRateTs.add(FUNDING_PERIOD.mul(new BN(2)))
+			)
+		);
+
+		// the update is accepted from then on
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(amm.lastFundingRateTs.gte(nextFundingTime));
+		const updateCountBefore = market.amm.fundingUpdateCount;
+		assert(amm.fundingUpdateCount.eq(updateCountBefore.add(new BN(1))));
+	});
+
+	it('projected funding follows the funding rate ema, not the last rate', async () => {
+		await longClearingHouse.updateMarketFundingRateEmaAlpha(
+			marketIndex,
+			new BN(2000)
+		);
+		// a varying series, so the ema trails the last rate
+		for (const oraclePrice of [0.96, 1.0]) {
+			await waitForNextFundingTime(longClearingHouse, marketIndex);
+			await setFeedPrice(anchor.workspace.Pyth, oraclePrice, oracle);
+			const txSig = await longClearingHouse.updateFundingRate(
+				oracle,
+				marketIndex
+			);
+			await eventSubscriber.awaitTx(txSig);
+		}
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(!amm.fundingRateEma.eq(amm.lastFundingRate));
+
+		const [markPrice] = await longClearingHouse.view(
+			{ markPrice: { marketIndex } },
+			[marketIndex]
+		);
+		const baseAssetAmount = AMM_RESERVE_PRECISION.mul(new BN(1000));
+		const windowSeconds = new BN(3600);
+		const projectedFunding = async (window: BN) => {
+			const [funding] = await longClearingHouse.view(
+				{
+					projectedFundingOverWindow: {
+						marketIndex,
+						baseAssetAmount,
+						markPrice,
+						windowSeconds: window,
+					},
+				},
+				[marketIndex]
+			);
+			return funding;
+		};
+		// the rate rescaled from the oracle twap it was priced against to the mark price
+		const fundingOverWindow = (fundingRate: BN) =>
+			expectedFundingPayment(
+				fundingRate
+					.mul(markPrice)
+					.div(amm.lastOraclePriceTwap)
+					.mul(windowSeconds)
+					.div(amm.fundingPeriod),
+				baseAssetAmount
+			);
+
+		const projected = await projectedFunding(windowSeconds);
+		assert(projected.eq(fundingOverWindow(amm.fundingRateEma)));
+		assert(!projected.eq(fundingOverWindow(amm.lastFundingRate)));
+		assert((await projectedFunding(ZERO)).eq(ZERO));
+
+		await longClearingHouse.updateMarketFundingRateEmaAlpha(marketIndex, ZERO);
+	});
+
+	it('pyth markets can price off the ema price', async () => {
+		await setFeedPrice(anchor.workspace.Pyth, 1, oracle);
+		// the mock feed's twap is the ema price
+		await setFeedTwap(anchor.workspace.Pyth, 0.9, oracle);
+		const oraclePriceWithPythEma = async (usePythEmaPrice: boolean) =>
+			await longClearingHouse.view(
+				{ oraclePriceWithPythEma: { marketIndex, usePythEmaPrice } },
+				[marketIndex]
+			);
+
+		const [aggregatePrice, aggregateConfidence] =
+			await oraclePriceWithPythEma(false);
+		assert(aggregatePrice.eq(MARK_PRICE_PRECISION));
+		const [emaPrice, emaConfidence] = await oraclePriceWithPythEma(true);
+		assert(emaPrice.eq(MARK_PRICE_PRECISION.mul(new BN(9)).div(new BN(10))));
+		// only the price is swapped, the confidence is still the aggregate's
+		assert(emaConfidence.eq(aggregateConfidence));
+
+		await setFeedTwap(anchor.workspace.Pyth, 1, oracle);
+	});
+
+	it('twap pair reads both twaps from the same update', async () => {
+		await waitForNextFundingTime(longClearingHouse, marketIndex);
+		const txSig = await longClearingHouse.updateFundingRate(
+			oracle,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const fundingRateRecord =
+			eventSubscriber.getEventsArray('FundingRateRecord')[0];
+		assert(fundingRateRecord.txSig === txSig);
+
+		const [markTwap, oracleTwap] = await longClearingHouse.view(
+			{ twapPair: { marketIndex } },
+			[marketIndex]
+		);
+		await longClearingHouse.fetchAccounts();
+		const amm = longClearingHouse.getMarketAccount(marketIndex).amm;
+		assert(markTwap.eq(amm.lastMarkPriceTwap));
+		assert(oracleTwap.eq(amm.lastOraclePriceTwap));
+		assert(oracleTwap.eq(fundingRateRecord.oraclePriceTwap));
+		assert(amm.lastMarkPriceTwapTs.eq(amm.lastOraclePriceTwapTs));
+	});
+
+	it('funding payment history keeps the last eight payments', async () => {
+		const user = await longClearingHouse.getUserAccountPublicKey();
+		// a payment on each of the long's two markets per round
+		const payments = [];
+		for (let round = 0; round < 4; round++) {
+			for (const [index, feed] of [
+				[marketIndex, oracle],
+				[thinMarketIndex, thinOracle],
+			] as [BN, PublicKey][]) {
+				await waitForNextFundingTime(longClearingHouse, index);
+				const txSig = await longClearingHouse.updateFundingRate(feed, index);
+				await eventSubscriber.awaitTx(txSig);
+			}
+
+			const txSig = await longClearingHouse.settleFundingPayment(user);
+			await eventSubscriber.awaitTx(txSig);
+			const fundingPaymentRecords = eventSubscriber
+				.getEventsArray('FundingPaymentRecord')
+				.filter((record) => record.txSig === txSig)
+				.reverse();
+			assert(fundingPaymentRecords.length === 2);
+			payments.push(...fundingPaymentRecords);
+		}
+
+		// eight payments wrap the buffer once, leaving the oldest at the head
+		await longClearingHouse.fetchAccounts();
+		const history = longClearingHouse.getUserAccount().fundingPaymentHistory;
+		payments.forEach((payment, i) => {
+			const entry = history.entries[(history.head + i) % 8];
+			assert(entry.ts.eq(payment.ts));
+			assert(entry.marketIndex.eq(payment.marketIndex));
+			assert(entry.fundingPayment.eq(payment.fundingPayment));
+		});
+	});
+});
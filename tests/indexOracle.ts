@@ -0,0 +1,150 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PEG_PRECISION,
+	PositionDirection,
+	QUOTE_PRECISION,
+	ZERO,
+	getIndexPricePublicKey,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('index oracle', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	let indexPrice: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		// the wallet maintains the index, published with 4 decimals
+		indexPrice = await getIndexPricePublicKey(
+			chProgram.programId,
+			provider.wallet.publicKey
+		);
+		await clearingHouse.initializeIndexPrice(-4);
+		await clearingHouse.updateIndexPrice(new BN(10000), new BN(1));
+
+		await clearingHouse.initializeMarket(
+			indexPrice,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60),
+			PEG_PRECISION,
+			OracleSource.INDEX
+		);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('reads the index like a pyth price', async () => {
+		const [price, confidence] = await clearingHouse.view(
+			{ oraclePrice: { oracle: indexPrice } },
+			[],
+			[indexPrice]
+		);
+		assert(price.eq(MARK_PRICE_PRECISION));
+		assert(confidence.eq(MARK_PRICE_PRECISION.div(new BN(10000))));
+	});
+
+	it('drives funding off the index', async () => {
+		// mark at about 1.0 and the index dropping to 0.98, so longs pay
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(100)),
+			marketIndex
+		);
+		await clearingHouse.updateIndexPrice(new BN(9800), new BN(1));
+
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		const txSig = await clearingHouse.updateFundingRate(
+			indexPrice,
+			marketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+
+		const fundingRateRecord =
+			eventSubscriber.getEventsArray('FundingRateRecord')[0];
+		assert(fundingRateRecord.marketIndex.eq(marketIndex));
+		// the twap moved from the index's 1.0 toward its 0.98
+		assert(fundingRateRecord.oraclePriceTwap.lt(MARK_PRICE_PRECISION));
+		assert(
+			fundingRateRecord.oraclePriceTwap.gte(
+				MARK_PRICE_PRECISION.mul(new BN(98)).div(new BN(100))
+			)
+		);
+		assert(fundingRateRecord.fundingRate.gt(ZERO));
+
+		await clearingHouse.fetchAccounts();
+		const market = clearingHouse.getMarketAccount(marketIndex);
+		assert(market.amm.cumulativeFundingRateLong.gt(ZERO));
+	});
+});
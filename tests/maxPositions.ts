@@ -18,6 +18,7 @@ import {
 	mockUserUSDCAccount,
 	setFeedPrice,
 	initializeQuoteAssetBank,
+	waitForNextFundingTime,
 } from './testHelpers';
 
 describe('max positions', () => {
@@ -72,7 +73,7 @@ describe('max positions', () => {
 
 		for (let i = 0; i < maxPositions; i++) {
 			const oracle = await mockOracle(1);
-			const periodicity = new BN(0);
+			const periodicity = new BN(60);
 
 			await clearingHouse.initializeMarket(
 				oracle,
@@ -111,6 +112,8 @@ describe('max positions', () => {
 
 	it('partial liquidate', async () => {
 		for (let i = 0; i < maxPositions; i++) {
+			// wait before setting the feed so the oracle isn't stale for the funding update
+			await waitForNextFundingTime(clearingHouse, i);
 			const oracle = clearingHouse.getMarketAccount(i).amm.oracle;
 			await setFeedPrice(anchor.workspace.Pyth, 0.83, oracle);
 			await clearingHouse.updateFundingRate(oracle, new BN(i));
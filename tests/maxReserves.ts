@@ -21,6 +21,7 @@ import {
 	mockUSDCMint,
 	mockUserUSDCAccount,
 	setFeedPrice,
+	waitForNextFundingTime,
 } from './testHelpers';
 
 describe('max reserves', () => {
@@ -80,7 +81,7 @@ describe('max reserves', () => {
 
 		for (let i = 0; i < maxPositions; i++) {
 			const oracle = await mockOracle(1);
-			const periodicity = new BN(0);
+			const periodicity = new BN(60);
 
 			await clearingHouse.initializeMarket(
 				oracle,
@@ -121,6 +122,8 @@ describe('max reserves', () => {
 
 	it('partial liquidate', async () => {
 		for (let i = 0; i < maxPositions; i++) {
+			// wait before setting the feed so the oracle isn't stale for the funding update
+			await waitForNextFundingTime(clearingHouse, i);
 			const oracle = clearingHouse.getMarketAccount(i).amm.oracle;
 			await setFeedPrice(anchor.workspace.Pyth, 0.85, oracle);
 			await clearingHouse.updateFundingRate(oracle, new BN(i));
@@ -0,0 +1,216 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	isVariant,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PositionDirection,
+	QUOTE_PRECISION,
+	ZERO,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	setFeedTwap,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('negative oracle', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	let oracle: PublicKey;
+	// a market whose oracle twap starts out negative
+	const twapMarketIndex = new BN(1);
+	let twapOracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		oracle = await mockOracle(1);
+		twapOracle = await mockOracle(1);
+		await setFeedTwap(anchor.workspace.Pyth, -1, twapOracle);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex, twapMarketIndex],
+			[new BN(0)],
+			[
+				{ publicKey: oracle, source: OracleSource.PYTH },
+				{ publicKey: twapOracle, source: OracleSource.PYTH },
+			]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+		// the pyth twap seeds the market's oracle twap
+		await clearingHouse.initializeMarket(
+			twapOracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	// the market's oracle price as read with its market's settings
+	const marketOraclePrice = async () => {
+		const [price] = await clearingHouse.view(
+			{
+				oraclePriceWithConversion: {
+					marketIndex,
+					oraclePriceMultiplier: new BN(1),
+					oraclePriceDivisor: new BN(1),
+				},
+			},
+			[marketIndex]
+		);
+		return price;
+	};
+
+	const expectInvalidOraclePrice = async (
+		action: () => Promise<unknown>,
+		message: string
+	) => {
+		let logs: string[] = [];
+		try {
+			await action();
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(logs.some((log) => log.includes('InvalidOraclePrice')), message);
+	};
+
+	it('rejects a negative price unless the market allows it', async () => {
+		await setFeedPrice(anchor.workspace.Pyth, -2, oracle);
+
+		await expectInvalidOraclePrice(
+			marketOraclePrice,
+			'reading a negative price should fail with InvalidOraclePrice'
+		);
+		await expectInvalidOraclePrice(
+			() =>
+				clearingHouse.openPosition(
+					PositionDirection.LONG,
+					QUOTE_PRECISION,
+					marketIndex
+				),
+			'trading against a negative price should fail with InvalidOraclePrice'
+		);
+
+		await clearingHouse.updateMarketAllowNegativeOracle(marketIndex, true);
+		assert(
+			(await marketOraclePrice()).eq(MARK_PRICE_PRECISION.mul(new BN(-2)))
+		);
+	});
+
+	it('rejects a zero price even if the market allows negative prices', async () => {
+		await setFeedPrice(anchor.workspace.Pyth, 0, oracle);
+		await expectInvalidOraclePrice(
+			marketOraclePrice,
+			'reading a zero price should fail with InvalidOraclePrice'
+		);
+
+		await setFeedPrice(anchor.workspace.Pyth, 1, oracle);
+		assert((await marketOraclePrice()).eq(MARK_PRICE_PRECISION));
+	});
+
+	it('skips funding while the oracle twap is not a valid price', async () => {
+		await clearingHouse.fetchAccounts();
+		const ammBefore = clearingHouse.getMarketAccount(twapMarketIndex).amm;
+		assert(ammBefore.lastOraclePriceTwap.lt(ZERO));
+
+		// the update toward a price of 1 is capped to a third of it, leaving the twap negative
+		await waitForNextFundingTime(clearingHouse, twapMarketIndex);
+		await setFeedPrice(anchor.workspace.Pyth, 1, twapOracle);
+		let txSig = await clearingHouse.updateFundingRate(
+			twapOracle,
+			twapMarketIndex
+		);
+		await eventSubscriber.awaitTx(txSig);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.marketIndex.eq(twapMarketIndex));
+		assert(isVariant(skippedRecord.reason, 'oracleInvalid'));
+
+		await clearingHouse.fetchAccounts();
+		let amm = clearingHouse.getMarketAccount(twapMarketIndex).amm;
+		assert(amm.lastOraclePriceTwap.eq(ammBefore.lastOraclePriceTwap));
+		assert(amm.lastFundingRateTs.eq(ammBefore.lastFundingRateTs));
+		assert(amm.fundingUpdateCount.eq(ZERO));
+
+		// realigning the twap with the feed resumes funding
+		await setFeedTwap(anchor.workspace.Pyth, 1, twapOracle);
+		await setFeedPrice(anchor.workspace.Pyth, 1, twapOracle);
+		await clearingHouse.updateAmmOracleTwap(twapMarketIndex);
+		txSig = await clearingHouse.updateFundingRate(twapOracle, twapMarketIndex);
+		await eventSubscriber.awaitTx(txSig);
+
+		await clearingHouse.fetchAccounts();
+		amm = clearingHouse.getMarketAccount(twapMarketIndex).amm;
+		assert(amm.lastOraclePriceTwap.gt(ZERO));
+		assert(amm.fundingUpdateCount.eq(new BN(1)));
+	});
+});
@@ -0,0 +1,144 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PositionDirection,
+	QUOTE_PRECISION,
+	ZERO,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	sleep,
+} from './testHelpers';
+
+describe('open interest twap', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	let oracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		oracle = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[{ publicKey: oracle, source: OracleSource.PYTH }]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			oracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+	});
+
+	const timeWeightedOi = async (windowSeconds: number) => {
+		const [oi] = await clearingHouse.view(
+			{
+				timeWeightedOi: { marketIndex, windowSeconds: new BN(windowSeconds) },
+			},
+			[marketIndex]
+		);
+		return oi;
+	};
+
+	const openInterest = async () => {
+		await clearingHouse.fetchAccounts();
+		const amm = clearingHouse.getMarketAccount(marketIndex).amm;
+		return amm.quoteAssetAmountLong.add(amm.quoteAssetAmountShort);
+	};
+
+	it('smooths step changes in open interest over the window', async () => {
+		assert((await timeWeightedOi(60)).eq(ZERO));
+
+		// first step, from no open interest
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(100)),
+			marketIndex
+		);
+		const firstStepOi = await openInterest();
+		await sleep(2000);
+
+		// only the seconds since the step are at the new level, a smaller share of a longer window
+		const firstShortWindowOi = await timeWeightedOi(60);
+		const firstLongWindowOi = await timeWeightedOi(3600);
+		assert(firstLongWindowOi.gt(ZERO));
+		assert(firstLongWindowOi.lt(firstShortWindowOi));
+		assert(firstShortWindowOi.lt(firstStepOi));
+
+		// second step, roughly doubling the open interest
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(100)),
+			marketIndex
+		);
+		const secondStepOi = await openInterest();
+		assert(secondStepOi.gt(firstStepOi));
+		await sleep(2000);
+
+		// moves toward the new level without jumping to it
+		const secondShortWindowOi = await timeWeightedOi(60);
+		assert(secondShortWindowOi.gt(firstShortWindowOi));
+		assert(secondShortWindowOi.lt(secondStepOi));
+	});
+});
@@ -0,0 +1,161 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import {
+	Admin,
+	BN,
+	MARK_PRICE_PRECISION,
+	PositionDirection,
+	QUOTE_PRECISION,
+	OracleSource,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	sleep,
+} from './testHelpers';
+
+describe('oracle ema', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const marketIndex = new BN(0);
+	// a long funding period keeps the twap slow, and funding from updating during the test
+	const fundingPeriod = new BN(60 * 60);
+	const oracleEmaHalflife = new BN(4);
+	let solUsd;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		solUsd = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[{ publicKey: solUsd, source: OracleSource.PYTH }]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			solUsd,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			fundingPeriod
+		);
+		await clearingHouse.updateMarketOracleEmaHalflife(
+			marketIndex,
+			oracleEmaHalflife
+		);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+	});
+
+	it('converges to a new oracle price faster than the twap', async () => {
+		// the first fill seeds the ema with the oracle price
+		await clearingHouse.openPosition(
+			PositionDirection.LONG,
+			QUOTE_PRECISION.mul(new BN(10)),
+			marketIndex
+		);
+		await clearingHouse.fetchAccounts();
+		let amm = clearingHouse.getMarketAccount(marketIndex).amm;
+		assert(amm.oraclePriceEma.eq(amm.lastOracleNormalisedPrice));
+		const seededEma = amm.oraclePriceEma;
+
+		await setFeedPrice(anchor.workspace.Pyth, 1.05, solUsd);
+
+		let lastEmaGap;
+		const directions = [
+			PositionDirection.SHORT,
+			PositionDirection.LONG,
+			PositionDirection.SHORT,
+		];
+		for (const direction of directions) {
+			const lastEma = amm.oraclePriceEma;
+			const lastOraclePriceTwapTs = amm.lastOraclePriceTwapTs;
+
+			await sleep(2000);
+			// fills update the oracle twap and ema
+			await clearingHouse.openPosition(
+				direction,
+				QUOTE_PRECISION.mul(new BN(10)),
+				marketIndex
+			);
+			await clearingHouse.fetchAccounts();
+			amm = clearingHouse.getMarketAccount(marketIndex).amm;
+
+			const oraclePrice = amm.lastOracleNormalisedPrice;
+			const sinceLast = amm.lastOraclePriceTwapTs.sub(lastOraclePriceTwapTs);
+			const expectedEma = lastEma
+				.mul(oracleEmaHalflife)
+				.add(oraclePrice.mul(sinceLast))
+				.div(oracleEmaHalflife.add(sinceLast));
+			assert(amm.oraclePriceEma.eq(expectedEma));
+
+			const emaGap = oraclePrice.sub(amm.oraclePriceEma).abs();
+			const twapGap = oraclePrice.sub(amm.lastOraclePriceTwap).abs();
+			assert(emaGap.lt(twapGap));
+			if (lastEmaGap !== undefined) {
+				assert(emaGap.lt(lastEmaGap));
+			}
+			lastEmaGap = emaGap;
+		}
+
+		// over several halflifes the ema closes most of the gap, while the hourly twap has barely moved
+		const oraclePrice = amm.lastOracleNormalisedPrice;
+		const initialGap = oraclePrice.sub(seededEma);
+		assert(lastEmaGap.mul(new BN(2)).lt(initialGap));
+		assert(
+			oraclePrice
+				.sub(amm.lastOraclePriceTwap)
+				.mul(new BN(2))
+				.gt(initialGap)
+		);
+	});
+});
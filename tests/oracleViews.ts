@@ -0,0 +1,513 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import { Admin, BN, MARK_PRICE_PRECISION, ZERO } from '../sdk/src';
+
+import { mockOracle, mockUSDCMint, setFeedPrice, sleep } from './testHelpers';
+
+describe('oracle views', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	let clearingHouse: Admin;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[],
+			[],
+			[]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+	});
+
+	it('confidence bps for tight and wide confidence', async () => {
+		// price 10, confidence 0.1 (expo -7)
+		const tightOracle = await mockOracle(10, -7, 10 ** 6);
+		const [tightConfidenceBps] = await clearingHouse.view(
+			{ oracleConfidenceBps: { oracle: tightOracle } },
+			[],
+			[tightOracle]
+		);
+		assert(tightConfidenceBps.eq(new BN(100)));
+
+		// price 10, confidence 5
+		const wideOracle = await mockOracle(10, -7, 5 * 10 ** 7);
+		const [wideConfidenceBps] = await clearingHouse.view(
+			{ oracleConfidenceBps: { oracle: wideOracle } },
+			[],
+			[wideOracle]
+		);
+		assert(wideConfidenceBps.eq(new BN(5000)));
+	});
+
+	it('confidence bps errors for a zero price', async () => {
+		const zeroPriceOracle = await mockOracle(0, -7, 10 ** 6);
+		let failed = false;
+		try {
+			await clearingHouse.view(
+				{ oracleConfidenceBps: { oracle: zeroPriceOracle } },
+				[],
+				[zeroPriceOracle]
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed, 'confidence bps should reject a zero price');
+	});
+
+	it('haircut collateral value for tight and wide confidence', async () => {
+		const amount = new BN(10 ** 6);
+
+		// price 10, confidence 0.1 (expo -7)
+		const tightOracle = await mockOracle(10, -7, 10 ** 6);
+		const [tightHaircutValue] = await clearingHouse.view(
+			{ haircutCollateralValue: { oracle: tightOracle, amount } },
+			[],
+			[tightOracle]
+		);
+		const fullValue = amount.mul(new BN(10));
+		assert(tightHaircutValue.eq(amount.mul(new BN(99)).div(new BN(10))));
+		assert(tightHaircutValue.lt(fullValue));
+
+		// price 10, confidence 5
+		const wideOracle = await mockOracle(10, -7, 5 * 10 ** 7);
+		const [wideHaircutValue] = await clearingHouse.view(
+			{ haircutCollateralValue: { oracle: wideOracle, amount } },
+			[],
+			[wideOracle]
+		);
+		assert(wideHaircutValue.eq(fullValue.div(new BN(2))));
+		assert(wideHaircutValue.lt(tightHaircutValue));
+	});
+
+	it('slots until stale for fresh and near stale readings', async () => {
+		const oracle = await mockOracle(10);
+		const slotBeforeUpdate = await connection.getSlot();
+		// stamps the reading with the current slot
+		await setFeedPrice(anchor.workspace.Pyth, 10, oracle);
+		const slotAfterUpdate = await connection.getSlot();
+
+		const maxDelay = new BN(100);
+		const [freshSlotsUntilStale] = await clearingHouse.view(
+			{ slotsUntilStale: { oracle, maxDelay } },
+			[],
+			[oracle]
+		);
+		const maxElapsed = (await connection.getSlot()) - slotBeforeUpdate;
+		assert(freshSlotsUntilStale.lte(maxDelay));
+		assert(freshSlotsUntilStale.gte(maxDelay.sub(new BN(maxElapsed))));
+
+		while ((await connection.getSlot()) - slotAfterUpdate < 3) {
+			await sleep(400);
+		}
+		const minElapsed = (await connection.getSlot()) - slotAfterUpdate;
+
+		// one slot of headroom past the youngest the reading can be
+		const [nearStaleSlotsUntilStale] = await clearingHouse.view(
+			{ slotsUntilStale: { oracle, maxDelay: new BN(minElapsed + 1) } },
+			[],
+			[oracle]
+		);
+		assert(nearStaleSlotsUntilStale.lte(new BN(1)));
+
+		// already stale clamps to zero rather than going negative
+		const [staleSlotsUntilStale] = await clearingHouse.view(
+			{ slotsUntilStale: { oracle, maxDelay: new BN(1) } },
+			[],
+			[oracle]
+		);
+		assert(staleSlotsUntilStale.eq(ZERO));
+	});
+
+	it('checked oracle price rejects a reading older than the max delay', async () => {
+		const oracle = await mockOracle(10);
+		// stamps the reading with the current slot
+		await setFeedPrice(anchor.workspace.Pyth, 10, oracle);
+		const slotAfterUpdate = await connection.getSlot();
+
+		const [price, , delay] = await clearingHouse.view(
+			{ oraclePriceChecked: { oracle, maxDelay: new BN(100) } },
+			[],
+			[oracle]
+		);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(10))));
+		assert(delay.lte(new BN(100)));
+
+		while ((await connection.getSlot()) - slotAfterUpdate < 3) {
+			await sleep(400);
+		}
+
+		let logs: string[] = [];
+		try {
+			await clearingHouse.view(
+				{ oraclePriceChecked: { oracle, maxDelay: new BN(1) } },
+				[],
+				[oracle]
+			);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('StaleOracle')),
+			'view should fail with StaleOracle'
+		);
+	});
+
+	it('price bounds widen with the confidence', async () => {
+		for (const confidence of [10 ** 6, 5 * 10 ** 7]) {
+			// price 10, confidence in 1e-7 units
+			const oracle = await mockOracle(10, -7, confidence);
+			const [lowerBound, price, upperBound] = await clearingHouse.view(
+				{ oraclePriceWithBounds: { oracle } },
+				[],
+				[oracle]
+			);
+			const scaledConfidence = MARK_PRICE_PRECISION.mul(
+				new BN(confidence)
+			).div(new BN(10 ** 7));
+			assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(10))));
+			assert(lowerBound.eq(price.sub(scaledConfidence)));
+			assert(upperBound.eq(price.add(scaledConfidence)));
+		}
+	});
+
+	it('price in decimals scaling down and up', async () => {
+		const oracle = await mockOracle(12.5);
+
+		// MARK_PRICE_PRECISION has 10 decimals
+		for (const [targetDecimals, expectedPrice] of [
+			[6, new BN(12500000)],
+			[10, MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10))],
+			[12, new BN(10).pow(new BN(12)).mul(new BN(125)).div(new BN(10))],
+		] as [number, BN][]) {
+			const [price] = await clearingHouse.view(
+				{ priceInDecimals: { oracle, targetDecimals } },
+				[],
+				[oracle]
+			);
+			assert(price.eq(expectedPrice));
+		}
+	});
+
+	it('switchboard round is stale once it is too many slots old', async () => {
+		// preloaded from tests/fixtures/switchboardAggregator.json, its latest round opened at slot 0
+		const switchboardOracle = new PublicKey(
+			'6VAjAU91KKndM3w6PgLMNxnu3J3bKfbQpN5yDBN5riof'
+		);
+
+		const [price, _confidence, _delay, recentHasSufficientDataPoints] =
+			await clearingHouse.view(
+				{ oraclePrice: { oracle: switchboardOracle } },
+				[],
+				[switchboardOracle]
+			);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10))));
+		assert(recentHasSufficientDataPoints.eq(new BN(1)));
+
+		// SWITCHBOARD_MAX_ROUND_AGE_SLOTS
+		const maxRoundAgeSlots = 1000;
+		for (const [slot, hasSufficientDataPoints] of [
+			[maxRoundAgeSlots, 1],
+			[maxRoundAgeSlots + 1, 0],
+		]) {
+			const [_price, _confidence, delay, sufficientDataPoints] =
+				await clearingHouse.view(
+					{
+						oraclePriceAtSlot: {
+							oracle: switchboardOracle,
+							slot: new BN(slot),
+						},
+					},
+					[],
+					[switchboardOracle]
+				);
+			assert(delay.eq(new BN(slot)));
+			assert(sufficientDataPoints.eq(new BN(hasSufficientDataPoints)));
+		}
+	});
+
+	it('flags a switchboard round with a std deviation half its price', async () => {
+		// preloaded from tests/fixtures/switchboardAggregatorLive.json and switchboardAggregatorWide.json,
+		// both price 12.5 with std deviations of 0.01 and 6.25
+		const liveOracle = new PublicKey(
+			'7gECj3S6c9miGqPNhkQnDnYXxecbEFTrf7TfEUVLDq6y'
+		);
+		const wideOracle = new PublicKey(
+			'Aa7YBnFZhmsEyj5jqzPyCMLdzE3kz7unq5F8pueetovF'
+		);
+
+		const [_livePrice, _liveConfidence, _liveDelay, liveSufficientDataPoints] =
+			await clearingHouse.view(
+				{ oraclePrice: { oracle: liveOracle } },
+				[],
+				[liveOracle]
+			);
+		assert(liveSufficientDataPoints.eq(new BN(1)));
+
+		// 5000bps of price, past DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS (1000)
+		const [price, confidence, _delay, sufficientDataPoints] =
+			await clearingHouse.view(
+				{ oraclePrice: { oracle: wideOracle } },
+				[],
+				[wideOracle]
+			);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10))));
+		assert(confidence.eq(price.div(new BN(2))));
+		assert(sufficientDataPoints.eq(ZERO));
+	});
+
+	it('reads legacy and price feed pyth layouts', async () => {
+		// price 12.5, confidence 0.0125
+		const legacyOracle = await mockOracle(12.5, -7, 125000);
+		// preloaded from tests/fixtures/pythPriceUpdateV2.json, same price and confidence (expo -8)
+		const priceUpdateOracle = new PublicKey(
+			'4TDDETJPRbJ26sAdxxVpbpTug4EvLQ9NqGnd1pT57nna'
+		);
+
+		for (const oracle of [legacyOracle, priceUpdateOracle]) {
+			const [price, confidence] = await clearingHouse.view(
+				{ oraclePrice: { oracle } },
+				[],
+				[oracle]
+			);
+			assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10))));
+			assert(confidence.eq(MARK_PRICE_PRECISION.div(new BN(80))));
+		}
+	});
+
+	it('scales pyth prices with zero and positive exponents', async () => {
+		// the raw price and confidence are whole units for expo 0 and hundreds for expo 2
+		for (const [price, expo, expectedConfidence] of [
+			[125, 0, 1],
+			[120000, 2, 100],
+		]) {
+			const oracle = await mockOracle(price, expo, 1);
+			const [scaledPrice, confidence] = await clearingHouse.view(
+				{ oraclePrice: { oracle } },
+				[],
+				[oracle]
+			);
+			assert(scaledPrice.eq(MARK_PRICE_PRECISION.mul(new BN(price))));
+			assert(
+				confidence.eq(MARK_PRICE_PRECISION.mul(new BN(expectedConfidence)))
+			);
+		}
+	});
+
+	it('quote asset price is pegged unless a backing feed is passed', async () => {
+		const [peggedPrice, peggedConfidence] = await clearingHouse.view({
+			quoteAssetPrice: { quoteOracle: PublicKey.default },
+		});
+		assert(peggedPrice.eq(MARK_PRICE_PRECISION));
+		assert(peggedConfidence.eq(new BN(1)));
+
+		// a USDC/USD feed during a depeg
+		const usdcOracle = await mockOracle(0.97, -7, 10 ** 4);
+		const [price, confidence] = await clearingHouse.view(
+			{ quoteAssetPrice: { quoteOracle: usdcOracle } },
+			[],
+			[usdcOracle]
+		);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(97)).div(new BN(100))));
+		assert(confidence.eq(MARK_PRICE_PRECISION.div(new BN(1000))));
+	});
+
+	it('rejects a price feed account with the wrong owner', async () => {
+		// owned by the pyth receiver program
+		const priceUpdateOracle = new PublicKey(
+			'4TDDETJPRbJ26sAdxxVpbpTug4EvLQ9NqGnd1pT57nna'
+		);
+		const [price] = await clearingHouse.view(
+			{ oraclePrice: { oracle: priceUpdateOracle } },
+			[],
+			[priceUpdateOracle]
+		);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10))));
+
+		// preloaded from tests/fixtures/pythPriceUpdateV2WrongOwner.json, owned by the legacy pyth program
+		const wrongOwnerOracle = new PublicKey(
+			'GBspUdGzgK57YytaVmn9dZ6KRivHBNECKA3aAk1sv4xe'
+		);
+		let logs: string[] = [];
+		try {
+			await clearingHouse.view(
+				{ oraclePrice: { oracle: wrongOwnerOracle } },
+				[],
+				[wrongOwnerOracle]
+			);
+		} catch (e) {
+			logs = e.simulationResponse?.logs ?? e.logs ?? [];
+		}
+		assert(
+			logs.some((log) => log.includes('OracleAccountWrongOwner')),
+			'view should fail with OracleAccountWrongOwner'
+		);
+	});
+
+	it('flags readings with a nonsensical confidence or price', async () => {
+		for (const [price, confidence, expectedSufficientDataPoints] of [
+			// price 10, confidence 0.1 (expo -7)
+			[10, 10 ** 6, 1],
+			// a published confidence of zero
+			[10, 0, 0],
+			// confidence 11, wider than the price itself
+			[10, 11 * 10 ** 7, 0],
+			// a zero price
+			[0, 10 ** 6, 0],
+		]) {
+			const oracle = await mockOracle(price, -7, confidence);
+			const [_price, _confidence, _delay, sufficientDataPoints] =
+				await clearingHouse.view({ oraclePrice: { oracle } }, [], [oracle]);
+			assert(sufficientDataPoints.eq(new BN(expectedSufficientDataPoints)));
+		}
+	});
+
+	it('hedge ratio for a couple of price pairs', async () => {
+		const tenOracle = await mockOracle(10);
+		const quarterOracle = await mockOracle(2.5);
+		for (const [oracleA, oracleB, expectedHedgeRatio] of [
+			// one base at 10 is offset by four at 2.5
+			[tenOracle, quarterOracle, MARK_PRICE_PRECISION.mul(new BN(4))],
+			[quarterOracle, tenOracle, MARK_PRICE_PRECISION.div(new BN(4))],
+			[tenOracle, tenOracle, MARK_PRICE_PRECISION],
+		] as [PublicKey, PublicKey, BN][]) {
+			const [hedgeRatio] = await clearingHouse.view(
+				{ hedgeRatio: { oracleA, oracleB } },
+				[],
+				[oracleA, oracleB]
+			);
+			assert(hedgeRatio.eq(expectedHedgeRatio));
+		}
+
+		const zeroPriceOracle = await mockOracle(0);
+		let failed = false;
+		try {
+			await clearingHouse.view(
+				{ hedgeRatio: { oracleA: tenOracle, oracleB: zeroPriceOracle } },
+				[],
+				[tenOracle, zeroPriceOracle]
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed, 'hedge ratio should reject a zero price for market b');
+	});
+
+	it('reads the latest chainlink transmission', async () => {
+		// preloaded from tests/fixtures/chainlinkTransmissions.json, an older transmission of 11 precedes it
+		const chainlinkOracle = new PublicKey(
+			'E7tzQC6yxAQNot9FGhWSvuy7ppJohmvVZ19yPCPrMKoE'
+		);
+		const [price, confidence, delay, sufficientDataPoints] =
+			await clearingHouse.view(
+				{ oraclePrice: { oracle: chainlinkOracle } },
+				[],
+				[chainlinkOracle]
+			);
+		const expectedPrice = MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10));
+		assert(price.eq(expectedPrice));
+		// no published deviation, so the 10bps of price floor
+		assert(confidence.eq(expectedPrice.div(new BN(1000))));
+		// updated at slot 0, so the delay is the slot the view ran at
+		assert(delay.gt(ZERO));
+		assert(sufficientDataPoints.eq(new BN(1)));
+	});
+
+	it('median price across the feeds with sufficient data points', async () => {
+		// confidence 0.1, 0.2 and 0.3 (expo -7)
+		const lowOracle = await mockOracle(10, -7, 10 ** 6);
+		const midOracle = await mockOracle(11, -7, 2 * 10 ** 6);
+		const highOracle = await mockOracle(30, -7, 3 * 10 ** 6);
+		const [price, confidence] = await clearingHouse.view(
+			{ oraclePriceMedian: {} },
+			[],
+			[lowOracle, midOracle, highOracle]
+		);
+		assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(11))));
+		// the widest of the contributing feeds
+		assert(confidence.eq(MARK_PRICE_PRECISION.mul(new BN(3)).div(new BN(10))));
+
+		// a published confidence of zero drops the feed, leaving the middle of the other two
+		const noConfidenceOracle = await mockOracle(30, -7, 0);
+		const [evenPrice, evenConfidence] = await clearingHouse.view(
+			{ oraclePriceMedian: {} },
+			[],
+			[lowOracle, midOracle, noConfidenceOracle]
+		);
+		assert(evenPrice.eq(MARK_PRICE_PRECISION.mul(new BN(105)).div(new BN(10))));
+		assert(
+			evenConfidence.eq(MARK_PRICE_PRECISION.mul(new BN(2)).div(new BN(10)))
+		);
+
+		let failed = false;
+		try {
+			await clearingHouse.view(
+				{ oraclePriceMedian: {} },
+				[],
+				[noConfidenceOracle]
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed, 'median should fail without a feed to aggregate');
+	});
+
+	it('reports every contributor behind the median price', async () => {
+		const oraclePrices = new Map<string, [number, number]>();
+		for (const [price, confidence] of [
+			[10, 10 ** 6],
+			[11, 2 * 10 ** 6],
+			// left out of the median, but still reported
+			[30, 0],
+		]) {
+			const oracle = await mockOracle(price, -7, confidence);
+			oraclePrices.set(oracle.toBase58(), [price, confidence]);
+		}
+		const oracles = [...oraclePrices.keys()].map((key) => new PublicKey(key));
+
+		const contributors = await clearingHouse.view(
+			{ oraclePriceContributors: {} },
+			[],
+			oracles
+		);
+		// reported in pubkey order
+		oracles.sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()));
+		assert(contributors.length === oracles.length * 6);
+		for (const [i, oracle] of oracles.entries()) {
+			const [source, read, price, confidence, _delay, sufficientDataPoints] =
+				contributors.slice(i * 6, (i + 1) * 6);
+			const [expectedPrice, expectedConfidence] = oraclePrices.get(
+				oracle.toBase58()
+			);
+			// OracleSource.PYTH
+			assert(source.eq(ZERO));
+			assert(read.eq(new BN(1)));
+			assert(price.eq(MARK_PRICE_PRECISION.mul(new BN(expectedPrice))));
+			assert(confidence.eq(new BN(expectedConfidence * 1000)));
+			assert(sufficientDataPoints.eq(new BN(expectedConfidence > 0 ? 1 : 0)));
+		}
+	});
+});
@@ -0,0 +1,151 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PositionDirection,
+	QUOTE_PRECISION,
+	ZERO,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	setFeedPrice,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('oracle volatility', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const usdcAmount = new BN(1000 * 10 ** 6);
+	const calmMarketIndex = new BN(0);
+	const noisyMarketIndex = new BN(1);
+	let calmOracle: PublicKey;
+	let noisyOracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider
+		);
+
+		calmOracle = await mockOracle(1);
+		noisyOracle = await mockOracle(1);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[calmMarketIndex, noisyMarketIndex],
+			[new BN(0)],
+			[
+				{ publicKey: calmOracle, source: OracleSource.PYTH },
+				{ publicKey: noisyOracle, source: OracleSource.PYTH },
+			]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('records higher volatility for a noisier oracle series', async () => {
+		const oracleVolatilitiesBps = [];
+		for (const [marketIndex, oracle, oraclePrices] of [
+			[calmMarketIndex, calmOracle, [1.001, 0.999, 1.001, 1]],
+			[noisyMarketIndex, noisyOracle, [1.05, 0.95, 1.05, 1]],
+		] as [BN, PublicKey, number[]][]) {
+			// initialized here so the fills land well inside the first funding period
+			await clearingHouse.initializeMarket(
+				oracle,
+				ammInitialBaseAssetReserve,
+				ammInitialQuoteAssetReserve,
+				new BN(60)
+			);
+
+			// fills sample the oracle between funding updates
+			for (const [i, oraclePrice] of oraclePrices.slice(0, -1).entries()) {
+				await setFeedPrice(anchor.workspace.Pyth, oraclePrice, oracle);
+				await clearingHouse.openPosition(
+					i % 2 === 0 ? PositionDirection.LONG : PositionDirection.SHORT,
+					QUOTE_PRECISION.mul(new BN(10)),
+					marketIndex
+				);
+			}
+
+			await waitForNextFundingTime(clearingHouse, marketIndex);
+			await setFeedPrice(
+				anchor.workspace.Pyth,
+				oraclePrices[oraclePrices.length - 1],
+				oracle
+			);
+			const txSig = await clearingHouse.updateFundingRate(oracle, marketIndex);
+			await eventSubscriber.awaitTx(txSig);
+			const fundingRateRecord =
+				eventSubscriber.getEventsArray('FundingRateRecord')[0];
+			assert(fundingRateRecord.marketIndex.eq(marketIndex));
+			oracleVolatilitiesBps.push(fundingRateRecord.oracleVolatilityBps);
+
+			// the accumulator restarts with each funding update
+			await clearingHouse.fetchAccounts();
+			assert(
+				clearingHouse
+					.getMarketAccount(marketIndex)
+					.amm.oracleSquaredReturnSum.eq(ZERO)
+			);
+		}
+
+		const [calmVolatilityBps, noisyVolatilityBps] = oracleVolatilitiesBps;
+		// returns of up to about 20 bps and 1000 bps
+		assert(calmVolatilityBps.gt(ZERO));
+		assert(calmVolatilityBps.lt(new BN(100)));
+		assert(noisyVolatilityBps.gt(new BN(1000)));
+	});
+});
@@ -10,6 +10,7 @@ import {
 	mockUSDCMint,
 	setFeedPrice,
 	initializeQuoteAssetBank,
+	waitForNextFundingTime,
 } from './testHelpers';
 
 import {
@@ -42,7 +43,7 @@ async function updateFundingRateHelper(
 	prices: Array<number>
 ) {
 	for (let i = 0; i < prices.length; i++) {
-		await new Promise((r) => setTimeout(r, 1000)); // wait 1 second
+		await waitForNextFundingTime(clearingHouse, marketIndex);
 
 		const newprice = prices[i];
 		setFeedPrice(anchor.workspace.Pyth, newprice, priceFeedAddress);
@@ -261,9 +262,9 @@ describe('pyth-oracle', () => {
 		assert.ok(feedDataAfter.exponent === expo);
 	});
 
-	it('oracle/vamm: funding rate calc 0hour periodicity', async () => {
+	it('oracle/vamm: funding rate calc 1min periodicity', async () => {
 		const priceFeedAddress = await mockOracle(40, -10);
-		const periodicity = new BN(0); // 1 HOUR
+		const periodicity = new BN(60); // 1 MINUTE
 		const marketIndex = new BN(0);
 
 		await clearingHouse.initializeMarket(
@@ -282,9 +283,9 @@ describe('pyth-oracle', () => {
 		);
 	});
 
-	it('oracle/vamm: funding rate calc2 0hour periodicity', async () => {
+	it('oracle/vamm: funding rate calc2 1min periodicity', async () => {
 		const priceFeedAddress = await mockOracle(40, -10);
-		const periodicity = new BN(0);
+		const periodicity = new BN(60);
 		const marketIndex = new BN(1);
 
 		await clearingHouse.initializeMarket(
@@ -308,7 +309,7 @@ describe('pyth-oracle', () => {
 		);
 	});
 
-	it('oracle/vamm: asym funding rate calc 0hour periodicity', async () => {
+	it('oracle/vamm: asym funding rate calc 1min periodicity', async () => {
 		const marketIndex = new BN(1);
 
 		// await clearingHouse.moveAmmToPrice(
@@ -0,0 +1,137 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	ZERO,
+	isVariant,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockOracle,
+	mockUSDCMint,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('pyth max confidence', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const marketIndex = new BN(0);
+	// price 1 with a confidence of 100bps and 101bps of it (in 1e-7 units)
+	let atMaxOracle: PublicKey;
+	let beyondMaxOracle: PublicKey;
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+
+		atMaxOracle = await mockOracle(1, -7, 100000);
+		beyondMaxOracle = await mockOracle(1, -7, 101000);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[
+				{ publicKey: atMaxOracle, source: OracleSource.PYTH },
+				{ publicKey: beyondMaxOracle, source: OracleSource.PYTH },
+			]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			atMaxOracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60)
+		);
+		await clearingHouse.updateMarketPythMaxConfidenceBps(
+			marketIndex,
+			new BN(100)
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	// cranks a funding update against oracle, returning whether it was skipped for an invalid oracle
+	const updateFundingRate = async (oracle: PublicKey) => {
+		await clearingHouse.updateMarketOracle(
+			marketIndex,
+			oracle,
+			OracleSource.PYTH
+		);
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		await clearingHouse.fetchAccounts();
+		const fundingUpdateCount =
+			clearingHouse.getMarketAccount(marketIndex).amm.fundingUpdateCount;
+
+		const txSig = await clearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+		await clearingHouse.fetchAccounts();
+		const updated = clearingHouse
+			.getMarketAccount(marketIndex)
+			.amm.fundingUpdateCount.gt(fundingUpdateCount);
+
+		const skippedForInvalidOracle = eventSubscriber
+			.getEventsArray('FundingUpdateSkippedRecord')
+			.some(
+				(record) =>
+					record.txSig === txSig && isVariant(record.reason, 'oracleInvalid')
+			);
+		assert(updated !== skippedForInvalidOracle);
+		return skippedForInvalidOracle;
+	};
+
+	it('accepts a confidence at the max', async () => {
+		assert(!(await updateFundingRate(atMaxOracle)));
+	});
+
+	it('flags a confidence beyond the max, blocking funding', async () => {
+		assert(await updateFundingRate(beyondMaxOracle));
+	});
+
+	it('accepts the same confidence once the max is back to its permissive default', async () => {
+		await clearingHouse.updateMarketPythMaxConfidenceBps(marketIndex, ZERO);
+		assert(!(await updateFundingRate(beyondMaxOracle)));
+	});
+});
@@ -0,0 +1,186 @@
+import * as anchor from '@project-serum/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@project-serum/anchor';
+
+import { PublicKey } from '@solana/web3.js';
+
+import {
+	Admin,
+	BN,
+	EventSubscriber,
+	MARK_PRICE_PRECISION,
+	OracleSource,
+	PEG_PRECISION,
+	isVariant,
+} from '../sdk/src';
+
+import {
+	initializeQuoteAssetBank,
+	mockUSDCMint,
+	waitForNextFundingTime,
+} from './testHelpers';
+
+describe('switchboard outage recovery', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.ClearingHouse as Program;
+
+	const eventSubscriber = new EventSubscriber(connection, chProgram);
+	eventSubscriber.subscribe();
+
+	let clearingHouse: Admin;
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(MARK_PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	const marketIndex = new BN(0);
+	// preloaded from tests/fixtures/switchboardAggregatorLive.json and switchboardAggregatorOutage.json,
+	// both price 12.5 with a 0.01 std deviation, the latter without enough successful oracle results
+	const liveOracle = new PublicKey(
+		'7gECj3S6c9miGqPNhkQnDnYXxecbEFTrf7TfEUVLDq6y'
+	);
+	const outageOracle = new PublicKey(
+		'H9Bc9JfuAWVy6jTw26jkrVGnzTgZAR5DT8zcgrCmRF9B'
+	);
+	const oraclePrice = MARK_PRICE_PRECISION.mul(new BN(125)).div(new BN(10));
+
+	before(async () => {
+		const usdcMint = await mockUSDCMint(provider);
+
+		clearingHouse = Admin.from(
+			connection,
+			provider.wallet,
+			chProgram.programId,
+			{
+				commitment: 'confirmed',
+			},
+			0,
+			[marketIndex],
+			[new BN(0)],
+			[]
+		);
+		await clearingHouse.initialize(usdcMint.publicKey, true);
+		await clearingHouse.subscribe();
+
+		await initializeQuoteAssetBank(clearingHouse, usdcMint.publicKey);
+
+		await clearingHouse.initializeMarket(
+			liveOracle,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			new BN(60),
+			PEG_PRECISION.mul(new BN(125)).div(new BN(10)),
+			OracleSource.SWITCHBOARD
+		);
+	});
+
+	after(async () => {
+		await clearingHouse.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	const updateFundingRate = async (oracle: PublicKey) => {
+		await waitForNextFundingTime(clearingHouse, marketIndex);
+		const txSig = await clearingHouse.updateFundingRate(oracle, marketIndex);
+		await eventSubscriber.awaitTx(txSig);
+		await clearingHouse.fetchAccounts();
+	};
+
+	const oracleOutageRecoveryCount = () =>
+		clearingHouse.getMarketAccount(marketIndex).amm.oracleOutageRecoveryCount;
+
+	// the confidence the market prices with, after any outage recovery floor
+	const marketOracleConfidence = async () => {
+		const [_price, confidence] = await clearingHouse.view(
+			{
+				oraclePriceWithConversion: {
+					marketIndex,
+					oraclePriceMultiplier: new BN(1),
+					oraclePriceDivisor: new BN(1),
+				},
+			},
+			[marketIndex]
+		);
+		return confidence;
+	};
+
+	it('raises the confidence floor after an outage and decays it back', async () => {
+		// the live feed's confidence is its 10bps of price floor, above the 0.01 std deviation
+		const normalConfidence = oraclePrice.div(new BN(1000));
+		assert((await marketOracleConfidence()).eq(normalConfidence));
+
+		// a funding update during the outage restarts the recovery
+		await clearingHouse.updateMarketOracle(
+			marketIndex,
+			outageOracle,
+			OracleSource.SWITCHBOARD
+		);
+		await updateFundingRate(outageOracle);
+		assert(oracleOutageRecoveryCount() === 5);
+
+		// ORACLE_OUTAGE_RECOVERY_UPDATES funding updates after the feed is back, each dropping the floor
+		// by ORACLE_OUTAGE_RECOVERY_CONFIDENCE_STEP_BPS (10bps) of price
+		await clearingHouse.updateMarketOracle(
+			marketIndex,
+			liveOracle,
+			OracleSource.SWITCHBOARD
+		);
+		for (const expectedRecoveryCount of [4, 3, 2, 1, 0]) {
+			await updateFundingRate(liveOracle);
+			assert(oracleOutageRecoveryCount() === expectedRecoveryCount);
+
+			const floorConfidence = oraclePrice
+				.mul(new BN(10 * expectedRecoveryCount))
+				.div(new BN(10000));
+			assert(
+				(await marketOracleConfidence()).eq(
+					BN.max(floorConfidence, normalConfidence)
+				)
+			);
+		}
+	});
+
+	it('blocks funding on a too uncertain round unless the market allows it', async () => {
+		// preloaded from tests/fixtures/switchboardAggregatorWide.json, price 12.5 with a 6.25 std deviation
+		const wideOracle = new PublicKey(
+			'Aa7YBnFZhmsEyj5jqzPyCMLdzE3kz7unq5F8pueetovF'
+		);
+		await clearingHouse.updateMarketOracle(
+			marketIndex,
+			wideOracle,
+			OracleSource.SWITCHBOARD
+		);
+
+		// 5000bps of price, past DEFAULT_SWITCHBOARD_MAX_CONFIDENCE_BPS (1000)
+		await updateFundingRate(wideOracle);
+		const skippedRecord = eventSubscriber.getEventsArray(
+			'FundingUpdateSkippedRecord'
+		)[0];
+		assert(skippedRecord.marketIndex.eq(marketIndex));
+		assert(isVariant(skippedRecord.reason, 'oracleInvalid'));
+		const fundingUpdateCount =
+			clearingHouse.getMarketAccount(marketIndex).amm.fundingUpdateCount;
+
+		await clearingHouse.updateMarketSwitchboardMaxConfidenceBps(
+			marketIndex,
+			new BN(10000)
+		);
+		await updateFundingRate(wideOracle);
+		assert(
+			clearingHouse
+				.getMarketAccount(marketIndex)
+				.amm.fundingUpdateCount.eq(fundingUpdateCount.add(new BN(1)))
+		);
+	});
+});
@@ -23,9 +23,12 @@ import {
 	BANK_RATE_PRECISION,
 	BANK_WEIGHT_PRECISION,
 	MARK_PRICE_PRECISION,
+	FUNDING_PAYMENT_PRECISION,
+	AMM_TO_QUOTE_PRECISION_RATIO,
 	ClearingHouse,
 	ClearingHouseUser,
 	OracleSource,
+	calculateNextFundingTime,
 } from '../sdk/src';
 
 export async function mockOracle(
@@ -444,7 +447,8 @@ export const createPriceFeed = async ({
 	confidence?: number;
 	expo?: number;
 }): Promise<PublicKey> => {
-	const conf = new BN(confidence) || new BN((initPrice / 10) * 10 ** -expo);
+	// feeds always publish a confidence, the program treats a zero one as a broken feed
+	const conf = new BN(confidence === undefined ? 1 : confidence);
 	const collateralTokenFeed = new anchor.web3.Account();
 	await oracleProgram.rpc.initialize(
 		new BN(initPrice * 10 ** -expo),
@@ -756,6 +760,49 @@ export function sleep(ms) {
 	return new Promise((resolve) => setTimeout(resolve, ms));
 }
 
+/**
+ * Polls the cluster clock until updateFundingRate will apply the next funding update for the market,
+ * failing rather than hanging if it hasn't arrived within timeoutMs
+ */
+export async function waitForNextFundingTime(
+	clearingHouse: ClearingHouse,
+	marketIndex: BN | number,
+	timeoutMs = 3 * 60 * 1000
+): Promise<void> {
+	await clearingHouse.fetchAccounts();
+	const nextFundingTime = calculateNextFundingTime(
+		clearingHouse.getMarketAccount(marketIndex)
+	);
+
+	const deadline = Date.now() + timeoutMs;
+	while (Date.now() < deadline) {
+		const slot = await clearingHouse.connection.getSlot();
+		const now = await clearingHouse.connection.getBlockTime(slot);
+		if (now !== null && nextFundingTime.lten(now)) {
+			return;
+		}
+		await sleep(1000);
+	}
+
+	throw new Error(
+		`timed out waiting for funding time ${nextFundingTime.toString()}`
+	);
+}
+
+// mirrors _calculate_funding_payment followed by the AMM_TO_QUOTE_PRECISION_RATIO truncation
+export function expectedFundingPayment(
+	fundingRateDelta: BN,
+	baseAssetAmount: BN
+): BN {
+	const magnitude = fundingRateDelta
+		.abs()
+		.mul(baseAssetAmount.abs())
+		.div(MARK_PRICE_PRECISION)
+		.div(FUNDING_PAYMENT_PRECISION);
+	const sign = fundingRateDelta.isNeg() === baseAssetAmount.isNeg() ? -1 : 1;
+	return magnitude.mul(new BN(sign)).div(AMM_TO_QUOTE_PRECISION_RATIO);
+}
+
 export async function getTokenAmountAsBN(
 	connection: Connection,
 	tokenAccount: PublicKey
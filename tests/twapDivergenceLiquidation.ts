@@ -15,6 +15,7 @@ import {
 	setFeedPrice,
 	setFeedTwap,
 	initializeQuoteAssetBank,
+	waitForNextFundingTime,
 } from './testHelpers';
 
 describe('twap divergence liquidation', () => {
@@ -69,7 +70,7 @@ describe('twap divergence liquidation', () => {
 		for (let i = 0; i < maxPositions; i++) {
 			// make invalid
 			const oracle = await mockOracle(1, -7, 2147483647);
-			const periodicity = new BN(0);
+			const periodicity = new BN(60);
 
 			await clearingHouse.initializeMarket(
 				oracle,
@@ -107,6 +108,8 @@ describe('twap divergence liquidation', () => {
 
 	it('liquidate', async () => {
 		for (let i = 0; i < maxPositions; i++) {
+			// wait before setting the feed so the oracle isn't stale for the funding update
+			await waitForNextFundingTime(clearingHouse, i);
 			const oracle = clearingHouse.getMarketAccount(i).amm.oracle;
 			await setFeedPrice(anchor.workspace.Pyth, 0.5, oracle);
 			await setFeedTwap(anchor.workspace.Pyth, 100, oracle);
@@ -75,7 +75,7 @@ describe('whale liquidation', () => {
 
 		for (let i = 0; i < maxPositions; i++) {
 			const oracle = await mockOracle(1);
-			const periodicity = new BN(0);
+			const periodicity = new BN(60);
 
 			await clearingHouse.initializeMarket(
 				oracle,